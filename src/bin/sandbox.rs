@@ -1,61 +1,179 @@
-use image::{ImageBuffer, RgbaImage};
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
-use std::{path::Path, time::Instant};
+use std::{path::Path, sync::atomic::AtomicBool};
 
 use raytracing_weekend::*;
 
+const CONFIG_FILE: &str = "config.txt";
+
 fn main() {
-    // config
-    let config = Config::load(Path::new("config.txt"));
+    let args: Vec<String> = std::env::args().collect();
+    if let Err(e) = run(&Config::load(Path::new(CONFIG_FILE)), &args) {
+        eprintln!("{}", e);
+        if !args.iter().any(|a| a == "--watch") {
+            std::process::exit(1)
+        }
+    }
+
+    // `--watch` renders again each time the scene file, or the config of an
+    // inline scene, is saved
+    #[cfg(feature = "notify")]
+    if args.iter().any(|a| a == "--watch") {
+        let watched = arg(&args, "--scene-file").map_or(CONFIG_FILE, |f| f.as_str());
+        println!("Watching {}", watched);
+        let watching = watch::watch_file(Path::new(watched), |_| {
+            if let Err(e) = run(&Config::load(Path::new(CONFIG_FILE)), &args) {
+                eprintln!("{}", e);
+            }
+            true
+        });
+        if let Err(e) = watching {
+            eprintln!("{}: {}", watched, e);
+        }
+    }
+
+    #[cfg(not(feature = "notify"))]
+    if args.iter().any(|a| a == "--watch") {
+        eprintln!("--watch needs the notify feature");
+    }
+}
+
+fn arg<'a>(args: &'a [String], name: &str) -> Option<&'a String> {
+    args.iter()
+        .position(|a| a == name)
+        .and_then(|i| args.get(i + 1))
+}
 
-    // image buffer
-    let mut img: RgbaImage = ImageBuffer::new(config.width, config.height);
+fn run(config: &Config, args: &[String]) -> Result<(), String> {
+    let mut timings = Timings::new();
+    let arg = |name: &str| arg(args, name);
+    for warning in config.validate() {
+        eprintln!("warning: {}", warning);
+    }
 
-    // scene
-    let (world, camera, background) = scenes::cornell_box(config.aspect_ratio);
-    let tree = BVTree::new(world);
+    // scene, `--scene-file model.obj` renders a mesh instead of the cornell box
+    let scene_file = arg("--scene-file");
+    let (mut world, camera, background) = timings.time("scene build", || match scene_file {
+        Some(file) => scenes::from_obj(Path::new(file), config.aspect_ratio)
+            .map_err(|e| format!("{}: {}", file, e)),
+        None => match &config.scene_inline {
+            Some(src) => scenes::from_ron(src, config.aspect_ratio)
+                .map_err(|e| format!("scene_inline: {}", e)),
+            None => Ok(scenes::cornell_box(config.aspect_ratio)),
+        },
+    })?;
+    frustum_cull(&mut world, &camera, (config.width, config.height));
+    let tree = timings.time("bvh build", || {
+        Accelerator::build(world, config.bvh_threshold as usize)
+    });
 
     // meta data
-    println!("Scene rendered : cornell_box");
-    println!("Objects rendered : {}", tree.objects_count);
+    println!(
+        "Scene rendered : {}",
+        match (scene_file, &config.scene_inline) {
+            (Some(file), _) => file.as_str(),
+            (None, Some(_)) => "scene_inline",
+            (None, None) => "cornell_box",
+        }
+    );
+    println!("Objects rendered : {}", tree.objects_count());
     println!("{}", config);
 
-    // render stage
-    let mut buffer: Vec<Color> = Vec::with_capacity((config.width * config.height) as usize);
-
-    let clock = Instant::now();
-    for y in 0..config.height {
-        let mut line: Vec<Color> = (0..config.width)
-            .into_par_iter()
-            .map(|x| {
-                let mut color = Vec3::ZERO;
-                for _ in 0..config.samples {
-                    color += ray_color(
-                        &get_ray(x, y, &camera, &config),
-                        &tree,
-                        config.depth,
-                        background.to_vec3(),
-                    );
-                }
-
-                Color::from_vec(color, config.samples as u64)
-            })
-            .collect();
-
-        buffer.append(&mut line);
+    // `--debug-pixel x,y` logs the samples of a pixel to stderr
+    if let Some(pixel) = arg("--debug-pixel") {
+        let coords: Vec<u32> = pixel.split(',').filter_map(|c| c.parse().ok()).collect();
+        if let [x, y] = coords[..] {
+            render::debug_pixel(&tree, &camera, background, config, (x, y));
+        } else {
+            eprintln!("--debug-pixel expects x,y");
+        }
     }
 
-    let dt = clock.elapsed().as_secs_f32();
-    println!("Render time : {}s", dt);
+    // `--sun x,y,z` lights the scene by a white sun in the direction x,y,z
+    let path_tracer = render::path_tracer(config);
+    let sun = arg("--sun").and_then(|dir| {
+        let coords: Vec<f64> = dir.split(',').filter_map(|c| c.parse().ok()).collect();
+        match coords[..] {
+            [x, y, z] => Some(SunPathTracer {
+                sun: DirectionalLight::new(vec3(x, y, z), Vec3::ONE, 0.005),
+            }),
+            _ => {
+                eprintln!("--sun expects x,y,z");
+                None
+            }
+        }
+    });
+    let integrator: &dyn Integrator = match &sun {
+        Some(sun) => sun,
+        None => &path_tracer,
+    };
 
-    // copy buffer to image
-    for (x, y, pixel) in img.enumerate_pixels_mut() {
-        *pixel = image::Rgba(buffer[(y * config.width + x) as usize].into());
+    // render stage, `--tiled 7` renders by tiles in `tile_order` with the seed 7
+    // and `--sample-map map.png` converges each channel on its own, saving the
+    // sample counts
+    let tiled = arg("--tiled").and_then(|seed| seed.parse().ok());
+    let sample_map = arg("--sample-map");
+    let (radiance, passes, counts) = timings.time("render", || match (tiled, sample_map) {
+        (Some(seed), map) => {
+            if map.is_some() {
+                eprintln!("--sample-map is ignored by --tiled");
+            }
+            let cancel = AtomicBool::new(false);
+            let (radiance, _) = render::render_tiled_with(
+                &tree, &camera, background, config, seed, &cancel, integrator,
+            );
+            (radiance, 1, None)
+        }
+        (None, Some(_)) => {
+            let (radiance, counts) =
+                render::render_per_channel_with(&tree, &camera, background, config, integrator);
+            let max = counts.iter().flatten().copied().max().unwrap_or(0);
+            (radiance, max / config.samples.max(1), Some(counts))
+        }
+        (None, None) => {
+            let (radiance, passes) =
+                render::render_progressive_hdr_with(&tree, &camera, background, config, integrator);
+            (radiance, passes, None)
+        }
+    });
+    println!(
+        "Render time : {}s ({} passes)",
+        timings.get("render").unwrap().as_secs_f32(),
+        passes
+    );
+    if let (Some(path), Some(counts)) = (sample_map, counts) {
+        if let Err(e) = render::sample_count_image(&counts, config).save(path) {
+            eprintln!("{}", e);
+        }
     }
 
     // save img
-    match image::imageops::flip_vertical(&img).save("out.png") {
-        Ok(()) => (),
-        Err(e) => eprintln!("{}", e),
+    let output = Path::new(&config.output);
+    if output.extension() == Some("exr".as_ref()) {
+        let saved = timings.time("save", || {
+            output::write_exr(output, config.width, config.height, &radiance)
+        });
+        if let Err(e) = saved {
+            eprintln!("{}", e);
+        }
+    } else if config.bit_depth == 16 {
+        let img = timings.time("tone map", || render::to_image_16(&radiance, config));
+        if let Err(e) = timings.time("save", || img.save(output)) {
+            eprintln!("{}", e);
+        }
+    } else {
+        let mut img = timings.time("tone map", || {
+            render::to_image(&render::tonemap(&radiance, config), config)
+        });
+        // `--grid 16` draws a framing grid over the image
+        if let Some(spacing) = arg("--grid").and_then(|s| s.parse().ok()) {
+            postprocess::grid_overlay(&mut img, spacing, Color::WHITE);
+        }
+        if let Err(e) = timings.time("save", || img.save(output)) {
+            eprintln!("{}", e);
+        }
     }
+
+    #[cfg(feature = "profile")]
+    println!("{}", timings);
+
+    Ok(())
 }
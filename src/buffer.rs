@@ -0,0 +1,106 @@
+use super::{Color, Vec3};
+use rayon::prelude::*;
+
+/// Image sized buffer of colors, stored row by row.
+#[derive(Debug, Clone)]
+pub struct PixelBuffer {
+    pub data: Vec<Color>,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl PixelBuffer {
+    pub fn new(data: Vec<Color>, width: u32, height: u32) -> Self {
+        assert_eq!(data.len(), (width * height) as usize);
+        Self {
+            data,
+            width,
+            height,
+        }
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Color {
+        self.data[(y * self.width + x) as usize]
+    }
+
+    pub fn par_pixels(&self) -> impl IndexedParallelIterator<Item = (u32, u32, &Color)> {
+        let width = self.width;
+        self.data
+            .par_iter()
+            .enumerate()
+            .map(move |(i, c)| (i as u32 % width, i as u32 / width, c))
+    }
+
+    pub fn par_pixels_mut(
+        &mut self,
+    ) -> impl IndexedParallelIterator<Item = (u32, u32, &mut Color)> {
+        let width = self.width;
+        self.data
+            .par_iter_mut()
+            .enumerate()
+            .map(move |(i, c)| (i as u32 % width, i as u32 / width, c))
+    }
+}
+
+/// Radiance sums and sample counts of each pixel, kept across renders so
+/// that samples can be added to an image over time.
+#[derive(Debug, Clone)]
+pub struct AccumBuffer {
+    pub sums: Vec<Vec3>,
+    pub counts: Vec<u32>,
+    pub width: u32,
+    pub height: u32,
+    pub seed: u64,
+}
+
+impl AccumBuffer {
+    pub fn new(width: u32, height: u32) -> Self {
+        let len = (width * height) as usize;
+        Self {
+            sums: vec![Vec3::ZERO; len],
+            counts: vec![0; len],
+            width,
+            height,
+            seed: 0,
+        }
+    }
+
+    /// Mean radiance of each pixel, zero where no sample was taken.
+    pub fn radiance(&self) -> Vec<Vec3> {
+        self.sums
+            .iter()
+            .zip(self.counts.iter())
+            .map(|(sum, n)| {
+                if *n == 0 {
+                    Vec3::ZERO
+                } else {
+                    *sum / *n as f64
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use rayon::prelude::*;
+
+    #[test]
+    fn test_par_pixels_mut() {
+        let mut buffer = PixelBuffer::new(vec![Color::new(0, 0, 0, 0); 12 * 7], 12, 7);
+        buffer.par_pixels_mut().for_each(|(x, y, c)| {
+            c.r += 1;
+            c.g = x as u8;
+            c.b = y as u8;
+        });
+
+        for y in 0..7 {
+            for x in 0..12 {
+                let c = buffer.get(x, y);
+                assert_eq!((c.r, c.g, c.b), (1, x as u8, y as u8));
+            }
+        }
+        assert_eq!(buffer.par_pixels().count(), 12 * 7);
+    }
+}
@@ -1,4 +1,4 @@
-use super::{radians, ray, vec3, Point3, Ray, Vec3};
+use super::{radians, ray, vec3, Point3, Ray, RayKind, Vec3, AABB};
 use crate::random_range;
 
 #[derive(Debug, Clone, Copy)]
@@ -9,13 +9,15 @@ pub struct Camera {
     vertical: Vec3,
     u: Vec3,
     v: Vec3,
-    w: Vec3,
     lens_radius: f64,
+    focus_dist: f64,
     t1: f64, // open / close shutter
     t2: f64,
+    readout: f64, // delay between the exposure of the top and bottom rows
 }
 
 impl Camera {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         fov: f64,
         eye: Point3,
@@ -25,12 +27,50 @@ impl Camera {
         focus_dist: f64,
         t1: f64,
         t2: f64,
+    ) -> Self {
+        let view = (eye, target, vec3(0.0, 1.0, 0.0));
+        Self::oriented(fov, view, aspect_ratio, aperture, focus_dist, t1, t2)
+    }
+
+    /// Camera of a photographic lens of focal length `focal_length_mm` on a
+    /// sensor `sensor_width_mm` wide, stopped down at `f_stop`, scene units
+    /// being meters. `vup` is the up direction of the image.
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_physical(
+        focal_length_mm: f64,
+        sensor_width_mm: f64,
+        f_stop: f64,
+        focus_dist: f64,
+        lookfrom: Point3,
+        lookat: Point3,
+        vup: Vec3,
+        aspect_ratio: f64,
+    ) -> Self {
+        let sensor_height_mm = sensor_width_mm / aspect_ratio;
+        let fov = 2.0
+            * (sensor_height_mm / (2.0 * focal_length_mm))
+                .atan()
+                .to_degrees();
+        let aperture = focal_length_mm / f_stop / 1000.0;
+        let view = (lookfrom, lookat, vup);
+        Self::oriented(fov, view, aspect_ratio, aperture, focus_dist, 0.0, 0.0)
+    }
+
+    // `new` with an up vector, `view` being the eye, target and up vector
+    fn oriented(
+        fov: f64,
+        (eye, target, vup): (Point3, Point3, Vec3),
+        aspect_ratio: f64,
+        aperture: f64,
+        focus_dist: f64,
+        t1: f64,
+        t2: f64,
     ) -> Self {
         let h = (radians(fov) * 0.5).tan();
         let viewport = (2.0 * h * aspect_ratio, 2.0 * h);
 
         let w = (eye - target).normalize();
-        let u = vec3(0.0, 1.0, 0.0).cross(w).normalize();
+        let u = vup.cross(w).normalize();
         let v = w.cross(u);
 
         let horizontal = focus_dist * viewport.0 * u;
@@ -44,21 +84,375 @@ impl Camera {
             lower_left_corner,
             u,
             v,
-            w,
             lens_radius: aperture / 2.0,
+            focus_dist,
             t1,
             t2,
+            readout: 0.0,
         }
     }
 
+    /// Moves the plane in focus to `focus_dist` from the eye, keeping the
+    /// field of view. `CameraPath` interpolates it between keyframes for
+    /// focus pulls.
+    pub fn set_focus_dist(&mut self, focus_dist: f64) {
+        let k = focus_dist / self.focus_dist;
+        let center = self.lower_left_corner + self.horizontal / 2.0 + self.vertical / 2.0;
+
+        self.horizontal = k * self.horizontal;
+        self.vertical = k * self.vertical;
+        self.lower_left_corner =
+            self.eye + k * (center - self.eye) - self.horizontal / 2.0 - self.vertical / 2.0;
+        self.focus_dist = focus_dist;
+    }
+
+    pub fn focus_dist(&self) -> f64 {
+        self.focus_dist
+    }
+
+    /// Whether part of `bbox` may be hit by a ray of `get_ray`, through the
+    /// viewport widened by `pixel`, the size of a pixel in viewport units, on
+    /// each side, and any point of the lens. Boxes behind the camera are never
+    /// seen.
+    pub fn sees(&self, bbox: &AABB, pixel: (f64, f64)) -> bool {
+        let (ds, dt) = pixel;
+        let corners = [
+            self.focus_point(-ds, -dt),
+            self.focus_point(1.0 + ds, -dt),
+            self.focus_point(1.0 + ds, 1.0 + dt),
+            self.focus_point(-ds, 1.0 + dt),
+        ];
+        let center = self.focus_point(0.5, 0.5);
+
+        // the rays along a side leave from the lens edge on its side until
+        // the focus plane, from the lens edge on the other side beyond it
+        let inside = |a: Point3, b: Point3, lens: Point3| {
+            let mut n = (a - lens).cross(b - lens);
+            if n.dot(center - lens) < 0.0 {
+                n = -n;
+            }
+            move |c: &Point3| n.dot(*c - lens) >= 0.0
+        };
+        (0..4).all(|i| {
+            let (a, b) = (corners[i], corners[(i + 1) % 4]);
+            let toward = (a + b) / 2.0 - center;
+            let m = self.u * toward.dot(self.u) + self.v * toward.dot(self.v);
+            let offset = self.lens_radius * m.try_normalize().unwrap_or(Vec3::ZERO);
+            let (near, far) = (
+                inside(a, b, self.eye + offset),
+                inside(a, b, self.eye - offset),
+            );
+            bbox.corners().iter().any(|c| near(c) || far(c))
+        })
+    }
+
+    /// Rolling shutter exposing the rows from top to bottom, the bottom row
+    /// opening `readout` after the top one. Fast objects look skewed, a zero
+    /// readout is a global shutter.
+    pub fn with_rolling_shutter(self, readout: f64) -> Self {
+        Self { readout, ..self }
+    }
+
+    /// Shutter opening and closing times of the row at viewport height `t`.
+    pub fn time_range(&self, t: f64) -> (f64, f64) {
+        let delay = self.readout * (1.0 - t);
+        (self.t1 + delay, self.t2 + delay)
+    }
+
     pub fn get_ray(&self, s: f64, t: f64) -> Ray {
-        let rd = self.lens_radius * Vec3::random_unit_disk();
+        self.get_ray_lens(s, t, Vec3::random_unit_disk())
+    }
+
+    /// Same as `get_ray` through the point `lens` of the unit disk, scaled
+    /// to the lens radius.
+    pub fn get_ray_lens(&self, s: f64, t: f64, lens: Vec3) -> Ray {
+        let rd = self.lens_radius * lens;
         let offset = self.u * rd.x + self.v * rd.y;
+        let (t1, t2) = self.time_range(t);
 
         ray(
             self.eye + offset,
             self.lower_left_corner + s * self.horizontal + t * self.vertical - self.eye - offset,
-            random_range(self.t1, self.t2),
+            random_range(t1, t2),
+        )
+        .with_kind(RayKind::Camera)
+    }
+
+    /// Left and right eyes `ipd` apart across the view direction. Both keep
+    /// the viewport of this camera, so their frustums are skewed toward each
+    /// other and objects on the focus plane have no parallax.
+    pub fn stereo_pair(&self, ipd: f64) -> (Camera, Camera) {
+        let half = self.u * ipd / 2.0;
+        (
+            Self {
+                eye: self.eye - half,
+                ..*self
+            },
+            Self {
+                eye: self.eye + half,
+                ..*self
+            },
         )
     }
+
+    pub fn eye(&self) -> Point3 {
+        self.eye
+    }
+
+    /// Point of the focus plane seen at the viewport coordinates `(s, t)`.
+    pub fn focus_point(&self, s: f64, t: f64) -> Point3 {
+        self.lower_left_corner + s * self.horizontal + t * self.vertical
+    }
+
+    /// Viewport coordinates `(s, t)` where `point` is seen, `None` if it is behind the camera.
+    pub fn project(&self, point: &Point3) -> Option<(f64, f64)> {
+        let n = self.horizontal.cross(self.vertical);
+        let dir = *point - self.eye;
+        let k = (self.lower_left_corner - self.eye).dot(n) / dir.dot(n);
+        if !k.is_finite() || k <= 0.0 {
+            return None;
+        }
+
+        let q = self.eye + k * dir - self.lower_left_corner;
+        Some((
+            q.dot(self.horizontal) / self.horizontal.squared_norm(),
+            q.dot(self.vertical) / self.vertical.squared_norm(),
+        ))
+    }
+}
+
+impl Default for Camera {
+    /// Pinhole camera at the origin looking down -z.
+    fn default() -> Self {
+        Self::new(
+            90.0,
+            Vec3::ZERO,
+            vec3(0.0, 0.0, -1.0),
+            16.0 / 9.0,
+            0.0,
+            1.0,
+            0.0,
+            0.0,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CameraKeyframe {
+    pub eye: Point3,
+    pub target: Point3,
+    pub fov: f64,
+    pub aperture: f64,
+    pub focus_dist: f64,
+}
+
+/// Smooth camera move through keyframes sorted by time, positions follow a
+/// Catmull-Rom spline and view directions are interpolated spherically.
+#[derive(Debug, Clone)]
+pub struct CameraPath {
+    keys: Vec<(f64, CameraKeyframe)>,
+    pub aspect_ratio: f64,
+}
+
+impl CameraPath {
+    pub fn new(keys: Vec<(f64, CameraKeyframe)>, aspect_ratio: f64) -> Self {
+        assert!(!keys.is_empty(), "no keyframe in camera path");
+        Self { keys, aspect_ratio }
+    }
+
+    /// Keyframes of the path with their times.
+    pub fn keys(&self) -> &[(f64, CameraKeyframe)] {
+        &self.keys
+    }
+
+    pub fn sample(&self, t: f64) -> Camera {
+        let last = self.keys.len() - 1;
+        let i = self.keys[..last]
+            .iter()
+            .rposition(|(time, _)| *time <= t)
+            .unwrap_or(0);
+        let j = (i + 1).min(last);
+
+        let (t1, k1) = self.keys[i];
+        let (t2, k2) = self.keys[j];
+        let k0 = self.keys[i.saturating_sub(1)].1;
+        let k3 = self.keys[(j + 1).min(last)].1;
+        let s = if t2 > t1 {
+            ((t - t1) / (t2 - t1)).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+
+        let eye = catmull_rom(k0.eye, k1.eye, k2.eye, k3.eye, s);
+        let (d1, d2) = (k1.target - k1.eye, k2.target - k2.eye);
+        let dir = d1.normalize().slerp(d2.normalize(), s);
+        let dist = d1.norm() + (d2.norm() - d1.norm()) * s;
+        let lerp = |a: f64, b: f64| a + (b - a) * s;
+
+        Camera::new(
+            lerp(k1.fov, k2.fov),
+            eye,
+            eye + dist * dir,
+            self.aspect_ratio,
+            lerp(k1.aperture, k2.aperture),
+            lerp(k1.focus_dist, k2.focus_dist),
+            0.0,
+            0.0,
+        )
+    }
+}
+
+fn catmull_rom(p0: Point3, p1: Point3, p2: Point3, p3: Point3, t: f64) -> Point3 {
+    0.5 * (2.0 * p1
+        + (p2 - p0) * t
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t * t
+        + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t * t * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::sync::Arc;
+
+    fn key(eye: Point3, target: Point3) -> CameraKeyframe {
+        CameraKeyframe {
+            eye,
+            target,
+            fov: 40.0,
+            aperture: 0.0,
+            focus_dist: 10.0,
+        }
+    }
+
+    fn path() -> CameraPath {
+        CameraPath::new(
+            vec![
+                (0.0, key(vec3(0.0, 0.0, -10.0), Vec3::ZERO)),
+                (1.0, key(vec3(10.0, 0.0, 0.0), Vec3::ZERO)),
+                (2.0, key(vec3(0.0, 5.0, 10.0), vec3(0.0, 1.0, 0.0))),
+                (3.0, key(vec3(-10.0, 0.0, 0.0), Vec3::ZERO)),
+            ],
+            1.0,
+        )
+    }
+
+    #[test]
+    fn test_sample_at_keyframe() {
+        let path = path();
+        for (t, k) in path.keys() {
+            let camera = path.sample(*t);
+            let expected = Camera::new(
+                k.fov,
+                k.eye,
+                k.target,
+                1.0,
+                k.aperture,
+                k.focus_dist,
+                0.0,
+                0.0,
+            );
+
+            assert!((camera.eye() - k.eye).norm() < 1e-9);
+            let (s, t) = camera.project(&k.target).unwrap();
+            let (es, et) = expected.project(&k.target).unwrap();
+            assert!((s - es).abs() < 1e-9 && (t - et).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_path_is_smooth() {
+        let path = path();
+        let h = 1e-4;
+        for t in [1.0, 2.0].iter() {
+            let before = (path.sample(*t).eye() - path.sample(t - h).eye()) / h;
+            let after = (path.sample(t + h).eye() - path.sample(*t).eye()) / h;
+            assert!((before - after).norm() < 1e-2 * before.norm());
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "no keyframe in camera path")]
+    fn test_empty_path() {
+        CameraPath::new(Vec::new(), 1.0);
+    }
+
+    #[test]
+    fn test_rolling_shutter() {
+        let camera = Camera::new(
+            40.0,
+            vec3(0.0, 0.0, -5.0),
+            Vec3::ZERO,
+            1.0,
+            0.0,
+            5.0,
+            0.0,
+            0.1,
+        );
+        assert_eq!(camera.time_range(0.0), camera.time_range(1.0));
+
+        let rolling = camera.with_rolling_shutter(0.5);
+        let (top, bottom) = (rolling.time_range(1.0), rolling.time_range(0.0));
+        assert_eq!(top, (0.0, 0.1));
+        assert_eq!(bottom, (0.5, 0.6));
+        for _ in 0..16 {
+            let time = rolling.get_ray(0.5, 0.0).time;
+            assert!(time >= bottom.0 && time <= bottom.1);
+        }
+    }
+
+    #[test]
+    fn test_physical_camera_fov() {
+        let camera = Camera::from_physical(
+            50.0,
+            36.0,
+            2.0,
+            10.0,
+            Vec3::ZERO,
+            vec3(0.0, 0.0, -1.0),
+            vec3(0.0, 1.0, 0.0),
+            1.5,
+        );
+
+        // 2 * atan(36 / (2 * 50))
+        let fov = 2.0 * (camera.horizontal.norm() / 20.0).atan().to_degrees();
+        assert!((fov - 39.6).abs() < 0.05);
+        assert!((camera.lens_radius - 0.0125).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_focus_pull() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::default());
+        let wall = |z| XYRect::new((-10.0, 10.0), (-10.0, 10.0), z, mat.clone());
+        let (near, far) = (wall(3.0), wall(10.0));
+
+        // spread of the points of a wall seen through the center of the image
+        let blur = |camera: &Camera, wall: &XYRect| {
+            let points: Vec<Point3> = (0..64)
+                .map(|_| {
+                    let r = camera.get_ray(0.5, 0.5);
+                    wall.hit(&r, 0.001, f64::INFINITY).unwrap().point
+                })
+                .collect();
+            let center = points.iter().fold(Vec3::ZERO, |acc, p| acc + *p) / 64.0;
+            points
+                .iter()
+                .map(|p| (*p - center).norm())
+                .fold(0.0, f64::max)
+        };
+
+        let target = vec3(0.0, 0.0, 1.0);
+        let mut camera = Camera::new(40.0, Vec3::ZERO, target, 1.0, 0.5, 3.0, 0.0, 0.0);
+        assert!(blur(&camera, &near) < 1e-9);
+        assert!(blur(&camera, &far) > 0.1);
+
+        camera.set_focus_dist(10.0);
+        assert_eq!(camera.focus_dist(), 10.0);
+        assert!(blur(&camera, &near) > 0.1);
+        assert!(blur(&camera, &far) < 1e-9);
+
+        // same framing as a camera built with that focus distance
+        let expected = Camera::new(40.0, Vec3::ZERO, target, 1.0, 0.5, 10.0, 0.0, 0.0);
+        assert!((camera.lower_left_corner - expected.lower_left_corner).norm() < 1e-9);
+        assert!((camera.horizontal - expected.horizontal).norm() < 1e-9);
+    }
 }
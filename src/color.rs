@@ -1,6 +1,159 @@
 use super::{Vec3, random};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
-#[derive(Debug, Clone, Copy)]
+/// How out of range linear colors are brought back to `[0, 1]`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClampMode {
+    /// clamp each channel independently, bright colors drift toward yellow / white
+    Clip,
+    /// scale the channels so the brightest is 1.0 and mix the excess with white
+    Desaturate,
+}
+
+impl ClampMode {
+    pub fn apply(self, v: Vec3) -> Vec3 {
+        match self {
+            Self::Clip => v.clamp(0.0, 1.0),
+            Self::Desaturate => {
+                let v = v.clamp(0.0, f64::INFINITY);
+                let max = v.x.max(v.y).max(v.z);
+                if max <= 1.0 {
+                    return v;
+                }
+
+                let excess = 1.0 - 1.0 / max;
+                (v / max) * (1.0 - excess) + Vec3::ONE * excess
+            }
+        }
+    }
+}
+
+impl FromStr for ClampMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clip" => Ok(Self::Clip),
+            "desaturate" => Ok(Self::Desaturate),
+            _ => Err(format!("unknown clamp mode: {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for ClampMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Clip => write!(f, "clip"),
+            Self::Desaturate => write!(f, "desaturate"),
+        }
+    }
+}
+
+/// Primaries of linear RGB colors. Renders are encoded in the `color_space`
+/// of their config, from the `working_space` in which the colors of the
+/// materials are given and the light transport happens, both sRGB (which
+/// shares the Rec.709 primaries) by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorSpace {
+    Srgb,
+    /// wide gamut primaries of UHD video
+    Rec2020,
+    /// AP1 primaries of the ACES working space of film pipelines, white point D60
+    Acescg,
+}
+
+// linear Rec.709 to Rec.2020 primaries, ITU-R BT.2087, and back
+#[rustfmt::skip]
+const REC709_TO_REC2020: [[f64; 3]; 3] = [
+    [0.6274, 0.3293, 0.0433],
+    [0.0691, 0.9195, 0.0114],
+    [0.0164, 0.0880, 0.8956],
+];
+#[rustfmt::skip]
+const REC2020_TO_REC709: [[f64; 3]; 3] = [
+    [ 1.6605, -0.5876, -0.0728],
+    [-0.1246,  1.1329, -0.0083],
+    [-0.0182, -0.1006,  1.1187],
+];
+
+// linear Rec.709 to ACEScg, with a Bradford adaptation from D65 to D60, and back
+#[rustfmt::skip]
+const REC709_TO_ACESCG: [[f64; 3]; 3] = [
+    [0.61310, 0.33952, 0.04737],
+    [0.07019, 0.91636, 0.01345],
+    [0.02062, 0.10957, 0.86981],
+];
+#[rustfmt::skip]
+const ACESCG_TO_REC709: [[f64; 3]; 3] = [
+    [ 1.70505, -0.62179, -0.08326],
+    [-0.13026,  1.14080, -0.01055],
+    [-0.02400, -0.12897,  1.15297],
+];
+
+fn transform(m: [[f64; 3]; 3], v: Vec3) -> Vec3 {
+    Vec3::new(
+        m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+        m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+        m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+    )
+}
+
+impl ColorSpace {
+    /// Linear sRGB `v` expressed in the primaries of the color space.
+    pub fn convert(self, v: Vec3) -> Vec3 {
+        match self {
+            Self::Srgb => v,
+            Self::Rec2020 => transform(REC709_TO_REC2020, v),
+            Self::Acescg => transform(REC709_TO_ACESCG, v),
+        }
+    }
+
+    /// Linear `v` of the color space expressed in sRGB primaries.
+    pub fn to_srgb(self, v: Vec3) -> Vec3 {
+        match self {
+            Self::Srgb => v,
+            Self::Rec2020 => transform(REC2020_TO_REC709, v),
+            Self::Acescg => transform(ACESCG_TO_REC709, v),
+        }
+    }
+
+    /// Linear `v` of the color space `from` expressed in this one.
+    pub fn convert_from(self, from: Self, v: Vec3) -> Vec3 {
+        if from == self {
+            return v;
+        }
+
+        self.convert(from.to_srgb(v))
+    }
+}
+
+impl FromStr for ColorSpace {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "srgb" => Ok(Self::Srgb),
+            "rec2020" => Ok(Self::Rec2020),
+            "acescg" => Ok(Self::Acescg),
+            _ => Err(format!("unknown color space: {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for ColorSpace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Srgb => write!(f, "srgb"),
+            Self::Rec2020 => write!(f, "rec2020"),
+            Self::Acescg => write!(f, "acescg"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Color {
     pub r: u8,
     pub g: u8,
@@ -9,11 +162,11 @@ pub struct Color {
 }
 
 impl Color {
-    pub fn new(r: u8, g: u8, b: u8, a: u8) -> Self {
+    pub const fn new(r: u8, g: u8, b: u8, a: u8) -> Self {
         Self { r, g, b, a }
     }
 
-    pub fn from_rgb(r: u8, g: u8, b: u8) -> Self {
+    pub const fn from_rgb(r: u8, g: u8, b: u8) -> Self {
         Self { r, g, b, a: 255 }
     }
 
@@ -26,6 +179,49 @@ impl Color {
         }
     }
 
+    pub fn from_vec_clamped(v: Vec3, samples: u64, mode: ClampMode) -> Self {
+        let v = mode.apply(v / samples as f64);
+        Self::from_vec(v, 1)
+    }
+
+    /// Same as `from_vec_clamped`, with a 4x4 Bayer threshold depending on
+    /// the pixel (x, y) added before quantization to break up banding.
+    pub fn from_vec_dithered(v: Vec3, samples: u64, mode: ClampMode, x: u32, y: u32) -> Self {
+        let threshold = (BAYER_4[(y % 4) as usize][(x % 4) as usize] as f64 + 0.5) / 16.0;
+        let v = mode.apply(v / samples as f64);
+        let quantize = |c: f64| (256.0 * c.powf(0.45) + threshold - 0.5).clamp(0.0, 255.0) as u8;
+        Self {
+            r: quantize(v.x),
+            g: quantize(v.y),
+            b: quantize(v.z),
+            a: 255
+        }
+    }
+
+    /// Linear color encoded by `from_vec`, taken at the center of its quantization step.
+    pub fn to_linear(self) -> Vec3 {
+        let decode = |c: u8| ((c as f64 + 0.5) / 256.0).powf(1.0 / 0.45);
+        Vec3::new(decode(self.r), decode(self.g), decode(self.b))
+    }
+
+    /// Combines two estimates of the same pixel made of `a_samples` and
+    /// `b_samples` samples into the estimate of all the samples. Channels
+    /// clipped by `from_vec` have lost their over range part and only merge
+    /// approximately.
+    pub fn merge(a: Self, a_samples: u64, b: Self, b_samples: u64) -> Self {
+        let total = a_samples + b_samples;
+        if total == 0 {
+            return a;
+        }
+
+        let sum = a.to_linear() * a_samples as f64 + b.to_linear() * b_samples as f64;
+        let alpha = (a.a as u64 * a_samples + b.a as u64 * b_samples) / total;
+        Self {
+            a: alpha as u8,
+            ..Self::from_vec(sum, total)
+        }
+    }
+
     pub fn random() -> Self {
         Self {
             r: (random().powi(2) * 255.0) as u8,
@@ -53,20 +249,20 @@ impl Color {
     }
 }
 
-impl Into<[f64; 4]> for Color {
-    fn into(self) -> [f64; 4] {
+impl From<Color> for [f64; 4] {
+    fn from(color: Color) -> Self {
         [
-            self.r as f64 / 255.0,
-            self.g as f64 / 255.0,
-            self.b as f64 / 255.0,
-            self.a as f64 / 255.0,
+            color.r as f64 / 255.0,
+            color.g as f64 / 255.0,
+            color.b as f64 / 255.0,
+            color.a as f64 / 255.0,
         ]
     }
 }
 
-impl Into<[u8; 4]> for Color {
-    fn into(self) -> [u8; 4] {
-        [self.r, self.g, self.b, self.a]
+impl From<Color> for [u8; 4] {
+    fn from(color: Color) -> Self {
+        [color.r, color.g, color.b, color.a]
     }
 }
 
@@ -76,6 +272,39 @@ impl From<[u8; 4]> for Color {
     }
 }
 
+/// Compares the channels as floats in `[0, 1]`.
+#[cfg(feature = "approx")]
+impl approx::AbsDiffEq for Color {
+    type Epsilon = f64;
+
+    fn default_epsilon() -> f64 {
+        f64::default_epsilon()
+    }
+
+    fn abs_diff_eq(&self, other: &Self, epsilon: f64) -> bool {
+        <[f64; 4]>::from(*self)
+            .iter()
+            .zip(<[f64; 4]>::from(*other).iter())
+            .all(|(a, b)| a.abs_diff_eq(b, epsilon))
+    }
+}
+
+#[cfg(feature = "approx")]
+impl approx::RelativeEq for Color {
+    fn default_max_relative() -> f64 {
+        f64::default_max_relative()
+    }
+
+    fn relative_eq(&self, other: &Self, epsilon: f64, max_relative: f64) -> bool {
+        <[f64; 4]>::from(*self)
+            .iter()
+            .zip(<[f64; 4]>::from(*other).iter())
+            .all(|(a, b)| a.relative_eq(b, epsilon, max_relative))
+    }
+}
+
+const BAYER_4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
 #[rustfmt::skip]
 impl Color {
     pub const BLACK: Self       = Self { r: 0,   g: 0,   b: 0,   a: 0   };
@@ -91,4 +320,96 @@ impl Color {
     pub const ORANGE: Self      = Self { r: 220, g: 105, b: 10,  a: 255 };
     pub const PINK: Self        = Self { r: 200, g: 65,  b: 150, a: 255 };
     pub const PURPLE: Self      = Self { r: 200, g: 10,  b: 210, a: 255 };
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn hue(v: Vec3) -> f64 {
+        let max = v.x.max(v.y).max(v.z);
+        let min = v.x.min(v.y).min(v.z);
+        assert!(v.x == max);
+        60.0 * (v.y - v.z) / (max - min)
+    }
+
+    #[test]
+    fn test_desaturate_keeps_hue() {
+        let orange = vec3(2.0, 1.0, 0.2);
+
+        let desaturated = ClampMode::Desaturate.apply(orange);
+        assert!(desaturated.x <= 1.0 && desaturated.y <= 1.0 && desaturated.z <= 1.0);
+        assert!((hue(desaturated) - hue(orange)).abs() < 1e-9);
+
+        let clipped = ClampMode::Clip.apply(orange);
+        assert!((hue(clipped) - hue(orange)).abs() > 10.0);
+    }
+
+    #[test]
+    fn test_merge_halves() {
+        let (world, camera, background) = scenes::two_checker(1.0);
+        let config = Config::builder().width(8).height(8).build().unwrap();
+
+        for y in 0..config.height {
+            for x in 0..config.width {
+                let samples: Vec<Vec3> = (0..64)
+                    .map(|_| {
+                        let r = get_ray(x, y, &camera, &config);
+                        ray_color(&r, &world, 5, background.to_vec3(), T_MIN)
+                    })
+                    .collect();
+                let sum = |s: &[Vec3]| s.iter().fold(Vec3::ZERO, |acc, v| acc + *v);
+
+                let whole = Color::from_vec(sum(&samples), 64);
+                let first = Color::from_vec(sum(&samples[..24]), 24);
+                let second = Color::from_vec(sum(&samples[24..]), 40);
+                let merged = Color::merge(first, 24, second, 40);
+
+                let pairs = [(merged.r, whole.r), (merged.g, whole.g), (merged.b, whole.b)];
+                for (m, w) in pairs.iter() {
+                    assert!((*m as i32 - *w as i32).abs() <= 2);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_dithering_reduces_banding() {
+        // mean of each 4x4 tile of a ramp spanning a few quantization steps
+        let tiles = |dither: bool| {
+            let mut levels: Vec<u32> = (0..64)
+                .map(|tile| {
+                    let mut sum = 0;
+                    for y in 0..4 {
+                        for x in 4 * tile..4 * tile + 4 {
+                            let v = Vec3::ONE * (0.2 + 0.01 * x as f64 / 256.0);
+                            let c = if dither {
+                                Color::from_vec_dithered(v, 1, ClampMode::Clip, x, y)
+                            } else {
+                                Color::from_vec_clamped(v, 1, ClampMode::Clip)
+                            };
+                            sum += c.r as u32;
+                        }
+                    }
+                    sum
+                })
+                .collect();
+            levels.dedup();
+            levels.len()
+        };
+
+        assert!(tiles(true) > 2 * tiles(false));
+    }
+
+    #[test]
+    fn test_color_space_green() {
+        let green = vec3(0.0, 1.0, 0.0);
+        let encode = |space: ColorSpace| Color::from_vec(space.convert(green), 1);
+
+        let (srgb, rec2020) = (encode(ColorSpace::Srgb), encode(ColorSpace::Rec2020));
+        assert_eq!((srgb.r, srgb.g, srgb.b), (0, 255, 0));
+        assert!(rec2020.r > 0 && rec2020.g < 255 && rec2020.b > 0);
+        assert!((ColorSpace::Rec2020.convert(Vec3::ONE) - Vec3::ONE).norm() < 1e-3);
+        assert_eq!("rec2020".parse::<ColorSpace>(), Ok(ColorSpace::Rec2020));
+    }
+}
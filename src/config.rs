@@ -1,31 +1,109 @@
+use super::{render::TileOrder, ClampMode, ColorSpace};
+use serde::{Deserialize, Serialize};
 use std::fmt::Display;
 use std::io::Write;
 
-#[derive(Debug, Clone)]
+/// Render settings, loaded from `key = value` lines or from TOML when the
+/// file has a `.toml` extension. Missing keys keep their default value.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     pub width: u32,
     pub height: u32,
+    #[serde(skip)]
     pub aspect_ratio: f64,
     pub samples: u32,
     pub depth: u32,
+    pub target_variance: f64,
+    pub max_passes: u32,
+    /// order of the tiles of `render::render_tiled`
+    pub tile_order: TileOrder,
+    /// object count above which the scene is put in a BVH
+    pub bvh_threshold: u32,
+    pub clamp_mode: ClampMode,
+    pub color_space: ColorSpace,
+    /// primaries of the material colors and of the light transport
+    pub working_space: ColorSpace,
+    pub dither: bool,
+    /// trace the samples in antithetic pairs, see `get_ray_antithetic`
+    pub antithetic: bool,
+    /// panic on the first sample of non-finite radiance, see `render::render`
+    pub debug_nan: bool,
+    /// bits per channel of PNG outputs, 8 or 16
+    pub bit_depth: u8,
+    pub output: String,
+    /// self intersection distance, derived from the scene size when None
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub t_min: Option<f64>,
+    /// luminance limit of the indirect lighting of each sample, see `ray_color_clamped`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub indirect_clamp: Option<f64>,
+    /// bounces of a path before Russian roulette may end it, see `ray_color_roulette`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub roulette_min_bounces: Option<u32>,
+    /// RON scene description rendered instead of the built-in scene, see `scenes::from_ron`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scene_inline: Option<String>,
 
-    src_file: String,
+    #[serde(skip)]
+    pub(crate) src_file: String,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
-            width: 512,
-            height: 288,
+            width: 800,
+            height: 450,
             aspect_ratio: 16.0 / 9.0,
-            samples: 10,
-            depth: 5,
+            samples: 100,
+            depth: 50,
+            target_variance: 0.0,
+            max_passes: 1,
+            tile_order: TileOrder::Rows,
+            bvh_threshold: 8,
+            clamp_mode: ClampMode::Clip,
+            color_space: ColorSpace::Srgb,
+            working_space: ColorSpace::Srgb,
+            dither: false,
+            antithetic: false,
+            debug_nan: false,
+            bit_depth: 8,
+            output: String::from("out.png"),
+            t_min: None,
+            indirect_clamp: None,
+            roulette_min_bounces: None,
+            scene_inline: None,
             src_file: String::from(""),
         }
     }
 }
 
 impl Config {
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    /// Settings that render an empty or all black image, to print before
+    /// rendering. Unlike `ConfigBuilder::build` a loaded config is never
+    /// refused, so these are only warnings.
+    pub fn validate(&self) -> Vec<ConfigWarning> {
+        let mut warnings = Vec::new();
+        if self.width == 0 || self.height == 0 {
+            warnings.push(ConfigWarning::ZeroDimension);
+        }
+        if self.samples == 0 {
+            warnings.push(ConfigWarning::ZeroSamples);
+        }
+        if self.depth == 0 {
+            warnings.push(ConfigWarning::ZeroDepth);
+        }
+        if self.bit_depth != 8 && self.bit_depth != 16 {
+            warnings.push(ConfigWarning::UnsupportedBitDepth(self.bit_depth));
+        }
+
+        warnings
+    }
+
     pub fn load(path: &std::path::Path) -> Self {
         let content = match std::fs::read_to_string(path) {
             Ok(c) => c,
@@ -35,8 +113,23 @@ impl Config {
             }
         };
 
-        let mut config = Self::default();
-        config.src_file = String::from(path.to_str().unwrap());
+        if path.extension() == Some("toml".as_ref()) {
+            return match Self::from_toml(&content) {
+                Ok(config) => Self {
+                    src_file: String::from(path.to_str().unwrap()),
+                    ..config
+                },
+                Err(e) => {
+                    eprintln!("in {:?}, {}\n\tfallback to default", path, e);
+                    Self::default()
+                }
+            };
+        }
+
+        let mut config = Self {
+            src_file: String::from(path.to_str().unwrap()),
+            ..Self::default()
+        };
 
         let lines = content.split("\n");
         let mut line_count = 1;
@@ -66,6 +159,22 @@ impl Config {
                 "height" => config.height = line_content[1].parse::<u32>().unwrap(),
                 "samples" => config.samples = line_content[1].parse::<u32>().unwrap(),
                 "depth" => config.depth = line_content[1].parse::<u32>().unwrap(),
+                "target_variance" => config.target_variance = line_content[1].parse::<f64>().unwrap(),
+                "max_passes" => config.max_passes = line_content[1].parse::<u32>().unwrap(),
+                "bvh_threshold" => config.bvh_threshold = line_content[1].parse::<u32>().unwrap(),
+                "clamp_mode" => config.clamp_mode = line_content[1].parse::<ClampMode>().unwrap(),
+                "color_space" => config.color_space = line_content[1].parse::<ColorSpace>().unwrap(),
+                "working_space" => config.working_space = line_content[1].parse::<ColorSpace>().unwrap(),
+                "tile_order" => config.tile_order = line_content[1].parse::<TileOrder>().unwrap(),
+                "dither" => config.dither = line_content[1].parse::<bool>().unwrap(),
+                "antithetic" => config.antithetic = line_content[1].parse::<bool>().unwrap(),
+                "debug_nan" => config.debug_nan = line_content[1].parse::<bool>().unwrap(),
+                "bit_depth" => config.bit_depth = line_content[1].parse::<u8>().unwrap(),
+                "output" => config.output = String::from(line_content[1]),
+                "t_min" => config.t_min = Some(line_content[1].parse::<f64>().unwrap()),
+                "indirect_clamp" => config.indirect_clamp = Some(line_content[1].parse::<f64>().unwrap()),
+                "roulette_min_bounces" => config.roulette_min_bounces = Some(line_content[1].parse::<u32>().unwrap()),
+                "scene_inline" => config.scene_inline = Some(String::from(line_content[1])),
                 _ => {
                     eprintln!(
                         "in {2:?}, error at line {0}: \n\tunknown pattern: {1}",
@@ -82,22 +191,339 @@ impl Config {
         config
     }
 
+    pub fn from_toml(src: &str) -> Result<Self, toml::de::Error> {
+        let mut config: Self = toml::from_str(src)?;
+        config.aspect_ratio = config.width as f64 / config.height as f64;
+        Ok(config)
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).unwrap()
+    }
+
     pub fn save(&self, path: &std::path::Path) {
         let mut file = std::fs::File::create(path).unwrap();
-        file.write(format!("width = {}\n", self.width).as_bytes()).unwrap();
-        file.write(format!("height = {}\n", self.height).as_bytes()).unwrap();
-        file.write(format!("samples = {}\n", self.samples).as_bytes()).unwrap();
-        file.write(format!("depth = {}", self.depth).as_bytes()).unwrap();
+        if path.extension() == Some("toml".as_ref()) {
+            file.write_all(self.to_toml().as_bytes()).unwrap();
+            return;
+        }
+
+        file.write_all(format!("width = {}\n", self.width).as_bytes()).unwrap();
+        file.write_all(format!("height = {}\n", self.height).as_bytes()).unwrap();
+        file.write_all(format!("samples = {}\n", self.samples).as_bytes()).unwrap();
+        file.write_all(format!("depth = {}\n", self.depth).as_bytes()).unwrap();
+        file.write_all(format!("target_variance = {}\n", self.target_variance).as_bytes()).unwrap();
+        file.write_all(format!("max_passes = {}\n", self.max_passes).as_bytes()).unwrap();
+        file.write_all(format!("bvh_threshold = {}\n", self.bvh_threshold).as_bytes()).unwrap();
+        file.write_all(format!("clamp_mode = {}\n", self.clamp_mode).as_bytes()).unwrap();
+        file.write_all(format!("color_space = {}\n", self.color_space).as_bytes()).unwrap();
+        file.write_all(format!("working_space = {}\n", self.working_space).as_bytes()).unwrap();
+        file.write_all(format!("tile_order = {}\n", self.tile_order).as_bytes()).unwrap();
+        file.write_all(format!("dither = {}\n", self.dither).as_bytes()).unwrap();
+        file.write_all(format!("antithetic = {}\n", self.antithetic).as_bytes()).unwrap();
+        file.write_all(format!("debug_nan = {}\n", self.debug_nan).as_bytes()).unwrap();
+        file.write_all(format!("bit_depth = {}\n", self.bit_depth).as_bytes()).unwrap();
+        file.write_all(format!("output = {}", self.output).as_bytes()).unwrap();
+        if let Some(t_min) = self.t_min {
+            file.write_all(format!("\nt_min = {}", t_min).as_bytes()).unwrap();
+        }
+        if let Some(indirect_clamp) = self.indirect_clamp {
+            file.write_all(format!("\nindirect_clamp = {}", indirect_clamp).as_bytes()).unwrap();
+        }
+        if let Some(min_bounces) = self.roulette_min_bounces {
+            file.write_all(format!("\nroulette_min_bounces = {}", min_bounces).as_bytes()).unwrap();
+        }
+        if let Some(scene) = &self.scene_inline {
+            file.write_all(format!("\nscene_inline = {}", scene.replace('\n', " ")).as_bytes()).unwrap();
+        }
     }
 }
 
 impl Display for Config {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Config: ({})\n", self.src_file)?;
-        write!(f, "\twidth   = {}\n", self.width)?;
-        write!(f, "\theight  = {}\n", self.height)?;
-        write!(f, "\tsamples = {}\n", self.samples)?;
-        write!(f, "\tdepth   = {}\n", self.depth)?;
+        writeln!(f, "Config: ({})", self.src_file)?;
+        writeln!(f, "\twidth   = {}", self.width)?;
+        writeln!(f, "\theight  = {}", self.height)?;
+        writeln!(f, "\tsamples = {}", self.samples)?;
+        writeln!(f, "\tdepth   = {}", self.depth)?;
+        writeln!(f, "\ttarget_variance = {}", self.target_variance)?;
+        writeln!(f, "\tmax_passes      = {}", self.max_passes)?;
+        writeln!(f, "\tbvh_threshold   = {}", self.bvh_threshold)?;
+        writeln!(f, "\tclamp_mode      = {}", self.clamp_mode)?;
+        writeln!(f, "\tcolor_space     = {}", self.color_space)?;
+        writeln!(f, "\tworking_space   = {}", self.working_space)?;
+        writeln!(f, "\ttile_order      = {}", self.tile_order)?;
+        writeln!(f, "\tdither          = {}", self.dither)?;
+        writeln!(f, "\tantithetic      = {}", self.antithetic)?;
+        writeln!(f, "\tdebug_nan       = {}", self.debug_nan)?;
+        writeln!(f, "\tbit_depth       = {}", self.bit_depth)?;
+        writeln!(f, "\toutput          = {}", self.output)?;
+        match self.t_min {
+            Some(t_min) => writeln!(f, "\tt_min           = {}", t_min)?,
+            None => writeln!(f, "\tt_min           = auto")?,
+        }
+        if let Some(indirect_clamp) = self.indirect_clamp {
+            writeln!(f, "\tindirect_clamp  = {}", indirect_clamp)?;
+        }
+        if let Some(min_bounces) = self.roulette_min_bounces {
+            writeln!(f, "\troulette_min_bounces = {}", min_bounces)?;
+        }
+        if self.scene_inline.is_some() {
+            writeln!(f, "\tscene_inline    = yes")?;
+        }
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigError {
+    ZeroDimension,
+    ZeroSamples,
+    BitDepth,
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ZeroDimension => write!(f, "width and height must be greater than 0"),
+            Self::ZeroSamples => write!(f, "samples must be greater than 0"),
+            Self::BitDepth => write!(f, "bit depth must be 8 or 16"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigWarning {
+    ZeroDimension,
+    ZeroSamples,
+    ZeroDepth,
+    UnsupportedBitDepth(u8),
+}
+
+impl Display for ConfigWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ZeroDimension => write!(f, "width or height is 0, the image is empty: set both to at least 1"),
+            Self::ZeroSamples => write!(f, "samples is 0, the image is black: set it to at least 1"),
+            Self::ZeroDepth => write!(f, "depth is 0, all rays return black: set it to at least 1, e.g. 50"),
+            Self::UnsupportedBitDepth(depth) => write!(f, "bit_depth is {}, the image is written with 8 bits: set it to 8 or 16", depth),
+        }
+    }
+}
+
+/// Chained construction of a `Config`, starting from the defaults.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl ConfigBuilder {
+    pub fn width(mut self, width: u32) -> Self {
+        self.config.width = width;
+        self
+    }
+
+    pub fn height(mut self, height: u32) -> Self {
+        self.config.height = height;
+        self
+    }
+
+    pub fn samples(mut self, samples: u32) -> Self {
+        self.config.samples = samples;
+        self
+    }
+
+    pub fn depth(mut self, depth: u32) -> Self {
+        self.config.depth = depth;
+        self
+    }
+
+    pub fn target_variance(mut self, target_variance: f64) -> Self {
+        self.config.target_variance = target_variance;
+        self
+    }
+
+    pub fn max_passes(mut self, max_passes: u32) -> Self {
+        self.config.max_passes = max_passes;
+        self
+    }
+
+    pub fn bvh_threshold(mut self, bvh_threshold: u32) -> Self {
+        self.config.bvh_threshold = bvh_threshold;
+        self
+    }
+
+    pub fn clamp_mode(mut self, clamp_mode: ClampMode) -> Self {
+        self.config.clamp_mode = clamp_mode;
+        self
+    }
+
+    pub fn color_space(mut self, color_space: ColorSpace) -> Self {
+        self.config.color_space = color_space;
+        self
+    }
+
+    pub fn working_space(mut self, working_space: ColorSpace) -> Self {
+        self.config.working_space = working_space;
+        self
+    }
+
+    pub fn tile_order(mut self, tile_order: TileOrder) -> Self {
+        self.config.tile_order = tile_order;
+        self
+    }
+
+    pub fn dither(mut self, dither: bool) -> Self {
+        self.config.dither = dither;
+        self
+    }
+
+    pub fn antithetic(mut self, antithetic: bool) -> Self {
+        self.config.antithetic = antithetic;
+        self
+    }
+
+    pub fn debug_nan(mut self, debug_nan: bool) -> Self {
+        self.config.debug_nan = debug_nan;
+        self
+    }
+
+    pub fn bit_depth(mut self, bit_depth: u8) -> Self {
+        self.config.bit_depth = bit_depth;
+        self
+    }
+
+    pub fn output(mut self, output: &str) -> Self {
+        self.config.output = String::from(output);
+        self
+    }
+
+    pub fn t_min(mut self, t_min: f64) -> Self {
+        self.config.t_min = Some(t_min);
+        self
+    }
+
+    pub fn indirect_clamp(mut self, indirect_clamp: f64) -> Self {
+        self.config.indirect_clamp = Some(indirect_clamp);
+        self
+    }
+
+    pub fn roulette_min_bounces(mut self, min_bounces: u32) -> Self {
+        self.config.roulette_min_bounces = Some(min_bounces);
+        self
+    }
+
+    pub fn build(self) -> Result<Config, ConfigError> {
+        let mut config = self.config;
+        if config.width == 0 || config.height == 0 {
+            return Err(ConfigError::ZeroDimension);
+        }
+        if config.samples == 0 {
+            return Err(ConfigError::ZeroSamples);
+        }
+        if config.bit_depth != 8 && config.bit_depth != 16 {
+            return Err(ConfigError::BitDepth);
+        }
+
+        config.aspect_ratio = config.width as f64 / config.height as f64;
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn test_builder() {
+        let config = Config::builder()
+            .width(400)
+            .height(200)
+            .samples(200)
+            .depth(50)
+            .build()
+            .unwrap();
+
+        assert_eq!((config.width, config.height), (400, 200));
+        assert_eq!((config.samples, config.depth), (200, 50));
+        assert_eq!(config.aspect_ratio, 2.0);
+        assert_eq!(config.max_passes, Config::default().max_passes);
+    }
+
+    #[test]
+    fn test_builder_errors() {
+        assert_eq!(
+            Config::builder().width(0).build().unwrap_err(),
+            ConfigError::ZeroDimension
+        );
+        assert_eq!(
+            Config::builder().samples(0).build().unwrap_err(),
+            ConfigError::ZeroSamples
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(Config::default().validate().is_empty());
+
+        let black = Config { depth: 0, ..Config::default() };
+        assert_eq!(black.validate(), vec![ConfigWarning::ZeroDepth]);
+        assert!(black.validate()[0].to_string().contains("all rays return black"));
+
+        let empty = Config { width: 0, samples: 0, ..Config::default() };
+        assert_eq!(empty.validate(), vec![ConfigWarning::ZeroDimension, ConfigWarning::ZeroSamples]);
+
+        let deep = Config { bit_depth: 12, ..Config::default() };
+        assert_eq!(deep.validate(), vec![ConfigWarning::UnsupportedBitDepth(12)]);
+        assert!(deep.validate()[0].to_string().contains("set it to 8 or 16"));
+    }
+
+    #[test]
+    fn test_toml_round_trip() {
+        let config = Config::builder()
+            .width(320)
+            .height(240)
+            .samples(16)
+            .target_variance(1e-4)
+            .clamp_mode(ClampMode::Desaturate)
+            .roulette_min_bounces(3)
+            .output("render.exr")
+            .build()
+            .unwrap();
+
+        assert_eq!(Config::from_toml(&config.to_toml()).unwrap(), config);
+
+        let partial = Config::from_toml("samples = 8").unwrap();
+        assert_eq!(partial, Config { samples: 8, ..Config::default() });
+    }
+
+    #[test]
+    fn test_inline_scene() {
+        let config = Config::from_toml(
+            r#"
+            width = 8
+            height = 8
+            samples = 4
+            scene_inline = """(
+                camera: (eye: (x: 0, y: 1, z: -6), target: (x: 0, y: 1, z: 0)),
+                background: (x: 0.7, y: 0.8, z: 1.0),
+                objects: [
+                    Sphere(center: (x: 0, y: -100, z: 0), radius: 100, material: Lambertian((x: 0.5, y: 0.5, z: 0.5))),
+                    Sphere(center: (x: 0, y: 1, z: 0), radius: 1, material: Metal(albedo: (x: 0.8, y: 0.6, z: 0.2), fuzz: 0.1)),
+                ],
+            )"""
+            "#,
+        )
+        .unwrap();
+
+        let src = config.scene_inline.as_ref().unwrap();
+        let (world, camera, background) = scenes::from_ron(src, config.aspect_ratio).unwrap();
+        assert_eq!(world.len(), 2);
+        let image = render::render(&world, &camera, background, &config);
+        assert!(image.iter().any(|c| c.r > 0));
+
+        assert!(scenes::from_ron("(objects: [Cone()])", 1.0).is_err());
+    }
+}
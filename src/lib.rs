@@ -1,18 +1,37 @@
+pub mod buffer;
 pub mod camera;
 pub mod color;
 pub mod config;
+pub mod light;
 pub mod material;
 pub mod math;
+pub mod mesh;
+pub mod metrics;
 pub mod objects;
+pub mod output;
+#[cfg(feature = "photon_map")]
+pub mod photon;
+pub mod postprocess;
+pub mod profile;
+pub mod render;
 pub mod scenes;
+pub mod temporal;
 pub mod texture;
+#[cfg(feature = "notify")]
+pub mod watch;
 
+pub use buffer::*;
 pub use camera::*;
 pub use color::*;
 pub use config::*;
+pub use light::*;
 pub use material::*;
 pub use math::*;
 pub use objects::*;
+#[cfg(feature = "photon_map")]
+pub use photon::*;
+pub use profile::*;
+pub use temporal::*;
 pub use texture::*;
 
 use std::ops::Range;
@@ -40,24 +59,71 @@ pub fn random_range(min: f64, max: f64) -> f64 {
 }
 
 pub fn get_ray(x: u32, y: u32, camera: &Camera, config: &Config) -> Ray {
-    let u = (x as f64 + random()) / ((config.width - 1) as f64);
-    let v = (y as f64 + random()) / ((config.height - 1) as f64);
+    let offset = (random(), random());
+    get_ray_at(x, y, offset, Vec3::random_unit_disk(), camera, config)
+}
+
+/// Two rays through pixel (x, y) of mirrored sub-pixel offsets `(du, dv)` and
+/// `(1 - du, 1 - dv)` and opposite lens points, whose errors partly cancel out
+/// when averaged.
+pub fn get_ray_antithetic(x: u32, y: u32, camera: &Camera, config: &Config) -> [Ray; 2] {
+    let (du, dv, lens) = (random(), random(), Vec3::random_unit_disk());
+    [
+        get_ray_at(x, y, (du, dv), lens, camera, config),
+        get_ray_at(x, y, (1.0 - du, 1.0 - dv), -lens, camera, config),
+    ]
+}
+
+/// Ray through the point of sub-pixel offset `(du, dv)` of pixel (x, y) and
+/// the point `lens` of the unit disk. A single pixel row or column spans the
+/// whole screen.
+pub fn get_ray_at(
+    x: u32,
+    y: u32,
+    (du, dv): (f64, f64),
+    lens: Vec3,
+    camera: &Camera,
+    config: &Config,
+) -> Ray {
+    let u = (x as f64 + du) / ((config.width.max(2) - 1) as f64);
+    let v = (y as f64 + dv) / ((config.height.max(2) - 1) as f64);
+
+    camera.get_ray_lens(u, v, lens)
+}
 
-    camera.get_ray(u, v)
+/// Default distance along rays under which hits are ignored, to keep
+/// scattered rays from hitting the surface they leave. It is a length in
+/// scene units, whatever the length of the ray directions.
+pub const T_MIN: f64 = 0.001;
+
+/// Self intersection distance suited to the size of the scene, `T_MIN` for
+/// unbounded scenes.
+pub fn scene_epsilon(world: &impl Hit) -> f64 {
+    match world.bounding_sphere() {
+        Some((_, radius)) if radius.is_finite() && radius > 0.0 => 2.0 * radius * 1e-6,
+        _ => T_MIN,
+    }
 }
 
-pub fn ray_color(r: &Ray, world: &impl Hit, depth: u32, background: Vec3) -> Vec3 {
+pub fn ray_color(
+    r: &Ray,
+    world: &(impl Hit + ?Sized),
+    depth: u32,
+    background: Vec3,
+    t_min: f64,
+) -> Vec3 {
     if depth == 0 {
         return Vec3::ZERO;
     }
 
-    if let Some(record) = world.hit(&r, 0.001, f64::INFINITY) {
-        let scatter_result = record.mat.scatter(&r, &record);
-        let emit = record.mat.emitted(record.u, record.v, &record.point);
+    if let Some(record) = world.hit(r, t_min / r.dir.norm(), f64::INFINITY) {
+        let scatter_result = record.mat.scatter(r, &record);
+        let emit = record.mat.emitted_hit(&record);
 
         if let Some((attenuation, scattered)) = scatter_result {
             return emit
-                + attenuation.to_vec3() * ray_color(&scattered, world, depth - 1, background);
+                + attenuation.to_vec3()
+                    * ray_color(&scattered, world, depth - 1, background, t_min);
         }
 
         return emit;
@@ -66,3 +132,476 @@ pub fn ray_color(r: &Ray, world: &impl Hit, depth: u32, background: Vec3) -> Vec
     // background
     background
 }
+
+/// Radiance carried by a camera ray, the estimator of `render::render_with`,
+/// e.g. to swap the path tracer for a normals or ambient occlusion debug view.
+pub trait Integrator: Send + Sync {
+    fn radiance(
+        &self,
+        r: &Ray,
+        world: &dyn Hit,
+        background: Vec3,
+        depth: u32,
+        t_min: f64,
+    ) -> Vec3;
+}
+
+/// The path tracer of `ray_color`, with the indirect clamp of
+/// `ray_color_clamped` and the Russian roulette of `ray_color_roulette` when
+/// set, used by `render::render` with the settings of the config.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PathTracer {
+    pub indirect_clamp: Option<f64>,
+    pub roulette_min_bounces: Option<u32>,
+}
+
+impl Integrator for PathTracer {
+    fn radiance(
+        &self,
+        r: &Ray,
+        world: &dyn Hit,
+        background: Vec3,
+        depth: u32,
+        t_min: f64,
+    ) -> Vec3 {
+        let min_bounces = self.roulette_min_bounces;
+        if self.indirect_clamp.is_none() && min_bounces.is_none() {
+            return ray_color(r, world, depth, background, t_min);
+        }
+
+        let (direct, indirect) = trace_split(r, world, depth, background, t_min, min_bounces);
+        match self.indirect_clamp {
+            Some(clamp) => clamp_indirect(direct, indirect, clamp),
+            None => direct + indirect,
+        }
+    }
+}
+
+/// The path tracer of `ray_color_sun`, for `render::render_with` to light
+/// the scene by `sun`.
+#[derive(Debug, Clone, Copy)]
+pub struct SunPathTracer {
+    pub sun: DirectionalLight,
+}
+
+impl Integrator for SunPathTracer {
+    fn radiance(
+        &self,
+        r: &Ray,
+        world: &dyn Hit,
+        background: Vec3,
+        depth: u32,
+        t_min: f64,
+    ) -> Vec3 {
+        ray_color_sun(r, world, depth, background, t_min, &self.sun)
+    }
+}
+
+/// Same paths as `ray_color` but returns the direct lighting, light reaching
+/// the camera after at most one bounce, apart from the indirect lighting.
+pub fn ray_color_split(
+    r: &Ray,
+    world: &(impl Hit + ?Sized),
+    depth: u32,
+    background: Vec3,
+    t_min: f64,
+) -> (Vec3, Vec3) {
+    trace_split(r, world, depth, background, t_min, None)
+}
+
+/// Same as `ray_color`, with Russian roulette ending the paths at random
+/// once they made `min_bounces` bounces, the more likely the darker their
+/// throughput. Surviving paths are weighted up so the estimate is unbiased,
+/// but dark paths stop long before `depth`.
+pub fn ray_color_roulette(
+    r: &Ray,
+    world: &(impl Hit + ?Sized),
+    depth: u32,
+    background: Vec3,
+    t_min: f64,
+    min_bounces: u32,
+) -> Vec3 {
+    let (direct, indirect) = trace_split(r, world, depth, background, t_min, Some(min_bounces));
+    direct + indirect
+}
+
+// `ray_color_split`, with Russian roulette after `min_bounces` if any
+fn trace_split(
+    r: &Ray,
+    world: &(impl Hit + ?Sized),
+    depth: u32,
+    background: Vec3,
+    t_min: f64,
+    min_bounces: Option<u32>,
+) -> (Vec3, Vec3) {
+    let (mut direct, mut indirect) = (Vec3::ZERO, Vec3::ZERO);
+    let mut throughput = Vec3::ONE;
+    let mut r = *r;
+
+    for bounce in 0..depth {
+        let hit = world.hit(&r, t_min / r.dir.norm(), f64::INFINITY);
+        let (contribution, scattered) = match hit {
+            Some(record) => {
+                let scatter_result = record.mat.scatter(&r, &record);
+                let emit = record.mat.emitted_hit(&record);
+                (throughput * emit, scatter_result)
+            }
+            None => (throughput * background, None),
+        };
+
+        if bounce < 2 {
+            direct += contribution;
+        } else {
+            indirect += contribution;
+        }
+
+        match scattered {
+            Some((attenuation, scattered)) => {
+                throughput = throughput * attenuation.to_vec3();
+                r = scattered;
+            }
+            None => break,
+        }
+        if !survives_roulette(&mut throughput, bounce + 1, min_bounces) {
+            break;
+        }
+    }
+
+    (direct, indirect)
+}
+
+// Russian roulette of a path after `bounces`, surviving with the probability
+// of its largest throughput component, its throughput divided by it
+fn survives_roulette(throughput: &mut Vec3, bounces: u32, min_bounces: Option<u32>) -> bool {
+    match min_bounces {
+        Some(min_bounces) if bounces >= min_bounces => (),
+        _ => return true,
+    }
+
+    let p = throughput.x.max(throughput.y).max(throughput.z).clamp(0.05, 1.0);
+    if random() >= p {
+        return false;
+    }
+    *throughput /= p;
+    true
+}
+
+/// Same paths as `ray_color`, with the luminance of the indirect lighting
+/// (see `ray_color_split`) clamped to `indirect_clamp` to remove fireflies
+/// while keeping the direct lighting exact.
+pub fn ray_color_clamped(
+    r: &Ray,
+    world: &(impl Hit + ?Sized),
+    depth: u32,
+    background: Vec3,
+    t_min: f64,
+    indirect_clamp: f64,
+) -> Vec3 {
+    let (direct, indirect) = ray_color_split(r, world, depth, background, t_min);
+    clamp_indirect(direct, indirect, indirect_clamp)
+}
+
+fn clamp_indirect(direct: Vec3, indirect: Vec3, indirect_clamp: f64) -> Vec3 {
+    let luminance = indirect.luminance();
+    if luminance > indirect_clamp {
+        direct + indirect * (indirect_clamp / luminance)
+    } else {
+        direct + indirect
+    }
+}
+
+/// Same as `ray_color` in a scene lit by `sun`: its irradiance is sampled at
+/// each hit of a diffuse material, which reflects `attenuation / PI` of it,
+/// or of a medium, which scatters `attenuation` times its phase function of
+/// it, and it is only seen directly by camera and specular rays.
+pub fn ray_color_sun(
+    r: &Ray,
+    world: &(impl Hit + ?Sized),
+    depth: u32,
+    background: Vec3,
+    t_min: f64,
+    sun: &DirectionalLight,
+) -> Vec3 {
+    let mut color = Vec3::ZERO;
+    let mut throughput = Vec3::ONE;
+    let mut specular = true;
+    let mut r = *r;
+
+    for _ in 0..depth {
+        let record = match world.hit(&r, t_min / r.dir.norm(), f64::INFINITY) {
+            Some(record) => record,
+            None => {
+                let sun_disk = if specular { sun.radiance(r.dir) } else { Vec3::ZERO };
+                color += throughput * (background + sun_disk);
+                break;
+            }
+        };
+
+        color += throughput * record.mat.emitted_hit(&record);
+        let (attenuation, scattered) = match record.mat.scatter(&r, &record) {
+            Some(scatter) => scatter,
+            None => break,
+        };
+
+        let attenuation = attenuation.to_vec3();
+        specular = record.mat.is_specular();
+        if !specular {
+            let irradiance = sun.irradiance(world, &record, t_min, r.time);
+            let cos = r.dir.normalize().dot(sun.direction);
+            let density = record.mat.phase(cos).unwrap_or(1.0 / PI);
+            color += throughput * attenuation * irradiance * density;
+        }
+        throughput = throughput * attenuation;
+        r = scattered;
+    }
+
+    color
+}
+
+/// Fraction of `shadow_rays` rays from `point` toward random points of
+/// `light` that reach it unoccluded: 1 in full light, 0 in the umbra and in
+/// between in the penumbra of an area light.
+pub fn soft_shadow(
+    world: &impl Hit,
+    light: &dyn Hit,
+    point: Point3,
+    t_min: f64,
+    shadow_rays: u32,
+) -> f64 {
+    let visible = (0..shadow_rays)
+        .filter(|_| {
+            let dir = light.random_toward(point);
+            let r = ray(point, dir, 0.0).with_kind(RayKind::Shadow);
+            let t_min = t_min / dir.norm();
+            match light.hit(&r, t_min, f64::INFINITY) {
+                Some(target) => world.hit(&r, t_min, target.t * (1.0 - 1e-6)).is_none(),
+                None => false,
+            }
+        })
+        .count();
+
+    visible as f64 / shadow_rays as f64
+}
+
+/// Quick preview shading of the first hit of `r`: its emission plus its
+/// albedo times the cosine weighted radiance of a random point of `light`,
+/// dimmed by `soft_shadow`. There is no indirect lighting.
+pub fn ray_color_preview(
+    r: &Ray,
+    world: &impl Hit,
+    light: &dyn Hit,
+    background: Vec3,
+    t_min: f64,
+    shadow_rays: u32,
+) -> Vec3 {
+    let record = match world.hit(r, t_min / r.dir.norm(), f64::INFINITY) {
+        Some(record) => record,
+        None => return background,
+    };
+
+    let emitted = record.mat.emitted_hit(&record);
+    let albedo = match record.mat.scatter(r, &record) {
+        Some((attenuation, _)) => attenuation.to_vec3(),
+        None => return emitted,
+    };
+    let to_light = light.random_toward(record.point);
+    let radiance = match light.hit(
+        &ray(record.point, to_light, r.time),
+        t_min / to_light.norm(),
+        f64::INFINITY,
+    ) {
+        Some(rec) => rec.mat.emitted_hit(&rec),
+        None => return emitted,
+    };
+    let cosine = record.normal.dot(to_light.normalize()).max(0.0);
+    let shadow = soft_shadow(world, light, record.point, t_min, shadow_rays);
+
+    emitted + albedo * radiance * cosine * shadow
+}
+
+/// Number of bounces of a path traced like `ray_color`, or like
+/// `ray_color_roulette` with `min_bounces`, before it leaves the scene, is
+/// absorbed, ended by the roulette or reaches `depth`.
+pub fn path_length(
+    r: &Ray,
+    world: &impl Hit,
+    depth: u32,
+    t_min: f64,
+    min_bounces: Option<u32>,
+) -> u32 {
+    let mut throughput = Vec3::ONE;
+    let mut r = *r;
+    for bounce in 0..depth {
+        match world
+            .hit(&r, t_min / r.dir.norm(), f64::INFINITY)
+            .and_then(|record| record.mat.scatter(&r, &record))
+        {
+            Some((attenuation, scattered)) => {
+                throughput = throughput * attenuation.to_vec3();
+                r = scattered;
+            }
+            None => return bounce,
+        }
+        if !survives_roulette(&mut throughput, bounce + 1, min_bounces) {
+            return bounce + 1;
+        }
+    }
+
+    depth
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_split_sums_to_beauty() {
+        let (world, camera, background) = scenes::cornell_box(1.0);
+        let config = Config {
+            width: 16,
+            height: 16,
+            ..Config::default()
+        };
+
+        for (i, (x, y)) in [(2, 3), (8, 8), (12, 1), (15, 15)].iter().enumerate() {
+            fastrand::seed(i as u64);
+            let r = get_ray(*x, *y, &camera, &config);
+            let beauty = ray_color(&r, &world, 10, background.to_vec3(), T_MIN);
+
+            fastrand::seed(i as u64);
+            let r = get_ray(*x, *y, &camera, &config);
+            let (direct, indirect) = ray_color_split(&r, &world, 10, background.to_vec3(), T_MIN);
+
+            assert!((direct + indirect - beauty).norm() < 1e-9 * (1.0 + beauty.norm()));
+        }
+    }
+
+    #[test]
+    fn test_get_ray_single_pixel() {
+        let (_, camera, _) = scenes::cornell_box(1.0);
+        let config = Config {
+            width: 1,
+            height: 1,
+            ..Config::default()
+        };
+
+        let r = get_ray_at(0, 0, (0.5, 0.5), Vec3::ZERO, &camera, &config);
+        let center = camera.get_ray_lens(0.5, 0.5, Vec3::ZERO);
+        assert!((r.dir.normalize() - center.dir.normalize()).norm() < 1e-12);
+    }
+
+    #[test]
+    fn test_indirect_clamp_keeps_direct() {
+        let (world, camera, background) = scenes::cornell_box(1.0);
+        let config = Config {
+            width: 16,
+            height: 16,
+            ..Config::default()
+        };
+
+        let mut clamped = 0;
+        for i in 0..200 {
+            fastrand::seed(i);
+            let r = get_ray(8, 8, &camera, &config);
+            let (direct, indirect) = ray_color_split(&r, &world, 10, background.to_vec3(), T_MIN);
+
+            fastrand::seed(i);
+            let r = get_ray(8, 8, &camera, &config);
+            let color = ray_color_clamped(&r, &world, 10, background.to_vec3(), T_MIN, 0.05);
+
+            let kept = color - direct;
+            assert!(kept.luminance() <= 0.05 + 1e-9);
+            if indirect.luminance() > 0.05 {
+                clamped += 1;
+            } else {
+                assert!((kept - indirect).norm() < 1e-12);
+            }
+        }
+        assert!(clamped > 0);
+    }
+
+    #[test]
+    fn test_russian_roulette_is_unbiased() {
+        // inside a closed sphere glowing 1 and reflecting `albedo`
+        #[derive(Debug)]
+        struct Glow(Color);
+        impl Material for Glow {
+            fn scatter(&self, _r: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+                let dir = rec.normal + Vec3::random_unit_sphere();
+                Some((self.0, ray(rec.point, dir, 0.0)))
+            }
+
+            fn emitted(&self, _u: f64, _v: f64, _point: &Point3) -> Vec3 {
+                Vec3::ONE
+            }
+        }
+        let albedo = Color::from_floats([0.5, 0.5, 0.5, 1.0]);
+        let world: HittableList =
+            vec![Arc::new(Sphere::new(Vec3::ZERO, 10.0, Arc::new(Glow(albedo))))];
+        let expected = 1.0 / (1.0 - albedo.to_vec3().x);
+
+        let r = ray(Vec3::ZERO, vec3(0.0, 0.0, -1.0), 0.0);
+        let n = 20000;
+        let mean = |min_bounces: Option<u32>| {
+            let tracer = PathTracer {
+                indirect_clamp: None,
+                roulette_min_bounces: min_bounces,
+            };
+            let radiance = (0..n)
+                .map(|_| tracer.radiance(&r, &world, Vec3::ZERO, 50, T_MIN).x)
+                .sum::<f64>();
+            let bounces = (0..n)
+                .map(|_| path_length(&r, &world, 50, T_MIN, min_bounces))
+                .sum::<u32>();
+            (radiance / n as f64, bounces as f64 / n as f64)
+        };
+
+        let (full, full_bounces) = mean(None);
+        let (roulette, roulette_bounces) = mean(Some(2));
+        assert_eq!(full_bounces, 50.0);
+        assert!((full - expected).abs() < 1e-6);
+        assert!((roulette - expected).abs() < 0.05, "{}", roulette);
+        // two bounces, then a survival probability under one half at each one
+        assert!((2.0..5.0).contains(&roulette_bounces));
+    }
+
+    #[test]
+    fn test_soft_shadow_penumbra() {
+        let white: Arc<dyn Material> = Arc::new(Lambertian::default());
+        let light: Arc<dyn Hit> = Arc::new(XZRect::new(
+            (-1.0, 1.0),
+            (-1.0, 1.0),
+            5.0,
+            Arc::new(DiffuseLight::from_color(Color::WHITE, 4.0)),
+        ));
+        let world: HittableList = vec![
+            light.clone(),
+            // occluder over the x < 0 half of the floor
+            Arc::new(XZRect::new((-10.0, 0.0), (-10.0, 10.0), 2.5, white.clone())),
+            Arc::new(XZRect::new((-10.0, 10.0), (-10.0, 10.0), 0.0, white)),
+        ];
+
+        let shadow = |x| soft_shadow(&world, light.as_ref(), vec3(x, 0.0, 0.0), T_MIN, 400);
+        assert_eq!(shadow(5.0), 1.0);
+        assert_eq!(shadow(-5.0), 0.0);
+        let penumbra = shadow(0.0);
+        assert!(penumbra > 0.3 && penumbra < 0.7, "{}", penumbra);
+
+        let down = |x| ray(vec3(x, 1.0, 0.0), vec3(0.0, -1.0, 0.0), 0.0);
+        let preview =
+            |x| ray_color_preview(&down(x), &world, light.as_ref(), Vec3::ZERO, T_MIN, 16);
+        assert!(preview(5.0).norm() > 0.5);
+        assert_eq!(preview(-5.0), Vec3::ZERO);
+
+        // an occluder hidden from shadow rays casts no shadow
+        let mut unshadowed = world.clone();
+        let mask = VisibilityMask {
+            shadow: false,
+            ..VisibilityMask::ALL
+        };
+        unshadowed[1] = Arc::new(Visibility::new(unshadowed[1].clone(), mask));
+        let shadow = |x| soft_shadow(&unshadowed, light.as_ref(), vec3(x, 0.0, 0.0), T_MIN, 16);
+        assert_eq!(shadow(-5.0), 1.0);
+    }
+}
@@ -0,0 +1,159 @@
+use super::{random, ray, Hit, HitRecord, RayKind, Vec3, PI};
+
+/// Light coming from a far away disk of angular radius `angular_radius` (in
+/// radians) around `direction`, like the sun, `color` being the irradiance it
+/// gives to a surface facing it. It is sampled explicitly from diffuse
+/// surfaces and media by `ray_color_sun`, and seen by camera and specular
+/// rays leaving the scene.
+#[derive(Debug, Clone, Copy)]
+pub struct DirectionalLight {
+    pub direction: Vec3,
+    pub color: Vec3,
+    pub angular_radius: f64,
+}
+
+impl DirectionalLight {
+    /// `direction` points toward the light.
+    pub fn new(direction: Vec3, color: Vec3, angular_radius: f64) -> Self {
+        Self {
+            direction: direction.normalize(),
+            color,
+            angular_radius,
+        }
+    }
+
+    pub fn solid_angle(&self) -> f64 {
+        2.0 * PI * (1.0 - self.angular_radius.cos())
+    }
+
+    /// Uniformly distributed unit direction toward the light disk.
+    pub fn sample_direction(&self) -> Vec3 {
+        let cos_max = self.angular_radius.cos();
+        let z = 1.0 - random() * (1.0 - cos_max);
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * PI * random();
+        let (a, b) = self.direction.orthonormal_pair();
+        r * phi.cos() * a + r * phi.sin() * b + z * self.direction
+    }
+
+    /// Radiance seen in the direction `dir` leaving the scene.
+    pub fn radiance(&self, dir: Vec3) -> Vec3 {
+        if dir.normalize().dot(self.direction) >= self.angular_radius.cos() {
+            self.color / self.solid_angle()
+        } else {
+            Vec3::ZERO
+        }
+    }
+
+    /// Irradiance at the hit, zero if the light is behind the surface or
+    /// occluded by `world`. Hits of media have no surface to face the light.
+    pub fn irradiance(
+        &self,
+        world: &(impl Hit + ?Sized),
+        rec: &HitRecord,
+        t_min: f64,
+        time: f64,
+    ) -> Vec3 {
+        let dir = self.sample_direction();
+        let cos = match rec.mat.phase(1.0) {
+            Some(_) => 1.0,
+            None => rec.normal.dot(dir),
+        };
+        if cos <= 0.0 {
+            return Vec3::ZERO;
+        }
+
+        let shadow = ray(rec.point, dir, time).with_kind(RayKind::Shadow);
+        if world.hit(&shadow, t_min, f64::INFINITY).is_some() {
+            return Vec3::ZERO;
+        }
+        self.color * cos
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_sun_irradiance() {
+        let sun = DirectionalLight::new(vec3(0.0, 1.0, 0.0), vec3(3.0, 3.0, 3.0), 0.01);
+        let ground: Arc<dyn Material> = Arc::new(Lambertian::from_color(Color::WHITE));
+        let floor = XZRect::new((-10.0, 10.0), (-10.0, 10.0), 0.0, ground.clone());
+        let from = |y| ray(vec3(0.0, y, 0.0), vec3(0.0, -y, 0.0), 0.0);
+
+        // lit from above, in the dark below
+        let world = HittableList::new();
+        let top = floor.hit(&from(1.0), 0.0, f64::INFINITY).unwrap();
+        let bottom = floor.hit(&from(-1.0), 0.0, f64::INFINITY).unwrap();
+        for _ in 0..100 {
+            let e = sun.irradiance(&world, &top, T_MIN, 0.0);
+            assert!((e - sun.color).norm() < 1e-3);
+            assert_eq!(sun.irradiance(&world, &bottom, T_MIN, 0.0), Vec3::ZERO);
+        }
+
+        // sharp shadow under a blocker
+        let blocker: HittableList = vec![Arc::new(Sphere::new(vec3(0.0, 2.0, 0.0), 0.5, ground))];
+        assert_eq!(sun.irradiance(&blocker, &top, T_MIN, 0.0), Vec3::ZERO);
+
+        assert_eq!(
+            sun.radiance(vec3(0.0, 1.0, 0.0)),
+            sun.color / sun.solid_angle()
+        );
+        assert_eq!(sun.radiance(vec3(1.0, 1.0, 0.0)), Vec3::ZERO);
+
+        // a white diffuse floor reflects E / PI, the sun disk isn't counted twice
+        let floor: HittableList = vec![Arc::new(floor)];
+        let color = ray_color_sun(&from(1.0), &floor, 4, Vec3::ZERO, T_MIN, &sun);
+        assert!((color - sun.color / PI).norm() < 1e-3);
+
+        let config = Config {
+            width: 8,
+            height: 8,
+            samples: 4,
+            ..Config::default()
+        };
+        let camera = Camera::new(
+            40.0,
+            vec3(0.0, 5.0, 5.0),
+            Vec3::ZERO,
+            1.0,
+            0.0,
+            5.0,
+            0.0,
+            0.0,
+        );
+        let lit = render::render_with(
+            &floor,
+            &camera,
+            Color::BLACK,
+            &config,
+            &SunPathTracer { sun },
+        );
+        assert!(lit.iter().all(|c| c.r > 0));
+        let dark = render::render(&floor, &camera, Color::BLACK, &config);
+        assert!(dark.iter().all(|c| c.r == 0));
+    }
+
+    #[test]
+    fn test_sun_lights_media() {
+        // lit from behind the camera, the arbitrary normal of media hits
+        // faces away, and the fog is too thin toward the sun to shadow itself
+        let sun = DirectionalLight::new(vec3(-1.0, 0.0, 0.0), vec3(3.0, 3.0, 3.0), 0.01);
+        let phase: Arc<dyn Material> = Arc::new(Isotropic::from_color(Color::WHITE));
+        let boundary: Arc<dyn Hit> = Arc::new(Sphere::new(Vec3::ZERO, 1.0, phase.clone()));
+        let fog = ConstantMedium::new(boundary, 1e6, phase);
+
+        let r = ray(vec3(-2.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), 0.0);
+        let rec = fog.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!(rec.normal.dot(sun.direction) <= 0.0);
+        let world = HittableList::new();
+        assert_eq!(sun.irradiance(&world, &rec, T_MIN, 0.0), sun.color);
+
+        // lit fog seen through itself, at least the first scattering
+        let fog: HittableList = vec![Arc::new(fog)];
+        let color = ray_color_sun(&r, &fog, 1, Vec3::ZERO, T_MIN, &sun);
+        assert!((color - sun.color / (4.0 * PI)).norm() < 1e-9);
+    }
+}
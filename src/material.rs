@@ -1,11 +1,52 @@
-use super::{random, ray, Color, HitRecord, Point3, Ray, SolidColor, Texture, Vec3};
-use std::{fmt::Debug, sync::Arc};
+use super::{
+    random, ray, schlick, Color, HitRecord, HittableList, Point3, Ray, SolidColor, Texture, Vec3,
+};
+use std::{
+    fmt::Debug,
+    sync::{Arc, RwLock},
+};
 
 pub trait Material: Send + Sync + Debug {
     fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<(Color, Ray)>;
     fn emitted(&self, _u: f64, _v: f64, _point: &Point3) -> Vec3 {
         Color::BLACK.to_vec3()
     }
+
+    /// Emission at a hit, for materials that need more of it than its texture
+    /// coordinates and point, like the barycentric coordinates of `Wireframe`.
+    fn emitted_hit(&self, rec: &HitRecord) -> Vec3 {
+        self.emitted(rec.u, rec.v, &rec.point)
+    }
+
+    /// Whether the material scatters in a few sharp directions, like mirrors
+    /// and glass, rather than diffusely.
+    fn is_specular(&self) -> bool {
+        false
+    }
+
+    /// Whether the surface only catches shadows, see `ShadowCatcher`.
+    fn is_shadow_catcher(&self) -> bool {
+        false
+    }
+
+    /// Probability density of the scattering of a medium over the sphere of
+    /// directions, `cos` being the cosine of the angle between the incoming
+    /// and scattered directions. None for surfaces.
+    fn phase(&self, _cos: f64) -> Option<f64> {
+        None
+    }
+
+    /// The material that hits take in place of this one, for materials looked
+    /// up elsewhere like `MaterialHandle`.
+    fn resolve(&self) -> Option<Arc<dyn Material>> {
+        None
+    }
+}
+
+/// Material of the hit records of an object of material `mat`, looked up once
+/// per hit rather than at each of its uses.
+pub fn resolve(mat: &Arc<dyn Material>) -> Arc<dyn Material> {
+    mat.resolve().unwrap_or_else(|| mat.clone())
 }
 
 #[derive(Debug, Clone)]
@@ -27,6 +68,12 @@ impl Lambertian<SolidColor> {
     }
 }
 
+impl Default for Lambertian<SolidColor> {
+    fn default() -> Self {
+        Self::from_color(Color::LIGHT_GREY)
+    }
+}
+
 impl<T: Texture> Material for Lambertian<T> {
     fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
         let mut scatter_dir = rec.normal + Vec3::random_unit_sphere();
@@ -35,7 +82,7 @@ impl<T: Texture> Material for Lambertian<T> {
         }
         let scattered = ray(rec.point, scatter_dir, r.time);
 
-        Some((self.albedo.texel(rec.u, rec.v, &rec.point), scattered))
+        Some((self.albedo.texel_hit(rec), scattered))
     }
 }
 
@@ -51,9 +98,15 @@ impl Metal {
     }
 }
 
+impl Default for Metal {
+    fn default() -> Self {
+        Self::new(Color::LIGHT_GREY, 0.0)
+    }
+}
+
 impl Material for Metal {
     fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
-        let reflected = r.dir.reflect(rec.normal).normalize();
+        let reflected = r.reflect(rec.normal)?;
         let scattered = ray(
             rec.point,
             reflected + self.fuzz * Vec3::random_unit_sphere(),
@@ -66,6 +119,10 @@ impl Material for Metal {
             None
         }
     }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -79,30 +136,36 @@ impl Dielectric {
     }
 
     pub fn reflectance(cos: f64, ref_dix: f64) -> f64 {
-        let r0 = ((1.0 - ref_dix) / (1.0 + ref_dix)).powi(2);
-        r0 + (1.0 - r0) * (1.0 - cos).powi(5)
+        schlick(cos, ref_dix)
+    }
+}
+
+impl Default for Dielectric {
+    fn default() -> Self {
+        Self::new(1.5)
     }
 }
 
 impl Material for Dielectric {
     fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
         let eta1 = if rec.front_face { 1.0 } else { self.eta };
-        let unit_dir = r.dir.normalize();
-        let cos_theta = -unit_dir.dot(rec.normal).min(1.0);
-        let sin_theta = (1.0 - cos_theta.powi(2)).sqrt();
-
-        let will_reflect = random() < Self::reflectance(cos_theta, eta1 / self.eta);
+        let unit_dir = r.dir.try_normalize()?;
+        let (reflected, refracted, reflectance) =
+            unit_dir.reflect_refract_fresnel(rec.normal, eta1 / self.eta);
 
-        let direction = if (eta1 / self.eta) * sin_theta > 1.0 || will_reflect {
-            unit_dir.reflect(rec.normal)
-        } else {
-            unit_dir.refract(rec.normal, eta1, self.eta)
+        let direction = match refracted {
+            Some(refracted) if random() >= reflectance => refracted,
+            _ => reflected,
         };
 
         let scattered = ray(rec.point, direction, r.time);
 
         Some((Color::WHITE, scattered))
     }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -135,4 +198,496 @@ impl Material for DiffuseLight {
     fn emitted(&self, u: f64, v: f64, point: &Point3) -> Vec3 {
         self.emit.texel(u, v, point).to_vec3() * self.power
     }
+
+    fn emitted_hit(&self, rec: &HitRecord) -> Vec3 {
+        self.emit.texel_hit(rec).to_vec3() * self.power
+    }
+}
+
+/// Draws the edges of triangles over a base material, from the barycentric
+/// coordinates of their hits. Points closer than `width` (in barycentric
+/// units) to an edge show `edge_color` and don't scatter, hits of other
+/// objects than triangles show the base material.
+#[derive(Debug, Clone)]
+pub struct Wireframe {
+    pub base: Arc<dyn Material>,
+    pub edge_color: Color,
+    pub width: f64,
+}
+
+impl Wireframe {
+    pub fn new(base: Arc<dyn Material>, edge_color: Color, width: f64) -> Self {
+        Self {
+            base,
+            edge_color,
+            width,
+        }
+    }
+
+    pub fn is_edge(&self, u: f64, v: f64) -> bool {
+        u.min(v).min(1.0 - u - v) < self.width
+    }
+
+    fn is_edge_hit(&self, rec: &HitRecord) -> bool {
+        rec.barycentric.is_some_and(|(u, v)| self.is_edge(u, v))
+    }
+}
+
+impl Material for Wireframe {
+    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+        if self.is_edge_hit(rec) {
+            return None;
+        }
+
+        self.base.scatter(r, rec)
+    }
+
+    fn emitted(&self, u: f64, v: f64, point: &Point3) -> Vec3 {
+        self.base.emitted(u, v, point)
+    }
+
+    fn emitted_hit(&self, rec: &HitRecord) -> Vec3 {
+        if self.is_edge_hit(rec) {
+            return self.edge_color.to_vec3();
+        }
+
+        self.base.emitted_hit(rec)
+    }
+
+    fn is_specular(&self) -> bool {
+        self.base.is_specular()
+    }
+}
+
+/// Phase function of participating media scattering evenly in all directions.
+#[derive(Debug, Clone)]
+pub struct Isotropic<T: Texture> {
+    pub albedo: T,
+}
+
+impl<T: Texture> Isotropic<T> {
+    pub fn new(texture: T) -> Self {
+        Self { albedo: texture }
+    }
+}
+
+impl Isotropic<SolidColor> {
+    pub fn from_color(color: Color) -> Self {
+        Self {
+            albedo: SolidColor::new(color),
+        }
+    }
+}
+
+impl<T: Texture> Material for Isotropic<T> {
+    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+        let scattered = ray(rec.point, Vec3::random_unit_sphere(), r.time);
+        Some((self.albedo.texel_hit(rec), scattered))
+    }
+
+    fn phase(&self, _cos: f64) -> Option<f64> {
+        Some(1.0 / (4.0 * std::f64::consts::PI))
+    }
+}
+
+/// Phase function of media scattering preferably forward for `g` in (0, 1)
+/// or backward for `g` in (-1, 0), isotropic at 0 (Henyey-Greenstein).
+#[derive(Debug, Clone)]
+pub struct HenyeyGreenstein<T: Texture> {
+    pub albedo: T,
+    pub g: f64,
+}
+
+impl<T: Texture> HenyeyGreenstein<T> {
+    pub fn new(texture: T, g: f64) -> Self {
+        Self { albedo: texture, g }
+    }
+
+    /// Cosine of the angle between the incoming and scattered directions.
+    pub fn sample_cos(&self) -> f64 {
+        let (g, xi) = (self.g, random());
+        if g.abs() < 1e-3 {
+            return 1.0 - 2.0 * xi;
+        }
+
+        let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * xi);
+        ((1.0 + g * g - s * s) / (2.0 * g)).clamp(-1.0, 1.0)
+    }
+}
+
+impl HenyeyGreenstein<SolidColor> {
+    pub fn from_color(color: Color, g: f64) -> Self {
+        Self::new(SolidColor::new(color), g)
+    }
+}
+
+impl<T: Texture> Material for HenyeyGreenstein<T> {
+    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+        let forward = r.dir.try_normalize()?;
+        let (a, b) = forward.orthonormal_pair();
+        let cos = self.sample_cos();
+        let sin = (1.0 - cos * cos).sqrt();
+        let phi = 2.0 * std::f64::consts::PI * random();
+        let dir = cos * forward + sin * (phi.cos() * a + phi.sin() * b);
+
+        Some((self.albedo.texel_hit(rec), ray(rec.point, dir, r.time)))
+    }
+
+    fn phase(&self, cos: f64) -> Option<f64> {
+        let g = self.g;
+        let denominator = (1.0 + g * g - 2.0 * g * cos).powf(1.5);
+        Some((1.0 - g * g) / (4.0 * std::f64::consts::PI * denominator))
+    }
+}
+
+/// Switches between materials by hit position: `selector` gives the index
+/// in `materials` of the material at a point, clamped to the last one.
+pub struct PositionalMaterial {
+    pub selector: Box<dyn Fn(Point3) -> usize + Send + Sync>,
+    pub materials: Vec<Arc<dyn Material>>,
+}
+
+impl PositionalMaterial {
+    pub fn new(
+        selector: impl Fn(Point3) -> usize + Send + Sync + 'static,
+        materials: Vec<Arc<dyn Material>>,
+    ) -> Self {
+        assert!(!materials.is_empty(), "no material to select");
+        Self {
+            selector: Box::new(selector),
+            materials,
+        }
+    }
+
+    pub fn select(&self, point: Point3) -> &Arc<dyn Material> {
+        let i = (self.selector)(point).min(self.materials.len() - 1);
+        &self.materials[i]
+    }
+}
+
+impl Debug for PositionalMaterial {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PositionalMaterial")
+            .field("materials", &self.materials)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Material for PositionalMaterial {
+    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+        self.select(rec.point).scatter(r, rec)
+    }
+
+    fn emitted(&self, u: f64, v: f64, point: &Point3) -> Vec3 {
+        self.select(*point).emitted(u, v, point)
+    }
+
+    fn emitted_hit(&self, rec: &HitRecord) -> Vec3 {
+        self.select(rec.point).emitted_hit(rec)
+    }
+}
+
+/// Invisible surface letting rays through unchanged, whose occlusion from the
+/// lights is rendered apart by `render::shadow_alpha` to composite the shadows
+/// of the scene over a photo.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ShadowCatcher;
+
+impl Material for ShadowCatcher {
+    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+        Some((
+            Color::WHITE,
+            ray(rec.point, r.dir, r.time).with_kind(r.kind),
+        ))
+    }
+
+    fn is_shadow_catcher(&self) -> bool {
+        true
+    }
+}
+
+/// Side table of materials that can be replaced while the objects using them
+/// stay in place, e.g. to tweak a material from a UI and render again without
+/// rebuilding the scene. Objects take the `MaterialHandle` of their material
+/// as material, clones of the table share the same materials.
+#[derive(Debug, Clone, Default)]
+pub struct MaterialTable {
+    materials: Arc<RwLock<Vec<Arc<dyn Material>>>>,
+}
+
+impl MaterialTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a material, returning the handle to give the objects using it.
+    pub fn insert(&self, mat: Arc<dyn Material>) -> MaterialHandle {
+        let mut materials = self.materials.write().unwrap();
+        materials.push(mat);
+        MaterialHandle {
+            id: materials.len() - 1,
+            materials: self.materials.clone(),
+        }
+    }
+
+    pub fn get(&self, id: usize) -> Option<Arc<dyn Material>> {
+        self.materials.read().unwrap().get(id).cloned()
+    }
+
+    /// Replaces the material `id` for all the objects using its handle, from
+    /// their next hit on. Returns false if there is no such material.
+    pub fn update_material(&self, id: usize, mat: Arc<dyn Material>) -> bool {
+        match self.materials.write().unwrap().get_mut(id) {
+            Some(old) => {
+                *old = mat;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Material `id` of a `MaterialTable`, that objects resolve once per hit.
+#[derive(Debug, Clone)]
+pub struct MaterialHandle {
+    pub id: usize,
+    materials: Arc<RwLock<Vec<Arc<dyn Material>>>>,
+}
+
+impl MaterialHandle {
+    fn current(&self) -> Arc<dyn Material> {
+        self.materials.read().unwrap()[self.id].clone()
+    }
+}
+
+impl Material for MaterialHandle {
+    fn scatter(&self, r: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
+        self.current().scatter(r, rec)
+    }
+
+    fn emitted(&self, u: f64, v: f64, point: &Point3) -> Vec3 {
+        self.current().emitted(u, v, point)
+    }
+
+    fn emitted_hit(&self, rec: &HitRecord) -> Vec3 {
+        self.current().emitted_hit(rec)
+    }
+
+    fn is_specular(&self) -> bool {
+        self.current().is_specular()
+    }
+
+    fn is_shadow_catcher(&self) -> bool {
+        self.current().is_shadow_catcher()
+    }
+
+    fn phase(&self, cos: f64) -> Option<f64> {
+        self.current().phase(cos)
+    }
+
+    fn resolve(&self) -> Option<Arc<dyn Material>> {
+        Some(self.current())
+    }
+}
+
+/// Replacement of the materials of a scene whose objects use handles of a
+/// `MaterialTable`.
+pub trait UpdateMaterial {
+    /// Replaces the material of `handle` for all the objects using it, from
+    /// their next hit on.
+    fn update_material(&self, handle: &MaterialHandle, mat: Arc<dyn Material>);
+}
+
+impl UpdateMaterial for HittableList {
+    fn update_material(&self, handle: &MaterialHandle, mat: Arc<dyn Material>) {
+        handle.materials.write().unwrap()[handle.id] = mat;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_default_render() {
+        let config = Config::default();
+        assert_eq!((config.width, config.height), (800, 450));
+        assert_eq!((config.samples, config.depth), (100, 50));
+
+        let world: HittableList = vec![Arc::new(Sphere::new(
+            vec3(0.0, 0.0, -1.0),
+            0.5,
+            Arc::new(Lambertian::default()),
+        ))];
+        let config = Config {
+            width: 16,
+            height: 9,
+            samples: 4,
+            ..config
+        };
+        let camera = Camera::default();
+
+        let radiance = render::render_progressive_hdr(&world, &camera, Color::WHITE, &config).0;
+        assert!(radiance.iter().all(|v| !v.is_nan() && !v.is_infinite()));
+
+        let center = radiance[(4 * config.width + 8) as usize];
+        assert!(center.x < 1.0);
+        assert_eq!(radiance[0], Vec3::ONE);
+    }
+
+    #[test]
+    fn test_wireframe() {
+        let mat = Arc::new(Wireframe::new(
+            Arc::new(Metal::default()),
+            Color::CYAN,
+            0.05,
+        ));
+        let triangle = Triangle::new(
+            [
+                vec3(-1.0, -1.0, 0.0),
+                vec3(1.0, -1.0, 0.0),
+                vec3(0.0, 1.0, 0.0),
+            ],
+            mat.clone(),
+        );
+        // texture coordinates far from any edge don't move the edges
+        let mapped = triangle
+            .clone()
+            .with_uvs([(0.3, 0.3), (0.3, 0.3), (0.3, 0.3)]);
+
+        for triangle in [triangle, mapped].iter() {
+            let hit = |x: f64, y: f64| {
+                let r = ray(vec3(x, y, -1.0), vec3(0.0, 0.0, 1.0), 0.0);
+                (r, triangle.hit(&r, 0.001, f64::INFINITY).unwrap())
+            };
+
+            let (r, corner) = hit(0.98, -0.99);
+            assert!(mat.scatter(&r, &corner).is_none());
+            assert_eq!(mat.emitted_hit(&corner), Color::CYAN.to_vec3());
+
+            let (r, center) = hit(0.0, -1.0 / 3.0);
+            let (attenuation, scattered) = mat.scatter(&r, &center).unwrap();
+            let (base_attenuation, base_scattered) = Metal::default().scatter(&r, &center).unwrap();
+            assert_eq!(attenuation.to_vec3(), base_attenuation.to_vec3());
+            assert_eq!(scattered.dir, base_scattered.dir);
+            assert_eq!(mat.emitted_hit(&center), Vec3::ZERO);
+        }
+    }
+
+    #[test]
+    fn test_dielectric_absorbs_degenerate_ray() {
+        let rec = HitRecord {
+            point: Vec3::ZERO,
+            normal: vec3(0.0, 1.0, 0.0),
+            t: 0.0,
+            u: 0.0,
+            v: 0.0,
+            barycentric: None,
+            front_face: true,
+            mat: Arc::new(Dielectric::default()),
+            velocity: Vec3::ZERO,
+        };
+
+        let degenerate = ray(Vec3::ZERO, Vec3::ZERO, 0.0);
+        assert!(Dielectric::default().scatter(&degenerate, &rec).is_none());
+    }
+
+    #[test]
+    fn test_henyey_greenstein_scatters_forward() {
+        let r = ray(Vec3::ZERO, vec3(0.0, 0.0, 3.0), 0.0);
+        let rec = HitRecord {
+            point: Vec3::ZERO,
+            normal: vec3(1.0, 0.0, 0.0),
+            t: 0.0,
+            u: 0.0,
+            v: 0.0,
+            barycentric: None,
+            front_face: true,
+            mat: Arc::new(ShadowCatcher),
+            velocity: Vec3::ZERO,
+        };
+        let mean_cos = |g| {
+            let phase = HenyeyGreenstein::from_color(Color::WHITE, g);
+            let n = 10000;
+            let mut sum = 0.0;
+            for _ in 0..n {
+                let (_, scattered) = phase.scatter(&r, &rec).unwrap();
+                assert!((scattered.dir.norm() - 1.0).abs() < 1e-9);
+                sum += scattered.dir.z;
+            }
+            sum / n as f64
+        };
+
+        // the mean cosine of the scattering angle is g
+        assert!((mean_cos(0.7) - 0.7).abs() < 0.03);
+        assert!(mean_cos(0.0).abs() < 0.03);
+        assert!((mean_cos(-0.5) + 0.5).abs() < 0.03);
+    }
+
+    #[test]
+    fn test_positional_material() {
+        let diffuse: Arc<dyn Material> = Arc::new(Lambertian::default());
+        let light: Arc<dyn Material> = Arc::new(DiffuseLight::from_color(Color::WHITE, 2.0));
+        let mat = PositionalMaterial::new(|p| (p.y > 0.0) as usize, vec![diffuse, light]);
+
+        let sphere = Sphere::new(Vec3::ZERO, 1.0, Arc::new(mat));
+        let down = ray(vec3(0.0, 5.0, 0.0), vec3(0.0, -1.0, 0.0), 0.0);
+        let up = ray(vec3(0.0, -5.0, 0.0), vec3(0.0, 1.0, 0.0), 0.0);
+
+        // the light above y = 0 emits and absorbs, the diffuse below scatters
+        let top = sphere.hit(&down, 0.001, f64::INFINITY).unwrap();
+        assert_eq!(top.mat.emitted(top.u, top.v, &top.point), Vec3::ONE * 2.0);
+        assert!(top.mat.scatter(&down, &top).is_none());
+
+        let bottom = sphere.hit(&up, 0.001, f64::INFINITY).unwrap();
+        assert_eq!(
+            bottom.mat.emitted(bottom.u, bottom.v, &bottom.point),
+            Vec3::ZERO
+        );
+        assert!(bottom.mat.scatter(&up, &bottom).is_some());
+    }
+
+    #[test]
+    fn test_update_material() {
+        let table = MaterialTable::new();
+        let red = table.insert(Arc::new(Lambertian::from_color(Color::RED)));
+        let world: HittableList = vec![Arc::new(Sphere::new(
+            Vec3::ZERO,
+            1.5,
+            Arc::new(red.clone()),
+        ))];
+        let camera = Camera::new(
+            40.0,
+            vec3(0.0, 0.0, -5.0),
+            Vec3::ZERO,
+            1.0,
+            0.0,
+            5.0,
+            0.0,
+            0.0,
+        );
+        let config = Config::builder()
+            .width(8)
+            .height(8)
+            .samples(4)
+            .build()
+            .unwrap();
+        let center = |image: Vec<Color>| image[4 * 8 + 4];
+
+        let before = center(render::render(&world, &camera, Color::WHITE, &config));
+        assert!(before.r > 0 && before.g == 0);
+
+        world.update_material(&red, Arc::new(Lambertian::from_color(Color::GREEN)));
+        let after = center(render::render(&world, &camera, Color::WHITE, &config));
+        assert!(after.r == 0 && after.g > 0);
+
+        // hits take the material itself, not the handle
+        let hit = ray(vec3(0.0, 0.0, -5.0), vec3(0.0, 0.0, 1.0), 0.0);
+        let rec = world.hit(&hit, 0.001, f64::INFINITY).unwrap();
+        assert!(Arc::ptr_eq(&rec.mat, &table.get(red.id).unwrap()));
+
+        assert!(table.update_material(red.id, Arc::new(Lambertian::default())));
+        assert!(!table.update_material(1, Arc::new(Lambertian::default())));
+    }
 }
@@ -1,7 +1,7 @@
-use std::ops::{Add, AddAssign, Div, DivAssign, Index, Mul, MulAssign, Neg, Sub, SubAssign};
-use super::{random, random_range};
+use std::ops::{Add, AddAssign, Div, DivAssign, Index, Mul, MulAssign, Neg, Range, Sub, SubAssign};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct Vec3 {
     pub x: f64,
     pub y: f64,
@@ -20,30 +20,70 @@ impl Vec3 {
         z: 1.0,
     };
 
-    pub fn new(x: f64, y: f64, z: f64) -> Self {
+    #[inline]
+    #[must_use]
+    pub const fn new(x: f64, y: f64, z: f64) -> Self {
         Self { x, y, z }
     }
 
+    /// Scalar product, `*` being the component-wise product.
+    #[inline]
+    #[must_use]
     pub fn dot(&self, other: Self) -> f64 {
         self.x * other.x + self.y * other.y + self.z * other.z
     }
 
+    #[inline]
+    #[must_use]
     pub fn squared_norm(self) -> f64 {
         self.dot(self)
     }
 
+    #[inline]
+    #[must_use]
     pub fn norm(self) -> f64 {
         self.squared_norm().sqrt()
     }
 
+    /// Unit vector of the same direction. The vector itself is left as is,
+    /// unlike with `normalized`, so a discarded result is an error:
+    ///
+    /// ```compile_fail
+    /// #![deny(unused_must_use)]
+    /// let v = raytracing_weekend::vec3(3.0, 0.0, 4.0);
+    /// v.normalize();
+    /// ```
+    #[inline]
+    #[must_use]
     pub fn normalize(self) -> Self {
         self / self.norm()
     }
 
+    #[inline]
+    #[must_use]
+    pub fn try_normalize(self) -> Option<Self> {
+        let norm = self.norm();
+        if norm < f64::EPSILON {
+            None
+        } else {
+            Some(self / norm)
+        }
+    }
+
+    #[inline]
     pub fn normalized(&mut self) {
         *self = self.normalize();
     }
 
+    /// Rec. 709 luminance of a linear RGB color.
+    #[inline]
+    #[must_use]
+    pub fn luminance(self) -> f64 {
+        0.2126 * self.x + 0.7152 * self.y + 0.0722 * self.z
+    }
+
+    #[inline]
+    #[must_use]
     pub fn cross(&self, other: Self) -> Self {
         Self {
             x: self.y * other.z - self.z * other.y,
@@ -52,6 +92,42 @@ impl Vec3 {
         }
     }
 
+    /// Component-wise product, same as `self * other`, e.g. to filter a color.
+    #[inline]
+    #[must_use]
+    pub fn hadamard(self, other: Self) -> Self {
+        self * other
+    }
+
+    /// Component-wise minimum of the vectors, None for an empty slice.
+    pub fn min_of(slice: &[Self]) -> Option<Self> {
+        let (first, rest) = slice.split_first()?;
+        Some(rest.iter().fold(*first, |m, v| vec3(m.x.min(v.x), m.y.min(v.y), m.z.min(v.z))))
+    }
+
+    /// Component-wise maximum of the vectors, None for an empty slice.
+    pub fn max_of(slice: &[Self]) -> Option<Self> {
+        let (first, rest) = slice.split_first()?;
+        Some(rest.iter().fold(*first, |m, v| vec3(m.x.max(v.x), m.y.max(v.y), m.z.max(v.z))))
+    }
+
+    /// Two unit vectors orthogonal to each other and to `self`, built without
+    /// branches (Duff et al., "Building an Orthonormal Basis, Revisited").
+    #[must_use]
+    pub fn orthonormal_pair(self) -> (Self, Self) {
+        let n = self.normalize();
+        let sign = 1f64.copysign(n.z);
+        let a = -1.0 / (sign + n.z);
+        let b = n.x * n.y * a;
+
+        (
+            Self::new(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x),
+            Self::new(b, sign + n.y * n.y * a, -n.y),
+        )
+    }
+
+    #[inline]
+    #[must_use]
     pub fn abs(self) -> Self {
         Self {
             x: self.x.abs(),
@@ -60,6 +136,8 @@ impl Vec3 {
         }
     }
 
+    #[inline]
+    #[must_use]
     pub fn clamp(self, min: f64, max: f64) -> Self {
         Self {
             x: self.x.clamp(min, max),
@@ -68,10 +146,22 @@ impl Vec3 {
         }
     }
 
+    #[inline]
+    #[must_use]
     pub fn reflect(&self, n: Self) -> Self {
         *self - 2.0 * n * self.dot(n)
     }
 
+    /// Mirror image of the point across the plane through `plane_point` of
+    /// normal `plane_normal`, which needs not be unit length.
+    #[must_use]
+    pub fn reflect_about_plane(self, plane_point: Point3, plane_normal: Vec3) -> Self {
+        let n = plane_normal.normalize();
+        self - 2.0 * (self - plane_point).dot(n) * n
+    }
+
+    #[inline]
+    #[must_use]
     pub fn refract(self, n: Self, eta1: f64, eta2: f64) -> Self {
         let cos_theta = -self.dot(n).min(1.0);
         let perp = (eta1 / eta2) * (self + cos_theta * n);
@@ -79,30 +169,128 @@ impl Vec3 {
         perp + parallel
     }
 
-    pub fn random(min: f64, max: f64) -> Self {
+    /// Reflected and refracted directions of a ray of unit direction `self`
+    /// hitting a surface of normal `n`, `ratio` being the ratio of the indices
+    /// of refraction, with the Schlick approximation of the fraction of light
+    /// reflected. There is no refracted direction and everything is reflected
+    /// on total internal reflection.
+    #[must_use]
+    pub fn reflect_refract_fresnel(self, n: Self, ratio: f64) -> (Self, Option<Self>, f64) {
+        let cos_theta = -self.dot(n).min(1.0);
+        let reflected = self.reflect(n);
+        if ratio * (1.0 - cos_theta * cos_theta).sqrt() > 1.0 {
+            return (reflected, None, 1.0);
+        }
+
+        (reflected, Some(self.refract(n, ratio, 1.0)), schlick(cos_theta, ratio))
+    }
+
+    /// Rounds each component to the nearest multiple of `grid`.
+    #[must_use]
+    pub fn snap(self, grid: f64) -> Self {
         Self {
-            x: random_range(min , max),
-            y: random_range(min , max),
-            z: random_range(min , max),
+            x: (self.x / grid).round() * grid,
+            y: (self.y / grid).round() * grid,
+            z: (self.z / grid).round() * grid,
         }
     }
 
+    /// Index in a flat x-major array of the voxel of a `dims` grid, unit sized
+    /// and starting at the origin, containing the point. None outside the grid
+    /// or for NaN coordinates.
+    #[must_use]
+    pub fn to_voxel_index(self, dims: (usize, usize, usize)) -> Option<usize> {
+        let (x, y, z) = (self.x.floor(), self.y.floor(), self.z.floor());
+        if self.is_nan() || x < 0.0 || y < 0.0 || z < 0.0 {
+            return None;
+        }
+
+        let (x, y, z) = (x as usize, y as usize, z as usize);
+        if x >= dims.0 || y >= dims.1 || z >= dims.2 {
+            return None;
+        }
+        Some(x + dims.0 * (y + dims.1 * z))
+    }
+
+    /// Lowest corner of the voxel at `index` of a `dims` grid, inverse of
+    /// `to_voxel_index`.
+    #[must_use]
+    pub fn from_voxel_index(index: usize, dims: (usize, usize, usize)) -> Option<Self> {
+        if index >= dims.0 * dims.1 * dims.2 {
+            return None;
+        }
+
+        let (x, rest) = (index % dims.0, index / dims.0);
+        let (y, z) = (rest % dims.1, rest / dims.1);
+        Some(Self::new(x as f64, y as f64, z as f64))
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn lerp(self, other: Self, t: f64) -> Self {
+        self + (other - self) * t
+    }
+
+    /// Spherical interpolation between two unit vectors. Opposite vectors
+    /// have no single arc between them, they turn around an arbitrary axis.
+    #[must_use]
+    pub fn slerp(self, other: Self, t: f64) -> Self {
+        let theta = self.dot(other).clamp(-1.0, 1.0).acos();
+        if theta < 1e-6 {
+            return self.lerp(other, t).normalize();
+        }
+        if std::f64::consts::PI - theta < 1e-6 {
+            let (side, _) = self.orthonormal_pair();
+            let angle = std::f64::consts::PI * t;
+            return angle.cos() * self + angle.sin() * side;
+        }
+
+        let sin = theta.sin();
+        ((1.0 - t) * theta).sin() / sin * self + (t * theta).sin() / sin * other
+    }
+
+    pub fn random(min: f64, max: f64) -> Self {
+        Self::random_in(min..max, &fastrand::Rng::new())
+    }
+
     pub fn random_unit_sphere() -> Self {
-        Self::random(-1.0, 1.0).normalize()
+        Self::random_unit_sphere_with(&fastrand::Rng::new())
     }
 
     pub fn random_unit_disk() -> Self {
-        let r = random();
-        let theta = random_range(0.0, std::f64::consts::PI * 2.0);
+        Self::random_unit_disk_with(&fastrand::Rng::new())
+    }
+
+    /// Same as `random` drawing from `rng`, for reproducible sequences. The
+    /// versions without a generator draw from a generator seeded by the thread
+    /// local one, itself seeded by `fastrand::seed`.
+    pub fn random_in(range: Range<f64>, rng: &fastrand::Rng) -> Self {
+        let component = || range.start + (range.end - range.start) * rng.f64();
+        Self {
+            x: component(),
+            y: component(),
+            z: component(),
+        }
+    }
+
+    pub fn random_unit_sphere_with(rng: &fastrand::Rng) -> Self {
+        Self::random_in(-1.0..1.0, rng).normalize()
+    }
+
+    pub fn random_unit_disk_with(rng: &fastrand::Rng) -> Self {
+        let r = rng.f64();
+        let theta = std::f64::consts::PI * 2.0 * rng.f64();
         r * vec3(theta.cos(), theta.sin(), 0.0)
     }
 
+    #[must_use]
     pub fn is_close(&self, v: Self) -> bool {
         (self.x - v.x).abs() < f64::EPSILON
             && (self.y - v.y).abs() < f64::EPSILON
             && (self.z - v.z).abs() < f64::EPSILON
     }
 
+    #[must_use]
     pub fn is_close_scalar(&self, v: f64) -> Self {
         Self {
             x: if (self.x - v).abs() < f64::EPSILON { 1.0 } else { 0.0 },
@@ -111,10 +299,14 @@ impl Vec3 {
         }
     }
 
+    #[inline]
+    #[must_use]
     pub fn is_nan(&self) -> bool {
         self.x.is_nan() || self.y.is_nan() || self.z.is_nan()
     }
 
+    #[inline]
+    #[must_use]
     pub fn is_infinite(&self) -> bool {
         self.x.is_infinite() || self.y.is_infinite() || self.z.is_infinite()
     }
@@ -123,6 +315,7 @@ impl Vec3 {
 impl Add for Vec3 {
     type Output = Self;
 
+    #[inline]
     fn add(self, rhs: Self) -> Self::Output {
         Self {
             x: self.x + rhs.x,
@@ -133,6 +326,7 @@ impl Add for Vec3 {
 }
 
 impl AddAssign for Vec3 {
+    #[inline]
     fn add_assign(&mut self, rhs: Self) {
         *self = *self + rhs;
     }
@@ -141,6 +335,7 @@ impl AddAssign for Vec3 {
 impl Sub for Vec3 {
     type Output = Self;
 
+    #[inline]
     fn sub(self, rhs: Self) -> Self::Output {
         Self {
             x: self.x - rhs.x,
@@ -151,6 +346,7 @@ impl Sub for Vec3 {
 }
 
 impl SubAssign for Vec3 {
+    #[inline]
     fn sub_assign(&mut self, rhs: Self) {
         *self = *self - rhs;
     }
@@ -159,6 +355,7 @@ impl SubAssign for Vec3 {
 impl Mul<f64> for Vec3 {
     type Output = Self;
 
+    #[inline]
     fn mul(self, rhs: f64) -> Self::Output {
         Self {
             x: self.x * rhs,
@@ -169,14 +366,17 @@ impl Mul<f64> for Vec3 {
 }
 
 impl MulAssign<f64> for Vec3 {
+    #[inline]
     fn mul_assign(&mut self, rhs: f64) {
         *self = *self * rhs;
     }
 }
 
+/// Component-wise (Hadamard) product, see `Vec3::dot` and `Vec3::cross` for the others.
 impl Mul for Vec3 {
     type Output = Self;
 
+    #[inline]
     fn mul(self, rhs: Self) -> Self::Output {
         Self {
             x: self.x * rhs.x,
@@ -189,6 +389,7 @@ impl Mul for Vec3 {
 impl Div<f64> for Vec3 {
     type Output = Self;
 
+    #[inline]
     fn div(self, rhs: f64) -> Self::Output {
         Self {
             x: self.x / rhs,
@@ -199,6 +400,7 @@ impl Div<f64> for Vec3 {
 }
 
 impl DivAssign<f64> for Vec3 {
+    #[inline]
     fn div_assign(&mut self, rhs: f64) {
         *self = *self / rhs;
     }
@@ -207,6 +409,7 @@ impl DivAssign<f64> for Vec3 {
 impl Neg for Vec3 {
     type Output = Self;
 
+    #[inline]
     fn neg(self) -> Self::Output {
         Self {
             x: -self.x,
@@ -219,6 +422,7 @@ impl Neg for Vec3 {
 impl Mul<Vec3> for f64 {
     type Output = Vec3;
 
+    #[inline]
     fn mul(self, rhs: Vec3) -> Self::Output {
         rhs.mul(self)
     }
@@ -227,6 +431,7 @@ impl Mul<Vec3> for f64 {
 impl Div<Vec3> for f64 {
     type Output = Vec3;
 
+    #[inline]
     fn div(self, rhs: Vec3) -> Self::Output {
         Self::Output {
             x: self / rhs.x,
@@ -236,9 +441,66 @@ impl Div<Vec3> for f64 {
     }
 }
 
+#[cfg(feature = "mint")]
+impl From<mint::Vector3<f64>> for Vec3 {
+    fn from(v: mint::Vector3<f64>) -> Self {
+        Self::new(v.x, v.y, v.z)
+    }
+}
+
+#[cfg(feature = "mint")]
+impl From<Vec3> for mint::Vector3<f64> {
+    fn from(v: Vec3) -> Self {
+        Self { x: v.x, y: v.y, z: v.z }
+    }
+}
+
+#[cfg(feature = "mint")]
+impl From<mint::Point3<f64>> for Vec3 {
+    fn from(p: mint::Point3<f64>) -> Self {
+        Self::new(p.x, p.y, p.z)
+    }
+}
+
+#[cfg(feature = "mint")]
+impl From<Vec3> for mint::Point3<f64> {
+    fn from(v: Vec3) -> Self {
+        Self { x: v.x, y: v.y, z: v.z }
+    }
+}
+
+#[cfg(feature = "approx")]
+impl approx::AbsDiffEq for Vec3 {
+    type Epsilon = f64;
+
+    fn default_epsilon() -> f64 {
+        f64::default_epsilon()
+    }
+
+    fn abs_diff_eq(&self, other: &Self, epsilon: f64) -> bool {
+        self.x.abs_diff_eq(&other.x, epsilon)
+            && self.y.abs_diff_eq(&other.y, epsilon)
+            && self.z.abs_diff_eq(&other.z, epsilon)
+    }
+}
+
+#[cfg(feature = "approx")]
+impl approx::RelativeEq for Vec3 {
+    fn default_max_relative() -> f64 {
+        f64::default_max_relative()
+    }
+
+    fn relative_eq(&self, other: &Self, epsilon: f64, max_relative: f64) -> bool {
+        self.x.relative_eq(&other.x, epsilon, max_relative)
+            && self.y.relative_eq(&other.y, epsilon, max_relative)
+            && self.z.relative_eq(&other.z, epsilon, max_relative)
+    }
+}
+
 impl Index<usize> for Vec3 {
     type Output = f64;
 
+    #[inline]
     fn index(&self, index: usize) -> &Self::Output {
         match index {
             0 => &self.x,
@@ -249,33 +511,325 @@ impl Index<usize> for Vec3 {
     }
 }
 
-pub fn vec3(x: f64, y: f64, z: f64) -> Vec3 {
+#[inline]
+#[must_use]
+pub const fn vec3(x: f64, y: f64, z: f64) -> Vec3 {
     Vec3 { x, y, z }
 }
 
 pub type Point3 = Vec3;
 
+/// What a ray is traced for, so that objects can be hidden from some rays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RayKind {
+    /// ray leaving the camera
+    Camera,
+    /// ray testing whether a light is occluded
+    Shadow,
+    /// ray scattered by a surface
+    Reflection,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Ray {
     pub origin: Point3,
     pub dir: Vec3,
     pub time: f64,
+    pub kind: RayKind,
 }
 
 impl Ray {
+    #[inline]
+    #[must_use]
     pub fn new(o: Point3, d: Vec3, t: f64) -> Self {
-        Self { origin: o, dir: d, time: t, }
+        Self { origin: o, dir: d, time: t, kind: RayKind::Reflection }
     }
 
+    #[inline]
+    #[must_use]
+    pub fn with_kind(self, kind: RayKind) -> Self {
+        Self { kind, ..self }
+    }
+
+    #[inline]
+    #[must_use]
     pub fn at(&self, t: f64) -> Point3 {
         self.origin + self.dir * t
     }
+
+    /// Unit direction of the ray reflected about the normal `n`, None for a
+    /// zero direction.
+    #[inline]
+    #[must_use]
+    pub fn reflect(&self, n: Vec3) -> Option<Vec3> {
+        Some(self.dir.try_normalize()?.reflect(n))
+    }
 }
 
+#[inline]
+#[must_use]
 pub fn ray(origin: Point3, dir: Vec3, time: f64) -> Ray {
-    Ray { origin, dir, time }
+    Ray { origin, dir, time, kind: RayKind::Reflection }
+}
+
+/// Weights of `a`, `b` and `c` whose combination is the projection of `p` on
+/// the plane of the triangle.
+pub fn barycentric(p: Point3, a: Point3, b: Point3, c: Point3) -> (f64, f64, f64) {
+    let (e0, e1, e2) = (b - a, c - a, p - a);
+    let (d00, d01, d11) = (e0.dot(e0), e0.dot(e1), e1.dot(e1));
+    let (d20, d21) = (e2.dot(e0), e2.dot(e1));
+    let denom = d00 * d11 - d01 * d01;
+
+    let v = (d11 * d20 - d01 * d21) / denom;
+    let w = (d00 * d21 - d01 * d20) / denom;
+    (1.0 - v - w, v, w)
+}
+
+/// Schlick approximation of the fraction of light reflected at an angle of
+/// cosine `cos` to the normal, `ratio` being the ratio of the indices of
+/// refraction.
+#[must_use]
+pub fn schlick(cos: f64, ratio: f64) -> f64 {
+    let r0 = ((1.0 - ratio) / (1.0 + ratio)).powi(2);
+    r0 + (1.0 - r0) * (1.0 - cos).powi(5)
 }
 
+#[inline]
+#[must_use]
 pub fn is_campled(v: f64, min: f64, max: f64) -> bool {
     v >= min && v <= max
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_try_normalize() {
+        assert_eq!(Vec3::ZERO.try_normalize(), None);
+
+        let v = vec3(0.0, 1.0, 0.0);
+        assert_eq!(v.try_normalize(), Some(v));
+    }
+
+    #[test]
+    fn test_slerp() {
+        let (x, y) = (vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0));
+        assert!((x.slerp(y, 0.5) - vec3(1.0, 1.0, 0.0).normalize()).norm() < 1e-12);
+
+        for &t in &[0.0, 0.25, 0.5, 1.0] {
+            let v = x.slerp(-x, t);
+            assert!(v.x.is_finite() && (v.norm() - 1.0).abs() < 1e-12);
+            assert!((v.dot(x) - (t * PI).cos()).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_orthonormal_pair() {
+        for n in [
+            vec3(0.0, 0.0, 1.0),
+            vec3(0.0, 0.0, -1.0),
+            vec3(1.0, 2.0, 3.0),
+            vec3(-0.3, 0.1, -2.0),
+        ]
+        .iter()
+        {
+            let (b1, b2) = n.orthonormal_pair();
+
+            assert!((b1.norm() - 1.0).abs() < 1e-12 && (b2.norm() - 1.0).abs() < 1e-12);
+            assert!(b1.dot(*n).abs() < 1e-12 && b2.dot(*n).abs() < 1e-12);
+            assert!(b1.dot(b2).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_ray_reflect() {
+        let r = ray(Vec3::ZERO, vec3(1.0, -2.0, 0.5), 0.0);
+        let n = vec3(0.0, 1.0, 0.0);
+        let unit_dir = r.dir.normalize();
+
+        assert!(r.reflect(n).unwrap().is_close(unit_dir.reflect(n)));
+        assert_eq!(ray(Vec3::ZERO, Vec3::ZERO, 0.0).reflect(n), None);
+    }
+
+    #[test]
+    fn test_snap() {
+        assert_eq!(vec3(0.3, 0.1, 0.6).snap(0.25), vec3(0.25, 0.0, 0.5));
+        assert_eq!(vec3(-0.3, 1.9, 0.0).snap(1.0), vec3(0.0, 2.0, 0.0));
+    }
+
+    #[test]
+    fn test_barycentric() {
+        let (a, b, c) = (vec3(0.0, 0.0, 0.0), vec3(2.0, 0.0, 1.0), vec3(0.0, 3.0, 0.0));
+
+        let (u, v, w) = barycentric((a + b + c) / 3.0, a, b, c);
+        assert!((u - 1.0 / 3.0).abs() < 1e-12);
+        assert!((v - 1.0 / 3.0).abs() < 1e-12);
+        assert!((w - 1.0 / 3.0).abs() < 1e-12);
+
+        let (u, v, w) = barycentric(a, a, b, c);
+        assert!((u - 1.0).abs() < 1e-12 && v.abs() < 1e-12 && w.abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_voxel_index() {
+        let dims = (4, 3, 2);
+
+        assert_eq!(Vec3::ZERO.to_voxel_index(dims), Some(0));
+        assert_eq!(vec3(3.5, 2.5, 1.5).to_voxel_index(dims), Some(23));
+        assert_eq!(vec3(4.0, 0.0, 0.0).to_voxel_index(dims), None);
+        assert_eq!(vec3(0.0, -0.1, 0.0).to_voxel_index(dims), None);
+        assert_eq!(vec3(f64::NAN, 0.0, 0.0).to_voxel_index(dims), None);
+        assert_eq!(vec3(0.0, 0.0, f64::INFINITY).to_voxel_index(dims), None);
+
+        assert_eq!(Vec3::from_voxel_index(23, dims), Some(vec3(3.0, 2.0, 1.0)));
+        assert_eq!(Vec3::from_voxel_index(24, dims), None);
+        for i in 0..24 {
+            assert_eq!(Vec3::from_voxel_index(i, dims).unwrap().to_voxel_index(dims), Some(i));
+        }
+    }
+
+    #[test]
+    fn test_reflect_refract_fresnel() {
+        let dir = vec3(1.0, -1.0, 0.0).normalize();
+        let n = vec3(0.0, 1.0, 0.0);
+        let close = |a: Vec3, b: Vec3| (a - b).norm() < 1e-4;
+
+        let (reflected, refracted, reflectance) = dir.reflect_refract_fresnel(n, 1.0 / 1.5);
+        assert!(close(reflected, vec3(1.0, 1.0, 0.0).normalize()));
+        assert!(close(refracted.unwrap(), vec3(0.4714, -0.8819, 0.0)));
+        assert!((reflectance - 0.04206).abs() < 1e-5);
+
+        let (reflected, refracted, reflectance) = dir.reflect_refract_fresnel(n, 1.5);
+        assert!(close(reflected, vec3(1.0, 1.0, 0.0).normalize()));
+        assert!(refracted.is_none());
+        assert_eq!(reflectance, 1.0);
+    }
+
+    #[test]
+    fn test_hadamard() {
+        let (a, b) = (vec3(1.0, -2.0, 3.0), vec3(4.0, 5.0, -0.5));
+        assert_eq!(a.hadamard(b), a * b);
+        assert_eq!(a.hadamard(b), vec3(4.0, -10.0, -1.5));
+    }
+
+    #[test]
+    fn test_min_max_of() {
+        let v = [vec3(1.0, -2.0, 3.0), vec3(-4.0, 5.0, 0.5), vec3(2.0, 0.0, -6.0)];
+        assert_eq!(Vec3::min_of(&v), Some(vec3(-4.0, -2.0, -6.0)));
+        assert_eq!(Vec3::max_of(&v), Some(vec3(2.0, 5.0, 3.0)));
+        assert_eq!(Vec3::min_of(&v[..1]), Some(v[0]));
+        assert_eq!(Vec3::min_of(&[]), None);
+        assert_eq!(Vec3::max_of(&[]), None);
+    }
+
+    #[test]
+    #[cfg(feature = "mint")]
+    fn test_mint_round_trip() {
+        let v = vec3(1.5, -2.0, 3.25);
+
+        let vector: mint::Vector3<f64> = v.into();
+        assert_eq!((vector.x, vector.y, vector.z), (1.5, -2.0, 3.25));
+        assert_eq!(Vec3::from(vector), v);
+
+        let point: mint::Point3<f64> = v.into();
+        assert_eq!(Vec3::from(point), v);
+        assert_eq!(Vec3::from(mint::Point3::from([0.0, 1.0, 2.0])), vec3(0.0, 1.0, 2.0));
+    }
+
+    #[test]
+    #[cfg(feature = "approx")]
+    fn test_approx_assertions() {
+        let v = vec3(0.1, 0.2, 0.3);
+        let w = vec3(0.1 + 0.2 - 0.2, 0.2 * 3.0 / 3.0, 0.1 + 0.2);
+        approx::assert_abs_diff_eq!(v, w);
+        approx::assert_relative_eq!(v * 1e6, w * 1e6, max_relative = 1e-12);
+        approx::assert_abs_diff_ne!(v, v + vec3(0.0, 0.0, 1e-3));
+
+        let red = Color::from_rgb(255, 0, 0);
+        approx::assert_abs_diff_eq!(red, Color::from_rgb(254, 1, 0), epsilon = 1.0 / 255.0);
+        approx::assert_relative_ne!(red, Color::from_rgb(250, 0, 0));
+    }
+
+    #[test]
+    fn test_reflect_about_plane() {
+        let p = vec3(1.5, 2.0, -3.0);
+        assert_eq!(p.reflect_about_plane(Vec3::ZERO, vec3(0.0, 1.0, 0.0)), vec3(1.5, -2.0, -3.0));
+        let below = vec3(0.0, -3.0, 0.0);
+        assert_eq!(p.reflect_about_plane(vec3(4.0, 0.0, 7.0), below), vec3(1.5, -2.0, -3.0));
+
+        let (q, n) = (vec3(1.0, 1.0, 0.0), vec3(1.0, 1.0, 1.0));
+        let image = p.reflect_about_plane(q, n);
+        assert!(((image - q).dot(n) + (p - q).dot(n)).abs() < 1e-12);
+        assert!((image.reflect_about_plane(q, n) - p).norm() < 1e-12);
+    }
+
+    #[test]
+    fn test_const_vectors() {
+        const EYE: Point3 = vec3(278.0, 278.0, -800.0);
+        const UP: Vec3 = Vec3::new(0.0, 1.0, 0.0);
+        const ALBEDO: Color = Color::from_rgb(200, 65, 150);
+
+        assert_eq!(EYE + UP, vec3(278.0, 279.0, -800.0));
+        assert_eq!(ALBEDO, Color::PINK);
+    }
+
+    #[test]
+    fn test_seeded_random_vectors() {
+        let draw = |seed| {
+            let rng = fastrand::Rng::with_seed(seed);
+            (0..16)
+                .map(|_| {
+                    (
+                        Vec3::random_in(-2.0..3.0, &rng),
+                        Vec3::random_unit_sphere_with(&rng),
+                        Vec3::random_unit_disk_with(&rng),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let sequence = draw(7);
+        assert_eq!(sequence, draw(7));
+        assert_ne!(sequence, draw(8));
+        for (v, s, d) in sequence {
+            assert!([v.x, v.y, v.z].iter().all(|c| (-2.0..3.0).contains(c)));
+            assert!((s.norm() - 1.0).abs() < 1e-12);
+            assert!(d.z == 0.0 && d.norm() < 1.0);
+        }
+    }
+
+    // unit vectors drawn by the property tests
+    fn unit() -> impl Strategy<Value = Vec3> {
+        (-1.0..1.0f64, -1.0..1.0f64, -1.0..1.0f64)
+            .prop_filter("too short to normalize", |(x, y, z)| vec3(*x, *y, *z).norm() > 0.1)
+            .prop_map(|(x, y, z)| vec3(x, y, z).normalize())
+    }
+
+    proptest! {
+        #[test]
+        fn prop_reflect_flips_normal_component(d in unit(), n in unit()) {
+            let r = d.reflect(n);
+            let (d_normal, r_normal) = (d.dot(n), r.dot(n));
+            prop_assert!((r_normal + d_normal).abs() < 1e-9);
+            prop_assert!(((r - r_normal * n) - (d - d_normal * n)).norm() < 1e-9);
+            prop_assert!((r.norm() - 1.0).abs() < 1e-9);
+        }
+
+        #[test]
+        fn prop_refract_scales_tangent_component(d in unit(), n in unit(), ratio in 0.3..3.0f64) {
+            // the normal faces the incoming ray, like hit records
+            let n = if d.dot(n) > 0.0 { -n } else { n };
+            let tangent = |v: Vec3| v - v.dot(n) * n;
+            match d.reflect_refract_fresnel(n, ratio).1 {
+                Some(t) => {
+                    prop_assert!((tangent(t) - ratio * tangent(d)).norm() < 1e-9);
+                    prop_assert!(t.dot(n) <= 0.0);
+                    prop_assert!((t.norm() - 1.0).abs() < 1e-9);
+                }
+                None => prop_assert!(ratio * tangent(d).norm() > 1.0),
+            }
+        }
+    }
+}
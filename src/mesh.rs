@@ -0,0 +1,516 @@
+use super::{
+    vec3, Color, Dielectric, HittableList, ImageTexture, Lambertian, Material, Metal, Point3,
+    Triangle, Vec3,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{Error, ErrorKind, Result},
+    path::Path,
+    sync::Arc,
+};
+
+/// Loads the faces of a Wavefront `.obj` file as triangles, polygons being
+/// split in fans around their first vertex. Faces take the material of their
+/// `usemtl` group from the `mtllib` files, or `mat` when they have none.
+pub fn load_obj(path: &Path, mat: Arc<dyn Material>) -> Result<HittableList> {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    parse_obj(&fs::read_to_string(path)?, dir, mat)
+}
+
+/// Same as `load_obj` with `dir` the directory of the material libraries.
+pub fn parse_obj(src: &str, dir: &Path, mat: Arc<dyn Material>) -> Result<HittableList> {
+    ObjMesh::parse(src)?.build(dir, mat)
+}
+
+/// Same as `load_obj`, keeping the parsed faces in a binary `.objcache` file
+/// next to `path` that is read instead of the `.obj` while it is the newer.
+/// An unreadable cache is replaced by parsing the `.obj` again, and failing to
+/// write it only warns.
+#[cfg(feature = "bincode")]
+pub fn load_obj_cached(path: &Path, mat: Arc<dyn Material>) -> Result<HittableList> {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let cache = path.with_extension("objcache");
+    let modified = |p: &Path| fs::metadata(p).and_then(|m| m.modified());
+
+    let cached = match (modified(&cache), modified(path)) {
+        (Ok(cached), Ok(source)) if cached >= source => {
+            match fs::read(&cache).and_then(|bytes| ObjMesh::from_bincode(&bytes)) {
+                Ok(mesh) => Some(mesh),
+                Err(e) => {
+                    eprintln!("warning: ignoring {}: {}", cache.display(), e);
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+    let mesh = match cached {
+        Some(mesh) => mesh,
+        None => {
+            let mesh = ObjMesh::parse(&fs::read_to_string(path)?)?;
+            if let Err(e) = fs::write(&cache, mesh.to_bincode()) {
+                eprintln!("warning: cannot write {}: {}", cache.display(), e);
+            }
+            mesh
+        }
+    };
+    mesh.build(dir, mat)
+}
+
+/// Faces of a Wavefront `.obj` file split in triangles, with the names of
+/// their materials and of the libraries defining them. Materials are only
+/// resolved by `build`, so that the mesh can be serialized.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ObjMesh {
+    pub triangles: Vec<ObjTriangle>,
+    pub materials: Vec<String>,
+    pub mtllibs: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObjTriangle {
+    pub vertices: [Point3; 3],
+    pub normals: Option<[Vec3; 3]>,
+    pub uvs: Option<[(f64, f64); 3]>,
+    /// index in `ObjMesh::materials` of its `usemtl` group
+    pub material: Option<usize>,
+}
+
+impl ObjMesh {
+    pub fn parse(src: &str) -> Result<Self> {
+        let mut vertices: Vec<Point3> = Vec::new();
+        let mut normals: Vec<Vec3> = Vec::new();
+        let mut uvs: Vec<(f64, f64)> = Vec::new();
+        let mut mesh = Self::default();
+        let mut current = None;
+
+        for (n, line) in src.lines().enumerate() {
+            let mut tokens = line.split_whitespace();
+            match tokens.next() {
+                Some("v") => vertices.push(point(tokens, n, line)?),
+                Some("vn") => normals.push(point(tokens, n, line)?.normalize()),
+                Some("vt") => {
+                    let c = tokens
+                        .take(2)
+                        .map(|t| t.parse::<f64>().map_err(|_| invalid(n, line)))
+                        .collect::<Result<Vec<_>>>()?;
+                    uvs.push((
+                        *c.first().ok_or_else(|| invalid(n, line))?,
+                        *c.get(1).unwrap_or(&0.0),
+                    ));
+                }
+                Some("f") => {
+                    let counts = [vertices.len(), uvs.len(), normals.len()];
+                    let face = tokens
+                        .map(|t| face_vertex(t, counts).ok_or_else(|| invalid(n, line)))
+                        .collect::<Result<Vec<_>>>()?;
+                    if face.len() < 3 {
+                        return Err(invalid(n, line));
+                    }
+                    for i in 1..face.len() - 1 {
+                        let corners = [face[0], face[i], face[i + 1]];
+                        let normals = match corners.map(|c| c.2) {
+                            [Some(n0), Some(n1), Some(n2)] => {
+                                Some([normals[n0], normals[n1], normals[n2]])
+                            }
+                            _ => None,
+                        };
+                        let uvs = match corners.map(|c| c.1) {
+                            [Some(t0), Some(t1), Some(t2)] => Some([uvs[t0], uvs[t1], uvs[t2]]),
+                            _ => None,
+                        };
+                        mesh.triangles.push(ObjTriangle {
+                            vertices: corners.map(|c| vertices[c.0]),
+                            normals,
+                            uvs,
+                            material: current,
+                        });
+                    }
+                }
+                Some("mtllib") => mesh.mtllibs.extend(tokens.map(String::from)),
+                Some("usemtl") => {
+                    let name = tokens.next().unwrap_or("");
+                    current = match mesh.materials.iter().position(|m| m == name) {
+                        Some(i) => Some(i),
+                        None => {
+                            mesh.materials.push(name.to_string());
+                            Some(mesh.materials.len() - 1)
+                        }
+                    };
+                }
+                _ => (),
+            }
+        }
+
+        Ok(mesh)
+    }
+
+    /// Triangles of the mesh, with the materials of the libraries in `dir`,
+    /// or `mat` for the faces that have none.
+    pub fn build(&self, dir: &Path, mat: Arc<dyn Material>) -> Result<HittableList> {
+        let mut library = HashMap::new();
+        for file in &self.mtllibs {
+            library.extend(load_mtl(&dir.join(file))?);
+        }
+        let materials: Vec<Arc<dyn Material>> = self
+            .materials
+            .iter()
+            .map(|name| match library.get(name) {
+                Some(m) => Arc::clone(m),
+                None => {
+                    eprintln!("warning: unknown material '{}', using the default", name);
+                    mat.clone()
+                }
+            })
+            .collect();
+
+        let mut world = HittableList::new();
+        for t in &self.triangles {
+            let mat = t
+                .material
+                .map_or_else(|| mat.clone(), |i| materials[i].clone());
+            let mut triangle = Triangle::new(t.vertices, mat);
+            if let Some(normals) = t.normals {
+                triangle = triangle.with_normals(normals);
+            }
+            if let Some(uvs) = t.uvs {
+                triangle = triangle.with_uvs(uvs);
+            }
+            world.push(Arc::new(triangle));
+        }
+
+        Ok(world)
+    }
+
+    #[cfg(feature = "bincode")]
+    pub fn to_bincode(&self) -> Vec<u8> {
+        bincode::serialize(self).unwrap()
+    }
+
+    #[cfg(feature = "bincode")]
+    pub fn from_bincode(bytes: &[u8]) -> Result<Self> {
+        bincode::deserialize(bytes).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+}
+
+/// Loads the materials of a Wavefront `.mtl` file by name.
+pub fn load_mtl(path: &Path) -> Result<HashMap<String, Arc<dyn Material>>> {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    parse_mtl(&fs::read_to_string(path)?, dir)
+}
+
+/// Converts the materials of a `.mtl` file, `dir` being the directory of its
+/// texture maps. Refractive materials (`illum` 4, 6, 7 or 9, or transparent
+/// through `d` or `Tr` without `illum`) become dielectrics of index `Ni`,
+/// metallic ones (`illum` 3, 5 or 8, or a black untextured `Kd`) metals of
+/// color `Ks` whose fuzz decreases with `Ns`, and the others lambertians of
+/// color `Kd` or texture `map_Kd`.
+pub fn parse_mtl(src: &str, dir: &Path) -> Result<HashMap<String, Arc<dyn Material>>> {
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlParams)> = None;
+
+    for (n, line) in src.lines().enumerate() {
+        let mut tokens = line.split_whitespace();
+        let key = match tokens.next() {
+            Some(k) if !k.starts_with('#') => k,
+            _ => continue,
+        };
+        if key == "newmtl" {
+            if let Some((name, params)) = current.take() {
+                materials.insert(name, params.to_material(dir));
+            }
+            current = Some((tokens.collect::<Vec<_>>().join(" "), MtlParams::default()));
+            continue;
+        }
+
+        let params = match current.as_mut() {
+            Some((_, params)) => params,
+            None => return Err(invalid(n, line)),
+        };
+        let values = tokens
+            .clone()
+            .map(|t| t.parse::<f64>().map_err(|_| invalid(n, line)));
+        match key {
+            "Kd" => params.kd = color(values)?,
+            "Ks" => params.ks = color(values)?,
+            "Ns" => params.ns = scalar(values)?,
+            "Ni" => params.ni = scalar(values)?,
+            "d" => params.d = scalar(values)?,
+            "Tr" => params.d = 1.0 - scalar(values)?,
+            "illum" => params.illum = Some(scalar(values)? as u32),
+            "map_Kd" => params.map_kd = tokens.last().map(String::from),
+            _ => eprintln!("warning: ignored mtl parameter '{}'", key),
+        }
+    }
+
+    if let Some((name, params)) = current {
+        materials.insert(name, params.to_material(dir));
+    }
+
+    Ok(materials)
+}
+
+#[derive(Debug, Clone)]
+struct MtlParams {
+    kd: Color,
+    ks: Color,
+    ns: f64,
+    ni: f64,
+    d: f64,
+    illum: Option<u32>,
+    map_kd: Option<String>,
+}
+
+impl Default for MtlParams {
+    fn default() -> Self {
+        Self {
+            kd: Color::LIGHT_GREY,
+            ks: Color::BLACK,
+            ns: 0.0,
+            ni: 1.5,
+            d: 1.0,
+            illum: None,
+            map_kd: None,
+        }
+    }
+}
+
+impl MtlParams {
+    fn to_material(&self, dir: &Path) -> Arc<dyn Material> {
+        let refractive = self
+            .illum
+            .map_or(self.d < 1.0, |illum| [4, 6, 7, 9].contains(&illum));
+        if refractive {
+            return Arc::new(Dielectric::new(self.ni));
+        }
+        let metallic = self.illum.is_some_and(|illum| [3, 5, 8].contains(&illum))
+            || (self.kd.to_vec3() == Vec3::ZERO && self.map_kd.is_none());
+        if metallic && self.ks.to_vec3() != Vec3::ZERO {
+            let fuzz = (1.0 - self.ns / 1000.0).clamp(0.0, 1.0);
+            return Arc::new(Metal::new(self.ks, fuzz));
+        }
+        if let Some(map) = &self.map_kd {
+            match image::open(dir.join(map)) {
+                Ok(img) => return Arc::new(Lambertian::new(ImageTexture::new(img.into_rgba8()))),
+                Err(e) => eprintln!("warning: texture '{}' not loaded: {}", map, e),
+            }
+        }
+
+        Arc::new(Lambertian::from_color(self.kd))
+    }
+}
+
+fn color(mut values: impl Iterator<Item = Result<f64>>) -> Result<Color> {
+    let r = values.next().unwrap_or(Ok(0.0))?;
+    let g = values.next().unwrap_or(Ok(r))?;
+    let b = values.next().unwrap_or(Ok(r))?;
+    Ok(Color::from_floats([r, g, b, 1.0]))
+}
+
+fn scalar(mut values: impl Iterator<Item = Result<f64>>) -> Result<f64> {
+    values.next().unwrap_or(Ok(0.0))
+}
+
+fn point<'a>(tokens: impl Iterator<Item = &'a str>, n: usize, line: &str) -> Result<Point3> {
+    let c = tokens
+        .take(3)
+        .map(|t| t.parse::<f64>().map_err(|_| invalid(n, line)))
+        .collect::<Result<Vec<_>>>()?;
+    if c.len() < 3 {
+        return Err(invalid(n, line));
+    }
+
+    Ok(vec3(c[0], c[1], c[2]))
+}
+
+// indices of the position, texture coordinates and normal of a face vertex
+// `v`, `v/vt`, `v//vn` or `v/vt/vn`, given the number of each defined so far
+fn face_vertex(token: &str, counts: [usize; 3]) -> Option<(usize, Option<usize>, Option<usize>)> {
+    let mut indices = [None; 3];
+    for (k, part) in token.split('/').enumerate() {
+        if k > 2 {
+            return None;
+        }
+        if part.is_empty() {
+            continue;
+        }
+
+        let i: i64 = part.parse().ok()?;
+        let i = if i < 0 { counts[k] as i64 + i } else { i - 1 };
+        if i < 0 || i >= counts[k] as i64 {
+            return None;
+        }
+        indices[k] = Some(i as usize);
+    }
+
+    Some((indices[0]?, indices[1], indices[2]))
+}
+
+fn invalid(n: usize, line: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("line {}: invalid obj statement '{}'", n + 1, line),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    const CUBE_OBJ: &str = "
+        v 0 0 0
+        v 1 0 0
+        v 1 1 0
+        v 0 1 0
+        v 0 0 1
+        v 1 0 1
+        v 1 1 1
+        v 0 1 1
+        f 1 2 3 4
+        f 5 8 7 6
+        f 1 5 6 2
+        f 2 6 7 3
+        f 3 7 8 4
+        f 5 1 4 8
+    ";
+
+    #[test]
+    fn test_obj_scene_frames_mesh() {
+        let path = std::env::temp_dir().join("raytracing_weekend_cube.obj");
+        std::fs::write(&path, CUBE_OBJ).unwrap();
+
+        let (world, camera, _) = scenes::from_obj(&path, 16.0 / 9.0).unwrap();
+
+        assert_eq!(world.len(), 12);
+        for i in 0..8 {
+            let corner = vec3((i & 1) as f64, (i >> 1 & 1) as f64, (i >> 2) as f64);
+            let (s, t) = camera.project(&corner).unwrap();
+            assert!(s > 0.0 && s < 1.0 && t > 0.0 && t < 1.0);
+        }
+    }
+
+    #[test]
+    fn test_parse_mtl() {
+        let src = "
+            # two materials
+            newmtl red
+            Kd 0.8 0.1 0.1
+            Ks 0.5 0.5 0.5
+            illum 2
+
+            newmtl tinted
+            Kd 0.8 0.1 0.1
+            d 0.5
+            illum 2
+
+            newmtl chrome
+            Kd 0 0 0
+            Ks 0.9 0.9 0.9
+            Ns 1000
+
+            newmtl mirror
+            Kd 0.5 0.5 0.5
+            Ks 0.9 0.9 0.9
+            Ns 1000
+            illum 3
+
+            newmtl glass
+            Ni 1.33
+            d 0.2
+
+            newmtl window
+            Ni 1.33
+            illum 7
+        ";
+        let materials = mesh::parse_mtl(src, std::path::Path::new("")).unwrap();
+        assert_eq!(materials.len(), 6);
+
+        // scatter of a ray coming down at 45 degrees on a floor
+        let scatter = |name: &str| {
+            let mat = materials[name].clone();
+            let rec = HitRecord {
+                point: Vec3::ZERO,
+                normal: vec3(0.0, 1.0, 0.0),
+                t: 1.0,
+                u: 0.0,
+                v: 0.0,
+                barycentric: None,
+                front_face: true,
+                mat: mat.clone(),
+                velocity: Vec3::ZERO,
+            };
+            let r = ray(vec3(-1.0, 1.0, 0.0), vec3(1.0, -1.0, 0.0), 0.0);
+            let (attenuation, scattered) = mat.scatter(&r, &rec).unwrap();
+            (mat.is_specular(), attenuation, scattered.dir)
+        };
+        let color = |r, g, b| Color::from_floats([r, g, b, 1.0]);
+        let mirrored = |dir: Vec3| (dir - vec3(1.0, 1.0, 0.0).normalize()).norm() < 1e-9;
+
+        // a small specular highlight keeps the diffuse color
+        for name in ["red", "tinted"].iter() {
+            let (specular, attenuation, _) = scatter(name);
+            assert!(!specular);
+            assert_eq!(attenuation, color(0.8, 0.1, 0.1));
+        }
+        for name in ["chrome", "mirror"].iter() {
+            let (specular, attenuation, dir) = scatter(name);
+            assert!(specular && mirrored(dir));
+            assert_eq!(attenuation, color(0.9, 0.9, 0.9));
+        }
+        for name in ["glass", "window"].iter() {
+            let refracted = (0..1000).map(|_| scatter(name)).find(|s| s.2.y < 0.0);
+            let (specular, attenuation, dir) = refracted.unwrap();
+            assert!(specular && attenuation == Color::WHITE);
+            // Snell's law from air into an index of 1.33
+            let sin = dir.x / dir.norm();
+            assert!((sin - 0.5f64.sqrt() / 1.33).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "bincode")]
+    fn test_bincode_cache_round_trip() {
+        let mesh = mesh::ObjMesh::parse(CUBE_OBJ).unwrap();
+        let cached = mesh::ObjMesh::from_bincode(&mesh.to_bincode()).unwrap();
+        assert_eq!(cached, mesh);
+
+        let mat: std::sync::Arc<dyn Material> = std::sync::Arc::new(Lambertian::default());
+        let dir = std::path::Path::new("");
+        let world = mesh.build(dir, mat.clone()).unwrap();
+        let reloaded = cached.build(dir, mat.clone()).unwrap();
+        let center = vec3(0.5, 0.5, 0.5);
+        for _ in 0..100 {
+            let r = ray(
+                center + 3.0 * Vec3::random_unit_sphere(),
+                Vec3::random_unit_sphere(),
+                0.0,
+            );
+            let hit = |w: &HittableList| {
+                w.hit(&r, 0.001, f64::INFINITY)
+                    .map(|rec| (rec.t, rec.normal))
+            };
+            assert_eq!(hit(&reloaded), hit(&world));
+        }
+
+        let path = std::env::temp_dir().join("raytracing_weekend_cached_cube.obj");
+        std::fs::write(&path, CUBE_OBJ).unwrap();
+        let _ = std::fs::remove_file(path.with_extension("objcache"));
+        assert_eq!(mesh::load_obj_cached(&path, mat.clone()).unwrap().len(), 12);
+        assert!(path.with_extension("objcache").exists());
+        assert_eq!(mesh::load_obj_cached(&path, mat.clone()).unwrap().len(), 12);
+
+        // a corrupt cache is parsed again and replaced
+        std::fs::write(path.with_extension("objcache"), b"not a mesh").unwrap();
+        assert_eq!(mesh::load_obj_cached(&path, mat.clone()).unwrap().len(), 12);
+        let bytes = std::fs::read(path.with_extension("objcache")).unwrap();
+        assert_eq!(mesh::ObjMesh::from_bincode(&bytes).unwrap(), mesh);
+
+        // a cache that can be neither read nor written
+        let path = std::env::temp_dir().join("raytracing_weekend_uncached_cube.obj");
+        std::fs::write(&path, CUBE_OBJ).unwrap();
+        let _ = std::fs::create_dir(path.with_extension("objcache"));
+        assert_eq!(mesh::load_obj_cached(&path, mat).unwrap().len(), 12);
+    }
+}
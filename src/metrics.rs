@@ -0,0 +1,112 @@
+use super::Vec3;
+
+/// Mean squared error between two images, averaged over the color channels.
+pub fn mse(a: &[Vec3], b: &[Vec3]) -> f64 {
+    assert_eq!(a.len(), b.len(), "images of different sizes");
+    if a.is_empty() {
+        return 0.0;
+    }
+
+    let total: f64 = a.iter().zip(b).map(|(a, b)| (*a - *b).squared_norm()).sum();
+    total / (3 * a.len()) as f64
+}
+
+/// Structural similarity of the luminance of two images, the mean SSIM of
+/// their 8x8 windows (partial at the borders), 1 for identical images.
+/// Values are expected in [0, 1], like linear radiance before tone mapping.
+pub fn ssim(a: &[Vec3], b: &[Vec3], width: u32, height: u32) -> f64 {
+    assert_eq!(a.len(), b.len(), "images of different sizes");
+    const WINDOW: u32 = 8;
+    const C1: f64 = 0.01 * 0.01;
+    const C2: f64 = 0.03 * 0.03;
+
+    let mut total = 0.0;
+    let mut windows = 0;
+    for wy in (0..height).step_by(WINDOW as usize) {
+        for wx in (0..width).step_by(WINDOW as usize) {
+            let pixels: Vec<(f64, f64)> = (wy..(wy + WINDOW).min(height))
+                .flat_map(|y| (wx..(wx + WINDOW).min(width)).map(move |x| (y * width + x) as usize))
+                .map(|i| (a[i].luminance(), b[i].luminance()))
+                .collect();
+            let n = pixels.len() as f64;
+            let (mean_a, mean_b) = pixels
+                .iter()
+                .fold((0.0, 0.0), |(sa, sb), (pa, pb)| (sa + pa / n, sb + pb / n));
+            let (var_a, var_b, covar) =
+                pixels
+                    .iter()
+                    .fold((0.0, 0.0, 0.0), |(va, vb, c), (pa, pb)| {
+                        let (da, db) = (pa - mean_a, pb - mean_b);
+                        (va + da * da / n, vb + db * db / n, c + da * db / n)
+                    });
+
+            total += (2.0 * mean_a * mean_b + C1) * (2.0 * covar + C2)
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+            windows += 1;
+        }
+    }
+
+    if windows == 0 {
+        1.0
+    } else {
+        total / windows as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_more_samples_get_closer_to_reference() {
+        let world: HittableList = vec![
+            Arc::new(Sphere::new(
+                Vec3::ZERO,
+                1.0,
+                Arc::new(Lambertian::from_color(Color::LIGHT_GREY)),
+            )),
+            Arc::new(Sphere::new(
+                vec3(0.0, -101.0, 0.0),
+                100.0,
+                Arc::new(Lambertian::from_color(Color::GREY)),
+            )),
+        ];
+        let camera = Camera::new(
+            40.0,
+            vec3(0.0, 0.0, -5.0),
+            Vec3::ZERO,
+            1.0,
+            0.0,
+            5.0,
+            0.0,
+            0.0,
+        );
+        let config = |samples| Config {
+            width: 16,
+            height: 16,
+            aspect_ratio: 1.0,
+            samples,
+            depth: 8,
+            ..Config::default()
+        };
+        let render = |samples, seed| {
+            render::render_partial(&world, &camera, Color::WHITE, &config(samples), seed, 0).0
+        };
+
+        let reference = render(1024, 1);
+        let (low, high) = (render(4, 2), render(128, 3));
+
+        assert_eq!(metrics::mse(&reference, &reference), 0.0);
+        assert!((metrics::ssim(&reference, &reference, 16, 16) - 1.0).abs() < 1e-12);
+        // 128 samples are close to the reference, and closer than 4
+        let (mse_low, mse_high) = (
+            metrics::mse(&low, &reference),
+            metrics::mse(&high, &reference),
+        );
+        assert!(mse_high < 1e-3 && mse_high < mse_low);
+        let ssim_low = metrics::ssim(&low, &reference, 16, 16);
+        let ssim_high = metrics::ssim(&high, &reference, 16, 16);
+        assert!(ssim_high > 0.99 && ssim_high > ssim_low);
+    }
+}
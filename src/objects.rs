@@ -1,9 +1,51 @@
-use super::{is_campled, random_u32, vec3, Material, Point3, Ray, Vec3, PI};
-use std::{cmp::Ordering, fmt::Debug, sync::Arc};
+use super::{
+    barycentric, is_campled, random, random_range, random_u32, ray, resolve, vec3, Camera,
+    Material, Point3, Ray, RayKind, Texture, Vec3, PI,
+};
+use std::{
+    cmp::Ordering,
+    fmt::Debug,
+    sync::{Arc, OnceLock},
+};
 
 pub trait Hit: Send + Sync + Debug {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
     fn bounding_box(&self, t1: f64, t2: f64) -> Option<AABB>;
+
+    fn bounding_sphere(&self) -> Option<(Point3, f64)> {
+        let bbox = self.bounding_box(0.0, 0.0)?;
+        Some((
+            (bbox.min + bbox.max) / 2.0,
+            (bbox.max - bbox.min).norm() / 2.0,
+        ))
+    }
+
+    /// Solid angle density of sampling `dir` from `origin` with `random_toward`.
+    fn pdf_value(&self, _origin: Point3, _dir: Vec3) -> f64 {
+        0.0
+    }
+
+    /// Random direction from `origin` toward the object.
+    fn random_toward(&self, _origin: Point3) -> Vec3 {
+        vec3(1.0, 0.0, 0.0)
+    }
+
+    /// The object as a sphere, for `SphereList::from_list`.
+    fn as_sphere(&self) -> Option<&Sphere> {
+        None
+    }
+}
+
+// density of a direction toward a flat surface of the given area
+fn area_pdf(rec: Option<HitRecord>, dir: Vec3, area: f64) -> f64 {
+    match rec {
+        Some(rec) => {
+            let distance_squared = rec.t * rec.t * dir.squared_norm();
+            let cosine = (dir.dot(rec.normal) / dir.norm()).abs();
+            distance_squared / (cosine * area)
+        }
+        None => 0.0,
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -13,8 +55,12 @@ pub struct HitRecord {
     pub t: f64,
     pub u: f64,
     pub v: f64,
+    /// Barycentric coordinates of triangle hits, kept apart from the texture
+    /// coordinates `u` and `v`.
+    pub barycentric: Option<(f64, f64)>,
     pub front_face: bool,
     pub mat: Arc<dyn Material>,
+    pub velocity: Vec3,
 }
 
 impl HitRecord {
@@ -28,6 +74,63 @@ impl HitRecord {
     }
 }
 
+/// Observer of the traversal of a group of objects, e.g. to count the
+/// bounding box and primitive tests of a ray. `()` observes nothing, and
+/// compiles down to the plain traversal.
+pub trait Probe {
+    /// Test of the bounding box of the node `id` of a `BVTree`.
+    fn node(&mut self, _id: usize, _hit: bool) {}
+
+    /// Test of an object of a `HittableList` or leaf of a `BVTree`.
+    fn primitive(&mut self, _object: &dyn Hit, _hit: bool) {}
+
+    /// Whether the traversal should stop and return the closest hit found so far.
+    fn exhausted(&self) -> bool {
+        false
+    }
+}
+
+impl Probe for () {}
+
+/// Probe capping the number of bounding box and primitive tests of a ray.
+/// Once the budget is spent the traversal returns the closest hit found so
+/// far, which may not be the closest one, to bound the time of a frame.
+#[derive(Debug, Clone, Copy)]
+pub struct TestBudget {
+    pub remaining: u32,
+}
+
+impl TestBudget {
+    pub fn new(tests: u32) -> Self {
+        Self { remaining: tests }
+    }
+}
+
+impl Probe for TestBudget {
+    fn node(&mut self, _id: usize, _hit: bool) {
+        self.remaining = self.remaining.saturating_sub(1);
+    }
+
+    fn primitive(&mut self, _object: &dyn Hit, _hit: bool) {
+        self.remaining = self.remaining.saturating_sub(1);
+    }
+
+    fn exhausted(&self) -> bool {
+        self.remaining == 0
+    }
+}
+
+/// Groups whose traversal can be observed with a `Probe`.
+pub trait ProbedHit: Hit {
+    fn hit_probed<P: Probe>(
+        &self,
+        r: &Ray,
+        t_min: f64,
+        t_max: f64,
+        probe: &mut P,
+    ) -> Option<HitRecord>;
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct AABB {
     min: Point3,
@@ -39,7 +142,28 @@ impl AABB {
         Self { min, max }
     }
 
-    pub fn hit(&self, r: &Ray, mut tmin: f64, mut tmax: f64) -> bool {
+    /// Inverted infinite box, the identity of `merge`.
+    pub fn empty() -> Self {
+        Self::new(f64::INFINITY * Vec3::ONE, f64::NEG_INFINITY * Vec3::ONE)
+    }
+
+    /// Smallest box enclosing both boxes.
+    pub fn merge(self, other: Self) -> Self {
+        Self::surrounding_box(&self, &other)
+    }
+
+    /// Box padded by `delta` on each side.
+    pub fn expand(self, delta: f64) -> Self {
+        Self::new(self.min - delta * Vec3::ONE, self.max + delta * Vec3::ONE)
+    }
+
+    pub fn hit(&self, r: &Ray, tmin: f64, tmax: f64) -> bool {
+        matches!(self.intersect_interval(r, tmin, tmax), Some((_, exit)) if exit >= 0.0)
+    }
+
+    /// Ray parameters at which the ray enters and leaves the box, clipped to
+    /// [tmin, tmax], or None if it misses the box in that range.
+    pub fn intersect_interval(&self, r: &Ray, mut tmin: f64, mut tmax: f64) -> Option<(f64, f64)> {
         let inv_d = 1.0 / r.dir;
         let t1 = (self.min - r.origin) * inv_d;
         let t2 = (self.max - r.origin) * inv_d;
@@ -49,7 +173,11 @@ impl AABB {
             tmax = t1[i].max(t2[i]).min(tmax);
         }
 
-        tmin <= tmax && tmax >= 0.0
+        if tmin <= tmax {
+            Some((tmin, tmax))
+        } else {
+            None
+        }
     }
 
     pub fn get_normal(&self, point: &Point3) -> Vec3 {
@@ -65,6 +193,26 @@ impl AABB {
         Vec3::ZERO
     }
 
+    /// Smallest box containing the points, None if there are none.
+    pub fn from_points(points: &[Point3]) -> Option<Self> {
+        Some(Self::new(Vec3::min_of(points)?, Vec3::max_of(points)?))
+    }
+
+    /// The 8 corners of the box.
+    pub fn corners(&self) -> [Point3; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            vec3(a.x, a.y, a.z),
+            vec3(b.x, a.y, a.z),
+            vec3(a.x, b.y, a.z),
+            vec3(b.x, b.y, a.z),
+            vec3(a.x, a.y, b.z),
+            vec3(b.x, a.y, b.z),
+            vec3(a.x, b.y, b.z),
+            vec3(b.x, b.y, b.z),
+        ]
+    }
+
     pub fn surrounding_box(b0: &Self, b1: &Self) -> Self {
         let min = vec3(
             b0.min.x.min(b1.min.x),
@@ -83,6 +231,9 @@ impl AABB {
 
 type NodeID = usize;
 
+// objects under which the subtrees of a BVTree are built on the current thread
+const PARALLEL_BUILD_SIZE: usize = 1024;
+
 #[derive(Debug, Clone)]
 struct BVNode {
     left: Option<NodeID>,
@@ -99,115 +250,199 @@ pub struct BVTree {
 }
 
 impl BVTree {
-    pub fn new(mut l: HittableList) -> Self {
-        let mut tree = Self {
-            nodes: Vec::with_capacity(2 * l.len() - 1),
-            root_id: 0,
-            objects_count: l.len(),
-        };
+    /// Builds the tree of the objects, its subtrees in parallel. Panics if
+    /// there is no object.
+    pub fn new(l: HittableList) -> Self {
+        Self::build(l, true)
+    }
+
+    /// Same tree as `new`, built on the current thread.
+    pub fn new_serial(l: HittableList) -> Self {
+        Self::build(l, false)
+    }
+
+    /// Leaf objects of the tree, which may be replaced by moved versions of
+    /// themselves before a `refit`.
+    pub fn objects_mut(&mut self) -> impl Iterator<Item = &mut Arc<dyn Hit>> {
+        self.nodes
+            .iter_mut()
+            .filter_map(|node| node.hittable.as_mut())
+    }
 
-        tree.root_id = tree.build(&mut l);
-        tree
+    /// Recomputes the boxes of the nodes from their objects, keeping the tree
+    /// structure. Cheap, but the tree degrades when objects move far apart.
+    pub fn refit(&mut self) {
+        // children are always stored before their parent
+        for id in 0..self.nodes.len() {
+            let node = &self.nodes[id];
+            let bbox = match (&node.hittable, node.left, node.right) {
+                (Some(hittable), _, _) => hittable.bounding_box(0.0, 0.0).unwrap(),
+                (None, Some(left), Some(right)) => {
+                    self.nodes[left].bbox.merge(self.nodes[right].bbox)
+                }
+                _ => continue,
+            };
+            self.nodes[id].bbox = bbox;
+        }
+    }
+
+    /// Builds the tree again from its objects, for large changes.
+    pub fn rebuild(&mut self) {
+        let objects: HittableList = self
+            .nodes
+            .iter()
+            .filter_map(|n| n.hittable.clone())
+            .collect();
+        *self = Self::new(objects);
+    }
+
+    fn build(mut l: HittableList, parallel: bool) -> Self {
+        assert!(!l.is_empty(), "no object in bounding volume tree");
+        let nodes = Self::build_nodes(&mut l, parallel);
+        Self {
+            root_id: nodes.len() - 1,
+            nodes,
+            objects_count: l.len(),
+        }
     }
 
-    fn build(&mut self, l: &mut [Arc<dyn Hit>]) -> NodeID {
-        let (left, right): (NodeID, NodeID);
+    // nodes of the subtree of `l`, children before their parent so the root
+    // is the last one. Subtrees are split along the longest axis of their box
+    // so that the tree only depends on the order of `l`.
+    fn build_nodes(l: &mut [Arc<dyn Hit>], parallel: bool) -> Vec<BVNode> {
         if l.len() == 1 {
-            return self.new_leaf(&l[0]);
-        } else if l.len() == 2 {
-            left = self.new_leaf(&l[0]);
-            right = self.new_leaf(&l[1]);
-        } else {
-            let axis = random_u32(0..3);
+            return vec![BVNode {
+                left: None,
+                right: None,
+                bbox: l[0].bounding_box(0.0, 0.0).unwrap(),
+                hittable: Some(l[0].clone()),
+            }];
+        }
+
+        if l.len() > 2 {
+            let bbox = l.iter().fold(AABB::empty(), |bbox, object| {
+                bbox.merge(object.bounding_box(0.0, 0.0).unwrap())
+            });
+            let size = bbox.max - bbox.min;
+            let axis = if size.x >= size.y && size.x >= size.z {
+                0
+            } else if size.y >= size.z {
+                1
+            } else {
+                2
+            };
             l.sort_by(|a, b| {
                 Self::compare_boxes(
                     &a.bounding_box(0.0, 0.0).unwrap(),
                     &b.bounding_box(0.0, 0.0).unwrap(),
-                    axis as usize,
+                    axis,
                 )
             });
-            let (left_hits, right_hits) = l.split_at_mut(l.len() / 2);
-            left = self.build(left_hits);
-            right = self.build(right_hits);
         }
 
-        self.new_node(
-            left,
-            right,
-            AABB::surrounding_box(&self.nodes[left].bbox, &self.nodes[right].bbox),
-        )
-    }
-
-    fn new_leaf(&mut self, hittable: &Arc<dyn Hit>) -> NodeID {
-        self.nodes.push(BVNode {
-            left: None,
-            right: None,
-            bbox: hittable.bounding_box(0.0, 0.0).unwrap(),
-            hittable: Some(hittable.clone()),
-        });
-
-        self.nodes.len() - 1
-    }
+        let parallel = parallel && l.len() >= PARALLEL_BUILD_SIZE;
+        let (left, right) = l.split_at_mut(l.len() / 2);
+        let (mut nodes, right) = if parallel {
+            rayon::join(
+                || Self::build_nodes(left, true),
+                || Self::build_nodes(right, true),
+            )
+        } else {
+            (
+                Self::build_nodes(left, false),
+                Self::build_nodes(right, false),
+            )
+        };
 
-    fn new_node(&mut self, left: NodeID, right: NodeID, bbox: AABB) -> NodeID {
-        self.nodes.push(BVNode {
+        // ids of the right subtree are shifted past the left one
+        let offset = nodes.len();
+        nodes.extend(right.into_iter().map(|node| BVNode {
+            left: node.left.map(|id| id + offset),
+            right: node.right.map(|id| id + offset),
+            ..node
+        }));
+        let (left, right) = (offset - 1, nodes.len() - 1);
+        let bbox = nodes[left].bbox.merge(nodes[right].bbox);
+        nodes.push(BVNode {
             left: Some(left),
             right: Some(right),
             bbox,
             hittable: None,
         });
 
-        self.nodes.len() - 1
+        nodes
     }
 
     fn compare_boxes(a: &AABB, b: &AABB, axis: usize) -> Ordering {
         a.min[axis].partial_cmp(&b.min[axis]).unwrap()
     }
 
-    fn hit_node(&self, id: NodeID, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit_node<P: Probe>(
+        &self,
+        id: NodeID,
+        r: &Ray,
+        t_min: f64,
+        t_max: f64,
+        probe: &mut P,
+    ) -> Option<HitRecord> {
+        if probe.exhausted() {
+            return None;
+        }
+
         let node = self.nodes[id].clone();
-        if !node.bbox.hit(&r, t_min, t_max) {
+        let hit = node.bbox.hit(r, t_min, t_max);
+        probe.node(id, hit);
+        if !hit {
             return None;
         }
 
         // check if node is a leaf
         if node.left.is_none() && node.right.is_none() {
-            return node.hittable.unwrap().hit(r, t_min, t_max);
+            let hittable = node.hittable.unwrap();
+            let rec = hittable.hit(r, t_min, t_max);
+            probe.primitive(hittable.as_ref(), rec.is_some());
+            return rec;
         }
 
         let hit_left = match node.left {
-            Some(id) => self.hit_node(id, &r, t_min, t_max),
+            Some(id) => self.hit_node(id, r, t_min, t_max, probe),
             None => None,
         };
         let hit_right = match node.right {
-            Some(id) => self.hit_node(id, &r, t_min, t_max),
+            Some(id) => self.hit_node(id, r, t_min, t_max, probe),
             None => None,
         };
 
         match (hit_left, hit_right) {
-            (None, None) => return None,
-            (Some(left_rec), None) => return Some(left_rec),
-            (None, Some(right_rec)) => return Some(right_rec),
+            (None, None) => None,
+            (Some(left_rec), None) => Some(left_rec),
+            (None, Some(right_rec)) => Some(right_rec),
             (Some(left_rec), Some(right_rec)) => {
                 if left_rec.t < right_rec.t {
-                    return Some(left_rec);
+                    Some(left_rec)
                 } else {
-                    return Some(right_rec);
+                    Some(right_rec)
                 }
             }
         }
     }
 }
 
+impl ProbedHit for BVTree {
+    fn hit_probed<P: Probe>(
+        &self,
+        r: &Ray,
+        t_min: f64,
+        t_max: f64,
+        probe: &mut P,
+    ) -> Option<HitRecord> {
+        self.hit_node(self.root_id, r, t_min, t_max, probe)
+    }
+}
+
 impl Hit for BVTree {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        // ray doesn't hit bbox of the tree
-        if !self.nodes[self.root_id].bbox.hit(&r, t_min, t_max) {
-            return None;
-        }
-
-        // ray hit bbox
-        self.hit_node(self.root_id, r, t_min, t_max)
+        self.hit_probed(r, t_min, t_max, &mut ())
     }
 
     fn bounding_box(&self, _: f64, _: f64) -> Option<AABB> {
@@ -217,11 +452,11 @@ impl Hit for BVTree {
 
 impl std::fmt::Display for BVTree {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Root ID: {}\n", self.root_id)?;
+        writeln!(f, "Root ID: {}", self.root_id)?;
         for (i, node) in self.nodes.iter().enumerate() {
-            write!(
+            writeln!(
                 f,
-                "Node {0}: (left = {1:?}, right = {2:?})\n",
+                "Node {0}: (left = {1:?}, right = {2:?})",
                 i, node.left, node.right
             )?;
         }
@@ -230,15 +465,43 @@ impl std::fmt::Display for BVTree {
     }
 }
 
+/// Group of objects, which may contain other groups. Children are shared
+/// immutably, so a group can't contain itself and nested groups always form a
+/// finite tree.
 pub type HittableList = Vec<Arc<dyn Hit>>;
 
-impl Hit for HittableList {
-    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+/// Collects boxed objects into a list, e.g. `spheres.into_iter().collect()`.
+impl std::iter::FromIterator<Box<dyn Hit>> for HittableList {
+    fn from_iter<I: IntoIterator<Item = Box<dyn Hit>>>(iter: I) -> Self {
+        iter.into_iter().map(Arc::from).collect()
+    }
+}
+
+impl Extend<Box<dyn Hit>> for HittableList {
+    fn extend<I: IntoIterator<Item = Box<dyn Hit>>>(&mut self, iter: I) {
+        Extend::<Arc<dyn Hit>>::extend(self, iter.into_iter().map(Arc::from));
+    }
+}
+
+impl ProbedHit for HittableList {
+    fn hit_probed<P: Probe>(
+        &self,
+        r: &Ray,
+        t_min: f64,
+        t_max: f64,
+        probe: &mut P,
+    ) -> Option<HitRecord> {
         let mut tmp_rec = None;
         let mut closest: f64 = t_max;
 
         for object in self {
-            if let Some(rec) = object.hit(&r, t_min, closest) {
+            if probe.exhausted() {
+                break;
+            }
+
+            let rec = object.hit(r, t_min, closest);
+            probe.primitive(object.as_ref(), rec.is_some());
+            if let Some(rec) = rec {
                 closest = rec.t;
                 tmp_rec = Some(rec)
             }
@@ -246,6 +509,12 @@ impl Hit for HittableList {
 
         tmp_rec
     }
+}
+
+impl Hit for HittableList {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.hit_probed(r, t_min, t_max, &mut ())
+    }
 
     fn bounding_box(&self, t1: f64, t2: f64) -> Option<AABB> {
         if self.is_empty() {
@@ -262,396 +531,2384 @@ impl Hit for HittableList {
 
         Some(bbox)
     }
+
+    // Ritter's algorithm over the children bounding spheres
+    fn bounding_sphere(&self) -> Option<(Point3, f64)> {
+        let spheres = self
+            .iter()
+            .map(|object| object.bounding_sphere())
+            .collect::<Option<Vec<_>>>()?;
+        let farthest = |from: Point3| {
+            spheres.iter().copied().max_by(|a, b| {
+                ((a.0 - from).norm() + a.1)
+                    .partial_cmp(&((b.0 - from).norm() + b.1))
+                    .unwrap()
+            })
+        };
+
+        let a = farthest(spheres.first()?.0)?;
+        let b = farthest(a.0)?;
+        let bounds = spheres.iter().fold(surrounding_sphere(a, b), |bounds, s| {
+            surrounding_sphere(bounds, *s)
+        });
+
+        Some(bounds)
+    }
+
+    fn pdf_value(&self, origin: Point3, dir: Vec3) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let sum: f64 = self.iter().map(|o| o.pdf_value(origin, dir)).sum();
+        sum / self.len() as f64
+    }
+
+    fn random_toward(&self, origin: Point3) -> Vec3 {
+        if self.is_empty() {
+            return vec3(1.0, 0.0, 0.0);
+        }
+        self[random_u32(0..self.len() as u32) as usize].random_toward(origin)
+    }
 }
 
-#[derive(Clone, Debug)]
-pub struct Sphere {
-    pub center: Point3,
-    pub radius: f64,
-    pub mat: Arc<dyn Material>,
+/// Acceleration structure of a scene: a `BVTree` for scenes of more than
+/// `threshold` objects, the plain list for smaller ones which are faster to
+/// test one by one than to traverse, or a `SphereList` when they are all
+/// spheres.
+#[derive(Debug, Clone)]
+pub enum Accelerator {
+    Linear(HittableList),
+    Spheres(SphereList),
+    Bvh(BVTree),
 }
 
-impl Sphere {
-    pub fn new(center: Point3, radius: f64, mat: Arc<dyn Material>) -> Self {
-        Self {
-            center,
-            radius,
-            mat,
+impl Accelerator {
+    pub fn build(world: HittableList, threshold: usize) -> Self {
+        if world.len() > threshold {
+            Self::Bvh(BVTree::new(world))
+        } else if let Some(spheres) = SphereList::from_list(&world).filter(|s| !s.is_empty()) {
+            Self::Spheres(spheres)
+        } else {
+            Self::Linear(world)
         }
     }
 
-    pub fn get_uv(point: &Point3) -> (f64, f64) {
-        let theta = (-point.y).acos();
-        let phi = (-point.z).atan2(point.x);
-
-        (phi / (2.0 * PI), theta / PI)
+    pub fn objects_count(&self) -> usize {
+        match self {
+            Self::Linear(world) => world.len(),
+            Self::Spheres(spheres) => spheres.len(),
+            Self::Bvh(tree) => tree.objects_count,
+        }
     }
 }
 
-impl Hit for Sphere {
+impl Hit for Accelerator {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let oc = r.origin - self.center;
-        let a = r.dir.squared_norm();
-        let half_b = oc.dot(r.dir);
-        let c = oc.squared_norm() - self.radius * self.radius;
-        let discriminant = half_b * half_b - a * c;
+        match self {
+            Self::Linear(world) => world.hit(r, t_min, t_max),
+            Self::Spheres(spheres) => spheres.hit(r, t_min, t_max),
+            Self::Bvh(tree) => tree.hit(r, t_min, t_max),
+        }
+    }
 
-        if discriminant < 0.0 {
-            return None;
+    fn bounding_box(&self, t1: f64, t2: f64) -> Option<AABB> {
+        match self {
+            Self::Linear(world) => world.bounding_box(t1, t2),
+            Self::Spheres(spheres) => spheres.bounding_box(t1, t2),
+            Self::Bvh(tree) => tree.bounding_box(t1, t2),
         }
+    }
 
-        let sqrtd = discriminant.sqrt();
-        let mut root = (-half_b - sqrtd) / a;
-        if !is_campled(root, t_min, t_max) {
-            root = (-half_b - sqrtd) / a;
-            if !is_campled(root, t_min, t_max) {
-                return None;
-            }
+    fn bounding_sphere(&self) -> Option<(Point3, f64)> {
+        match self {
+            Self::Linear(world) => world.bounding_sphere(),
+            Self::Spheres(spheres) => spheres.bounding_sphere(),
+            Self::Bvh(tree) => tree.bounding_sphere(),
         }
+    }
+}
 
-        let outward_normal = (r.at(root) - self.center) / self.radius;
-        let (u, v) = Self::get_uv(&outward_normal);
-        let mut rec = HitRecord {
-            point: r.at(root),
-            normal: vec3(0.0, 0.0, 0.0),
-            t: root,
-            u,
-            v,
-            front_face: false,
-            mat: self.mat.clone(),
-        };
+/// Sorts the objects by distance to the camera so that a linear traversal
+/// finds the closest hits first and rejects more objects early. Objects
+/// without bounds are moved to the end.
+pub fn optimize_for(world: &mut HittableList, camera: &Camera) {
+    let eye = camera.eye();
+    let distance = |object: &Arc<dyn Hit>| match object.bounding_sphere() {
+        Some((center, radius)) => ((center - eye).norm() - radius).max(0.0),
+        None => f64::INFINITY,
+    };
+
+    world.sort_by(|a, b| distance(a).partial_cmp(&distance(b)).unwrap());
+}
 
-        rec.set_face_normal(&r, outward_normal);
-        Some(rec)
+/// Hides from camera rays the objects whose bounding box is out of the view
+/// frustum of `camera` for an image of `(width, height)` pixels, which no
+/// primary ray can hit. They are still hit by shadow and reflection rays, so
+/// they keep showing in mirrors.
+pub fn frustum_cull(world: &mut HittableList, camera: &Camera, (width, height): (u32, u32)) {
+    // `get_ray` samples up to one pixel past the viewport
+    let pixel = (
+        1.0 / width.saturating_sub(1).max(1) as f64,
+        1.0 / height.saturating_sub(1).max(1) as f64,
+    );
+    let (t1, t2) = (camera.time_range(1.0).0, camera.time_range(0.0).1);
+    let hidden = VisibilityMask {
+        camera: false,
+        ..VisibilityMask::ALL
+    };
+
+    for object in world.iter_mut() {
+        match object.bounding_box(t1, t2) {
+            Some(bbox) if !camera.sees(&bbox, pixel) => {
+                *object = Arc::new(Visibility::new(object.clone(), hidden));
+            }
+            _ => (),
+        }
     }
+}
 
-    fn bounding_box(&self, _: f64, _: f64) -> Option<AABB> {
-        Some(AABB::new(
-            self.center - self.radius * Vec3::ONE,
-            self.center + self.radius * Vec3::ONE,
-        ))
+/// Smallest sphere enclosing the spheres `a` and `b`.
+pub fn surrounding_sphere(a: (Point3, f64), b: (Point3, f64)) -> (Point3, f64) {
+    let d = (b.0 - a.0).norm();
+    if d + b.1 <= a.1 {
+        return a;
     }
+    if d + a.1 <= b.1 {
+        return b;
+    }
+
+    let radius = (d + a.1 + b.1) / 2.0;
+    (a.0 + (b.0 - a.0) * ((radius - a.1) / d), radius)
 }
 
-#[derive(Clone, Debug)]
-pub struct MovingSphere {
-    pub radius: f64,
-    pub mat: Arc<dyn Material>,
-    centers: (Point3, Point3),
-    t1: f64,
-    t2: f64,
+/// Kinds of rays an object is visible to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VisibilityMask {
+    pub camera: bool,
+    pub shadow: bool,
+    pub reflection: bool,
 }
 
-impl MovingSphere {
-    pub fn new(
-        center: (Point3, Point3),
-        radius: f64,
-        t1: f64,
-        t2: f64,
-        mat: Arc<dyn Material>,
-    ) -> Self {
-        Self {
-            centers: center,
-            radius,
-            mat,
-            t1,
-            t2,
+impl VisibilityMask {
+    pub const ALL: Self = Self {
+        camera: true,
+        shadow: true,
+        reflection: true,
+    };
+
+    pub fn accepts(&self, kind: RayKind) -> bool {
+        match kind {
+            RayKind::Camera => self.camera,
+            RayKind::Shadow => self.shadow,
+            RayKind::Reflection => self.reflection,
         }
     }
+}
 
-    pub fn center(&self, t: f64) -> Point3 {
-        self.centers.0 + ((t - self.t1) / (self.t2 - self.t1)) * (self.centers.1 - self.centers.0)
+impl Default for VisibilityMask {
+    fn default() -> Self {
+        Self::ALL
     }
+}
 
-    pub fn get_uv(point: &Point3) -> (f64, f64) {
-        let theta = -point.y.acos();
-        let phi = -point.z.atan2(point.x);
+/// Object only hit by the kinds of rays of its mask.
+#[derive(Debug, Clone)]
+pub struct Visibility {
+    pub object: Arc<dyn Hit>,
+    pub mask: VisibilityMask,
+}
 
-        (phi / (2.0 * PI), theta / PI)
+impl Visibility {
+    pub fn new(object: Arc<dyn Hit>, mask: VisibilityMask) -> Self {
+        Self { object, mask }
     }
 }
 
-impl Hit for MovingSphere {
+impl Hit for Visibility {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let oc = r.origin - self.center(r.time);
-        let a = r.dir.squared_norm();
-        let half_b = oc.dot(r.dir);
-        let c = oc.squared_norm() - self.radius * self.radius;
-        let discriminant = half_b * half_b - a * c;
-
-        if discriminant < 0.0 {
+        if !self.mask.accepts(r.kind) {
             return None;
         }
 
-        let sqrtd = discriminant.sqrt();
-        let mut root = (-half_b - sqrtd) / a;
-        if !is_campled(root, t_min, t_max) {
-            root = (-half_b - sqrtd) / a;
-            if !is_campled(root, t_min, t_max) {
-                return None;
-            }
-        }
+        self.object.hit(r, t_min, t_max)
+    }
 
-        let outward_normal = (r.at(root) - self.center(r.time)) / self.radius;
-        let (u, v) = Self::get_uv(&outward_normal);
-        let mut rec = HitRecord {
-            point: r.at(root),
-            normal: vec3(0.0, 0.0, 0.0),
-            t: root,
-            u,
-            v,
-            front_face: false,
-            mat: self.mat.clone(),
-        };
+    fn bounding_box(&self, t1: f64, t2: f64) -> Option<AABB> {
+        self.object.bounding_box(t1, t2)
+    }
 
-        rec.set_face_normal(&r, outward_normal);
-        Some(rec)
+    fn bounding_sphere(&self) -> Option<(Point3, f64)> {
+        self.object.bounding_sphere()
     }
 
-    fn bounding_box(&self, t1: f64, t2: f64) -> Option<AABB> {
-        Some(AABB::surrounding_box(
-            &AABB::new(
-                self.center(t1) - self.radius * Vec3::ONE,
-                self.center(t1) - self.radius * Vec3::ONE,
-            ),
-            &AABB::new(
-                self.center(t2) - self.radius * Vec3::ONE,
-                self.center(t2) - self.radius * Vec3::ONE,
-            ),
-        ))
+    fn pdf_value(&self, origin: Point3, dir: Vec3) -> f64 {
+        self.object.pdf_value(origin, dir)
+    }
+
+    fn random_toward(&self, origin: Point3) -> Vec3 {
+        self.object.random_toward(origin)
     }
 }
 
+/// Level of detail for distant objects: secondary rays starting farther than
+/// `distance` from the object hit its bounding sphere, of material `mat`,
+/// rather than its exact geometry. Camera rays always hit the exact geometry.
+/// The object, most often a mesh, keeps its bounding box in `CachedBounds`.
 #[derive(Debug, Clone)]
-pub struct XYRect {
-    x: (f64, f64),
-    y: (f64, f64),
-    k: f64,
-    mat: Arc<dyn Material>,
+pub struct Lod {
+    pub object: Arc<dyn Hit>,
+    pub distance: f64,
+    proxy: Option<Sphere>,
 }
 
-impl XYRect {
-    pub fn new(x: (f64, f64), y: (f64, f64), k: f64, mat: Arc<dyn Material>) -> Self {
-        Self { x, y, k, mat }
+impl Lod {
+    pub fn new(object: Arc<dyn Hit>, mat: Arc<dyn Material>, distance: f64) -> Self {
+        let object: Arc<dyn Hit> = Arc::new(CachedBounds::new(object));
+        let proxy = object
+            .bounding_sphere()
+            .map(|(center, radius)| Sphere::new(center, radius, mat));
+        Self {
+            object,
+            distance,
+            proxy,
+        }
     }
 }
 
-impl Hit for XYRect {
+impl Hit for Lod {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let t = (self.k - r.origin.z) / r.dir.z;
-        if !is_campled(t, t_min, t_max) {
-            return None;
+        match &self.proxy {
+            Some(proxy)
+                if r.kind != RayKind::Camera
+                    && (proxy.center - r.origin).norm() - proxy.radius > self.distance =>
+            {
+                proxy.hit(r, t_min, t_max)
+            }
+            _ => self.object.hit(r, t_min, t_max),
         }
+    }
 
-        let x = r.origin.x + t * r.dir.x;
-        let y = r.origin.y + t * r.dir.y;
-        if !is_campled(x, self.x.0, self.x.1) || !is_campled(y, self.y.0, self.y.1) {
-            return None;
+    fn bounding_box(&self, t1: f64, t2: f64) -> Option<AABB> {
+        self.object.bounding_box(t1, t2)
+    }
+
+    fn bounding_sphere(&self) -> Option<(Point3, f64)> {
+        self.object.bounding_sphere()
+    }
+}
+
+// time interval and bounding box of the object over it
+type BoundsCache = ((f64, f64), Option<AABB>);
+
+/// Remembers the bounding box of an expensive object, like a mesh, for the
+/// first time interval asked, other intervals being computed every time.
+/// Replacing the object, for example by a moved copy, drops the cached box.
+#[derive(Debug, Clone)]
+pub struct CachedBounds {
+    object: Arc<dyn Hit>,
+    cache: OnceLock<BoundsCache>,
+}
+
+impl CachedBounds {
+    pub fn new(object: Arc<dyn Hit>) -> Self {
+        Self {
+            object,
+            cache: OnceLock::new(),
         }
+    }
 
-        let normal = vec3(0.0, 0.0, 1.0);
-        let mut rec = HitRecord {
-            point: r.at(t),
-            normal,
-            t,
-            u: (x - self.x.0) / (self.x.1 - self.x.0),
-            v: (y - self.y.0) / (self.y.1 - self.y.0),
-            front_face: false,
-            mat: self.mat.clone(),
-        };
-        rec.set_face_normal(&r, normal);
-        Some(rec)
+    pub fn object(&self) -> &Arc<dyn Hit> {
+        &self.object
     }
 
-    fn bounding_box(&self, _t1: f64, _t2: f64) -> Option<AABB> {
-        Some(AABB::new(
-            vec3(self.x.0, self.y.0, self.k - 0.0001),
-            vec3(self.x.1, self.y.1, self.k + 0.0001),
-        ))
+    pub fn set_object(&mut self, object: Arc<dyn Hit>) {
+        self.object = object;
+        self.invalidate();
+    }
+
+    /// Drops the cached box, when the object moved in place.
+    pub fn invalidate(&mut self) {
+        self.cache.take();
+    }
+}
+
+impl Hit for CachedBounds {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.object.hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, t1: f64, t2: f64) -> Option<AABB> {
+        let (interval, bbox) = self
+            .cache
+            .get_or_init(|| ((t1, t2), self.object.bounding_box(t1, t2)));
+        if *interval == (t1, t2) {
+            *bbox
+        } else {
+            self.object.bounding_box(t1, t2)
+        }
+    }
+
+    fn bounding_sphere(&self) -> Option<(Point3, f64)> {
+        self.object.bounding_sphere()
     }
 }
 
+/// Fog of uniform `density` filling a convex `boundary`, scattering rays
+/// with its `phase` material at random distances inside.
 #[derive(Debug, Clone)]
-pub struct XZRect {
-    x: (f64, f64),
-    z: (f64, f64),
-    k: f64,
-    mat: Arc<dyn Material>,
+pub struct ConstantMedium {
+    pub boundary: Arc<dyn Hit>,
+    pub density: f64,
+    pub phase: Arc<dyn Material>,
 }
 
-impl XZRect {
-    pub fn new(x: (f64, f64), z: (f64, f64), k: f64, mat: Arc<dyn Material>) -> Self {
-        Self { x, z, k, mat }
+impl ConstantMedium {
+    pub fn new(boundary: Arc<dyn Hit>, density: f64, phase: Arc<dyn Material>) -> Self {
+        Self {
+            boundary,
+            density,
+            phase,
+        }
     }
 }
 
-impl Hit for XZRect {
+impl Hit for ConstantMedium {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let t = (self.k - r.origin.y) / r.dir.y;
-        if !is_campled(t, t_min, t_max) {
+        let (t1, t2) = medium_interval(&self.boundary, r, t_min, t_max)?;
+        let len = r.dir.norm();
+        let t = t1 - (1.0 - random()).ln() / (self.density * len);
+        if t >= t2 {
             return None;
         }
 
-        let x = r.origin.x + t * r.dir.x;
-        let z = r.origin.z + t * r.dir.z;
-        if !is_campled(x, self.x.0, self.x.1) || !is_campled(z, self.z.0, self.z.1) {
-            return None;
+        Some(medium_record(r, t, &self.phase))
+    }
+
+    fn bounding_box(&self, t1: f64, t2: f64) -> Option<AABB> {
+        self.boundary.bounding_box(t1, t2)
+    }
+}
+
+/// Fog of spatially varying density inside a convex `boundary`, the
+/// `value` of `density` times `max_density`. Scattering distances are
+/// sampled by delta (Woodcock) tracking against `max_density`, so `density`
+/// must not exceed 1.
+#[derive(Debug, Clone)]
+pub struct HeterogeneousMedium {
+    pub boundary: Arc<dyn Hit>,
+    pub density: Arc<dyn Texture>,
+    pub max_density: f64,
+    pub phase: Arc<dyn Material>,
+}
+
+impl HeterogeneousMedium {
+    pub fn new(
+        boundary: Arc<dyn Hit>,
+        density: Arc<dyn Texture>,
+        max_density: f64,
+        phase: Arc<dyn Material>,
+    ) -> Self {
+        Self {
+            boundary,
+            density,
+            max_density,
+            phase,
         }
+    }
 
-        let normal = vec3(0.0, 1.0, 0.0);
-        let mut rec = HitRecord {
-            point: r.at(t),
-            normal,
-            t,
-            u: (x - self.x.0) / (self.x.1 - self.x.0),
-            v: (z - self.z.0) / (self.z.1 - self.z.0),
-            front_face: false,
-            mat: self.mat.clone(),
-        };
-        rec.set_face_normal(&r, normal);
-        Some(rec)
+    /// Fraction of `max_density` at `point`.
+    pub fn density_at(&self, point: &Point3) -> f64 {
+        self.density.value(0.0, 0.0, point)
     }
+}
 
-    fn bounding_box(&self, _t1: f64, _t2: f64) -> Option<AABB> {
-        Some(AABB::new(
-            vec3(self.x.0, self.k - 0.0001, self.z.0),
-            vec3(self.x.1, self.k + 0.0001, self.z.1),
-        ))
+impl Hit for HeterogeneousMedium {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let (mut t, t2) = medium_interval(&self.boundary, r, t_min, t_max)?;
+        let len = r.dir.norm();
+        loop {
+            t -= (1.0 - random()).ln() / (self.max_density * len);
+            if t >= t2 {
+                return None;
+            }
+
+            if random() < self.density_at(&r.at(t)) {
+                return Some(medium_record(r, t, &self.phase));
+            }
+        }
+    }
+
+    fn bounding_box(&self, t1: f64, t2: f64) -> Option<AABB> {
+        self.boundary.bounding_box(t1, t2)
+    }
+}
+
+// part of [t_min, t_max] of the ray inside a convex boundary
+fn medium_interval(boundary: &Arc<dyn Hit>, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64)> {
+    let enter = boundary.hit(r, f64::NEG_INFINITY, f64::INFINITY)?;
+    let exit = boundary.hit(r, enter.t + 0.0001, f64::INFINITY)?;
+    let (t1, t2) = (enter.t.max(t_min).max(0.0), exit.t.min(t_max));
+    if t1 >= t2 {
+        return None;
+    }
+
+    Some((t1, t2))
+}
+
+fn medium_record(r: &Ray, t: f64, phase: &Arc<dyn Material>) -> HitRecord {
+    HitRecord {
+        point: r.at(t),
+        // arbitrary, phase functions don't use it
+        normal: vec3(1.0, 0.0, 0.0),
+        t,
+        u: 0.0,
+        v: 0.0,
+        barycentric: None,
+        front_face: true,
+        mat: resolve(phase),
+        velocity: Vec3::ZERO,
+    }
+}
+
+/// Boolean operation combining the solids of a `Csg`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsgOp {
+    Union,
+    Intersection,
+    /// the first solid minus the second
+    Difference,
+}
+
+impl CsgOp {
+    fn contains(self, in_a: bool, in_b: bool) -> bool {
+        match self {
+            Self::Union => in_a || in_b,
+            Self::Intersection => in_a && in_b,
+            Self::Difference => in_a && !in_b,
+        }
     }
 }
 
+/// Constructive solid geometry of two closed objects. The surface hit is
+/// the first crossing of `a` or `b` along the ray where the ray enters or
+/// leaves the combined solid, so the surfaces of `b` carved out by a
+/// difference face inward. Both objects keep their bounding boxes in
+/// `CachedBounds`.
 #[derive(Debug, Clone)]
-pub struct YZRect {
-    y: (f64, f64),
-    z: (f64, f64),
-    k: f64,
-    mat: Arc<dyn Material>,
+pub struct Csg {
+    pub a: Arc<dyn Hit>,
+    pub b: Arc<dyn Hit>,
+    pub op: CsgOp,
 }
 
-impl YZRect {
-    pub fn new(y: (f64, f64), z: (f64, f64), k: f64, mat: Arc<dyn Material>) -> Self {
-        Self { y, z, k, mat }
+impl Csg {
+    pub fn new(a: Arc<dyn Hit>, b: Arc<dyn Hit>, op: CsgOp) -> Self {
+        Self {
+            a: Arc::new(CachedBounds::new(a)),
+            b: Arc::new(CachedBounds::new(b)),
+            op,
+        }
     }
 }
 
-impl Hit for YZRect {
+impl Hit for Csg {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let t = (self.k - r.origin.x) / r.dir.x;
-        if !is_campled(t, t_min, t_max) {
-            return None;
-        }
+        let mut crossings: Vec<(usize, HitRecord)> = crossings(&self.a, r)
+            .into_iter()
+            .map(|rec| (0, rec))
+            .chain(crossings(&self.b, r).into_iter().map(|rec| (1, rec)))
+            .collect();
+        crossings.sort_by(|c1, c2| c1.1.t.partial_cmp(&c2.1.t).unwrap());
+
+        let mut inside = [false, false];
+        for (object, mut rec) in crossings {
+            if rec.t > t_max {
+                return None;
+            }
 
-        let y = r.origin.y + t * r.dir.y;
-        let z = r.origin.z + t * r.dir.z;
-        if !is_campled(y, self.y.0, self.y.1) || !is_campled(z, self.z.0, self.z.1) {
-            return None;
+            let was_inside = self.op.contains(inside[0], inside[1]);
+            inside[object] = rec.front_face;
+            let is_inside = self.op.contains(inside[0], inside[1]);
+            if was_inside != is_inside && rec.t >= t_min {
+                // the normal already faces the ray, only the side changes
+                rec.front_face = is_inside;
+                return Some(rec);
+            }
         }
 
-        let normal = vec3(1.0, 0.0, 0.0);
-        let mut rec = HitRecord {
-            point: r.at(t),
-            normal,
-            t,
-            u: (y - self.y.0) / (self.y.1 - self.y.0),
-            v: (z - self.z.0) / (self.z.1 - self.z.0),
-            front_face: false,
-            mat: self.mat.clone(),
-        };
-        rec.set_face_normal(&r, normal);
-        Some(rec)
+        None
     }
 
-    fn bounding_box(&self, _t1: f64, _t2: f64) -> Option<AABB> {
-        Some(AABB::new(
-            vec3(self.k - 0.0001, self.y.0, self.z.0),
-            vec3(self.k + 0.0001, self.y.1, self.z.1),
-        ))
+    fn bounding_box(&self, t1: f64, t2: f64) -> Option<AABB> {
+        let a = self.a.bounding_box(t1, t2)?;
+        match self.op {
+            CsgOp::Union => Some(AABB::surrounding_box(&a, &self.b.bounding_box(t1, t2)?)),
+            _ => Some(a),
+        }
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct Cube {
-    bbox: AABB,
-    mat: Arc<dyn Material>,
+// upper bound on the surfaces of an object crossed by a ray
+const MAX_CROSSINGS: usize = 64;
+
+// every crossing of the surface of `object` along the whole line of the ray
+fn crossings(object: &Arc<dyn Hit>, r: &Ray) -> Vec<HitRecord> {
+    let mut records = Vec::new();
+    let mut t = f64::NEG_INFINITY;
+    while let Some(rec) = object.hit(r, t, f64::INFINITY) {
+        t = rec.t + 1e-9 * rec.t.abs().max(1.0);
+        records.push(rec);
+        if records.len() == MAX_CROSSINGS {
+            break;
+        }
+    }
+
+    records
 }
 
-impl Cube {
-    pub fn new(p0: Vec3, p1: Vec3, mat: Arc<dyn Material>) -> Self {
+#[derive(Clone, Debug)]
+pub struct Sphere {
+    pub center: Point3,
+    pub radius: f64,
+    pub mat: Arc<dyn Material>,
+}
+
+impl Sphere {
+    pub fn new(center: Point3, radius: f64, mat: Arc<dyn Material>) -> Self {
         Self {
-            bbox: AABB::new(p0, p1),
+            center,
+            radius,
             mat,
         }
     }
 
-    pub fn unit_cube(pos: Vec3, mat: Arc<dyn Material>) -> Self {
-        Self::new(pos, pos + Vec3::ONE, mat)
+    pub fn get_uv(point: &Point3) -> (f64, f64) {
+        let theta = (-point.y).acos();
+        let phi = (-point.z).atan2(point.x);
+
+        (phi / (2.0 * PI), theta / PI)
     }
 }
 
-impl Hit for Cube {
-    fn hit(&self, r: &Ray, mut t_min: f64, mut t_max: f64) -> Option<HitRecord> {
-        let inv_d = 1.0 / r.dir;
-        let t1 = (self.bbox.min - r.origin) * inv_d;
-        let t2 = (self.bbox.max - r.origin) * inv_d;
+impl Hit for Sphere {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let oc = r.origin - self.center;
+        let a = r.dir.squared_norm();
+        let half_b = oc.dot(r.dir);
+        let c = oc.squared_norm() - self.radius * self.radius;
+        let discriminant = half_b * half_b - a * c;
 
-        for i in 0..3 {
-            t_min = t1[i].min(t2[i]).max(t_min);
-            t_max = t1[i].max(t2[i]).min(t_max);
+        if discriminant < 0.0 {
+            return None;
         }
 
-        if t_min >= t_max {
-            return None;
+        let sqrtd = discriminant.sqrt();
+        let mut root = (-half_b - sqrtd) / a;
+        if !is_campled(root, t_min, t_max) {
+            root = (-half_b + sqrtd) / a;
+            if !is_campled(root, t_min, t_max) {
+                return None;
+            }
         }
 
-        let point = r.at(t_min);
-        let normal = self.bbox.get_normal(&point);
+        let outward_normal = (r.at(root) - self.center) / self.radius;
+        let (u, v) = Self::get_uv(&outward_normal);
         let mut rec = HitRecord {
-            t: t_min,
-            mat: self.mat.clone(),
-            normal,
-            point,
-            u: 0.0,
-            v: 0.0,
+            point: r.at(root),
+            normal: vec3(0.0, 0.0, 0.0),
+            t: root,
+            u,
+            v,
+            barycentric: None,
             front_face: false,
+            mat: resolve(&self.mat),
+            velocity: Vec3::ZERO,
         };
-        rec.set_face_normal(&r, normal);
 
-        return Some(rec);
+        rec.set_face_normal(r, outward_normal);
+        Some(rec)
     }
 
     fn bounding_box(&self, _: f64, _: f64) -> Option<AABB> {
-        Some(self.bbox)
+        Some(AABB::new(
+            self.center - self.radius * Vec3::ONE,
+            self.center + self.radius * Vec3::ONE,
+        ))
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::*;
-    use std::sync::Arc;
+    fn bounding_sphere(&self) -> Option<(Point3, f64)> {
+        Some((self.center, self.radius))
+    }
 
-    #[test]
-    fn test_build_tree() {
-        let mut world = HittableList::new();
-        world.push(Arc::new(Sphere::new(
-            vec3(-10.0, 0.0, 0.0),
-            2.5,
-            Arc::new(Lambertian::from_color(Color::new(255, 0, 0, 255))),
-        )));
-        world.push(Arc::new(Sphere::new(
-            vec3(0.0, 0.0, 0.0),
-            2.5,
-            Arc::new(Lambertian::from_color(Color::new(0, 255, 0, 255))),
-        )));
-        world.push(Arc::new(Sphere::new(
-            vec3(10.0, 0.0, 0.0),
-            2.5,
-            Arc::new(Lambertian::from_color(Color::new(0, 0, 255, 255))),
-        )));
+    fn as_sphere(&self) -> Option<&Sphere> {
+        Some(self)
+    }
 
-        let tree = BVTree::new(world);
-        println!("{}", tree);
+    fn pdf_value(&self, origin: Point3, dir: Vec3) -> f64 {
+        if self
+            .hit(&ray(origin, dir, 0.0), 0.001, f64::INFINITY)
+            .is_none()
+        {
+            return 0.0;
+        }
 
-        let r = ray(vec3(0.0, -10.0, 0.0), vec3(0.0, 1.0, 0.0), 0.0);
-        println!("{:?}", tree.hit(&r, 0.0001, f64::INFINITY));
+        let cos_theta_max =
+            (1.0 - self.radius.powi(2) / (self.center - origin).squared_norm()).sqrt();
+        1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+    }
+
+    fn random_toward(&self, origin: Point3) -> Vec3 {
+        let direction = self.center - origin;
+        let cos_theta_max = (1.0 - self.radius.powi(2) / direction.squared_norm()).sqrt();
+        let z = 1.0 + random() * (cos_theta_max - 1.0);
+        let phi = random_range(0.0, 2.0 * PI);
+        let r = (1.0 - z * z).sqrt();
+
+        let w = direction.normalize();
+        let a = if w.x.abs() > 0.9 {
+            vec3(0.0, 1.0, 0.0)
+        } else {
+            vec3(1.0, 0.0, 0.0)
+        };
+        let v = w.cross(a).normalize();
+        let u = w.cross(v);
+        u * (r * phi.cos()) + v * (r * phi.sin()) + w * z
+    }
+}
+
+/// Spheres stored as arrays of their coordinates, intersected in a tight
+/// loop which is much faster than a `HittableList` of spheres when the scene
+/// has no BVH.
+#[derive(Debug, Clone, Default)]
+pub struct SphereList {
+    x: Vec<f64>,
+    y: Vec<f64>,
+    z: Vec<f64>,
+    radius: Vec<f64>,
+    spheres: Vec<Sphere>,
+}
+
+impl SphereList {
+    pub fn new(spheres: Vec<Sphere>) -> Self {
+        Self {
+            x: spheres.iter().map(|s| s.center.x).collect(),
+            y: spheres.iter().map(|s| s.center.y).collect(),
+            z: spheres.iter().map(|s| s.center.z).collect(),
+            radius: spheres.iter().map(|s| s.radius).collect(),
+            spheres,
+        }
+    }
+
+    /// The objects of `world` as a sphere list, if they are all spheres.
+    pub fn from_list(world: &HittableList) -> Option<Self> {
+        let spheres = world
+            .iter()
+            .map(|object| object.as_sphere().cloned())
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self::new(spheres))
+    }
+
+    pub fn len(&self) -> usize {
+        self.spheres.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spheres.is_empty()
+    }
+}
+
+impl Hit for SphereList {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let a = r.dir.squared_norm();
+        let mut closest = t_max;
+        let mut nearest = None;
+
+        for k in 0..self.spheres.len() {
+            let oc = vec3(
+                r.origin.x - self.x[k],
+                r.origin.y - self.y[k],
+                r.origin.z - self.z[k],
+            );
+            let half_b = oc.dot(r.dir);
+            let c = oc.squared_norm() - self.radius[k] * self.radius[k];
+            let discriminant = half_b * half_b - a * c;
+            if discriminant < 0.0 {
+                continue;
+            }
+
+            let sqrtd = discriminant.sqrt();
+            let mut root = (-half_b - sqrtd) / a;
+            if !is_campled(root, t_min, closest) {
+                root = (-half_b + sqrtd) / a;
+                if !is_campled(root, t_min, closest) {
+                    continue;
+                }
+            }
+            closest = root;
+            nearest = Some(k);
+        }
+
+        // only build the record of the closest sphere
+        self.spheres[nearest?].hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, t1: f64, t2: f64) -> Option<AABB> {
+        self.spheres
+            .iter()
+            .map(|s| s.bounding_box(t1, t2))
+            .try_fold(AABB::empty(), |bbox, b| Some(bbox.merge(b?)))
+            .filter(|_| !self.is_empty())
+    }
+
+    fn pdf_value(&self, origin: Point3, dir: Vec3) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let sum: f64 = self.spheres.iter().map(|s| s.pdf_value(origin, dir)).sum();
+        sum / self.len() as f64
+    }
+
+    fn random_toward(&self, origin: Point3) -> Vec3 {
+        if self.is_empty() {
+            return vec3(1.0, 0.0, 0.0);
+        }
+        self.spheres[random_u32(0..self.len() as u32) as usize].random_toward(origin)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MovingSphere {
+    pub radius: f64,
+    pub mat: Arc<dyn Material>,
+    centers: (Point3, Point3),
+    t1: f64,
+    t2: f64,
+}
+
+impl MovingSphere {
+    pub fn new(
+        center: (Point3, Point3),
+        radius: f64,
+        t1: f64,
+        t2: f64,
+        mat: Arc<dyn Material>,
+    ) -> Self {
+        Self {
+            centers: center,
+            radius,
+            mat,
+            t1,
+            t2,
+        }
+    }
+
+    pub fn velocity(&self) -> Vec3 {
+        (self.centers.1 - self.centers.0) / (self.t2 - self.t1)
+    }
+
+    pub fn center(&self, t: f64) -> Point3 {
+        self.centers.0 + ((t - self.t1) / (self.t2 - self.t1)) * (self.centers.1 - self.centers.0)
+    }
+
+    pub fn get_uv(point: &Point3) -> (f64, f64) {
+        let theta = -point.y.acos();
+        let phi = -point.z.atan2(point.x);
+
+        (phi / (2.0 * PI), theta / PI)
+    }
+}
+
+impl Hit for MovingSphere {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let oc = r.origin - self.center(r.time);
+        let a = r.dir.squared_norm();
+        let half_b = oc.dot(r.dir);
+        let c = oc.squared_norm() - self.radius * self.radius;
+        let discriminant = half_b * half_b - a * c;
+
+        if discriminant < 0.0 {
+            return None;
+        }
+
+        let sqrtd = discriminant.sqrt();
+        let mut root = (-half_b - sqrtd) / a;
+        if !is_campled(root, t_min, t_max) {
+            root = (-half_b + sqrtd) / a;
+            if !is_campled(root, t_min, t_max) {
+                return None;
+            }
+        }
+
+        let outward_normal = (r.at(root) - self.center(r.time)) / self.radius;
+        let (u, v) = Self::get_uv(&outward_normal);
+        let mut rec = HitRecord {
+            point: r.at(root),
+            normal: vec3(0.0, 0.0, 0.0),
+            t: root,
+            u,
+            v,
+            barycentric: None,
+            front_face: false,
+            mat: resolve(&self.mat),
+            velocity: self.velocity(),
+        };
+
+        rec.set_face_normal(r, outward_normal);
+        Some(rec)
+    }
+
+    fn bounding_box(&self, t1: f64, t2: f64) -> Option<AABB> {
+        Some(AABB::surrounding_box(
+            &AABB::new(
+                self.center(t1) - self.radius * Vec3::ONE,
+                self.center(t1) + self.radius * Vec3::ONE,
+            ),
+            &AABB::new(
+                self.center(t2) - self.radius * Vec3::ONE,
+                self.center(t2) + self.radius * Vec3::ONE,
+            ),
+        ))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct XYRect {
+    x: (f64, f64),
+    y: (f64, f64),
+    k: f64,
+    mat: Arc<dyn Material>,
+}
+
+impl XYRect {
+    pub fn new(x: (f64, f64), y: (f64, f64), k: f64, mat: Arc<dyn Material>) -> Self {
+        Self { x, y, k, mat }
+    }
+}
+
+impl Hit for XYRect {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let t = (self.k - r.origin.z) / r.dir.z;
+        if !is_campled(t, t_min, t_max) {
+            return None;
+        }
+
+        let x = r.origin.x + t * r.dir.x;
+        let y = r.origin.y + t * r.dir.y;
+        if !is_campled(x, self.x.0, self.x.1) || !is_campled(y, self.y.0, self.y.1) {
+            return None;
+        }
+
+        let normal = vec3(0.0, 0.0, 1.0);
+        let mut rec = HitRecord {
+            point: r.at(t),
+            normal,
+            t,
+            u: (x - self.x.0) / (self.x.1 - self.x.0),
+            v: (y - self.y.0) / (self.y.1 - self.y.0),
+            barycentric: None,
+            front_face: false,
+            mat: resolve(&self.mat),
+            velocity: Vec3::ZERO,
+        };
+        rec.set_face_normal(r, normal);
+        Some(rec)
+    }
+
+    fn bounding_box(&self, _t1: f64, _t2: f64) -> Option<AABB> {
+        Some(AABB::new(
+            vec3(self.x.0, self.y.0, self.k - 0.0001),
+            vec3(self.x.1, self.y.1, self.k + 0.0001),
+        ))
+    }
+
+    fn pdf_value(&self, origin: Point3, dir: Vec3) -> f64 {
+        let area = (self.x.1 - self.x.0) * (self.y.1 - self.y.0);
+        area_pdf(
+            self.hit(&ray(origin, dir, 0.0), 0.001, f64::INFINITY),
+            dir,
+            area,
+        )
+    }
+
+    fn random_toward(&self, origin: Point3) -> Vec3 {
+        vec3(
+            random_range(self.x.0, self.x.1),
+            random_range(self.y.0, self.y.1),
+            self.k,
+        ) - origin
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct XZRect {
+    x: (f64, f64),
+    z: (f64, f64),
+    k: f64,
+    mat: Arc<dyn Material>,
+}
+
+impl XZRect {
+    pub fn new(x: (f64, f64), z: (f64, f64), k: f64, mat: Arc<dyn Material>) -> Self {
+        Self { x, z, k, mat }
+    }
+}
+
+impl Hit for XZRect {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let t = (self.k - r.origin.y) / r.dir.y;
+        if !is_campled(t, t_min, t_max) {
+            return None;
+        }
+
+        let x = r.origin.x + t * r.dir.x;
+        let z = r.origin.z + t * r.dir.z;
+        if !is_campled(x, self.x.0, self.x.1) || !is_campled(z, self.z.0, self.z.1) {
+            return None;
+        }
+
+        let normal = vec3(0.0, 1.0, 0.0);
+        let mut rec = HitRecord {
+            point: r.at(t),
+            normal,
+            t,
+            u: (x - self.x.0) / (self.x.1 - self.x.0),
+            v: (z - self.z.0) / (self.z.1 - self.z.0),
+            barycentric: None,
+            front_face: false,
+            mat: resolve(&self.mat),
+            velocity: Vec3::ZERO,
+        };
+        rec.set_face_normal(r, normal);
+        Some(rec)
+    }
+
+    fn bounding_box(&self, _t1: f64, _t2: f64) -> Option<AABB> {
+        Some(AABB::new(
+            vec3(self.x.0, self.k - 0.0001, self.z.0),
+            vec3(self.x.1, self.k + 0.0001, self.z.1),
+        ))
+    }
+
+    fn pdf_value(&self, origin: Point3, dir: Vec3) -> f64 {
+        let area = (self.x.1 - self.x.0) * (self.z.1 - self.z.0);
+        area_pdf(
+            self.hit(&ray(origin, dir, 0.0), 0.001, f64::INFINITY),
+            dir,
+            area,
+        )
+    }
+
+    fn random_toward(&self, origin: Point3) -> Vec3 {
+        vec3(
+            random_range(self.x.0, self.x.1),
+            self.k,
+            random_range(self.z.0, self.z.1),
+        ) - origin
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct YZRect {
+    y: (f64, f64),
+    z: (f64, f64),
+    k: f64,
+    mat: Arc<dyn Material>,
+}
+
+impl YZRect {
+    pub fn new(y: (f64, f64), z: (f64, f64), k: f64, mat: Arc<dyn Material>) -> Self {
+        Self { y, z, k, mat }
+    }
+}
+
+impl Hit for YZRect {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let t = (self.k - r.origin.x) / r.dir.x;
+        if !is_campled(t, t_min, t_max) {
+            return None;
+        }
+
+        let y = r.origin.y + t * r.dir.y;
+        let z = r.origin.z + t * r.dir.z;
+        if !is_campled(y, self.y.0, self.y.1) || !is_campled(z, self.z.0, self.z.1) {
+            return None;
+        }
+
+        let normal = vec3(1.0, 0.0, 0.0);
+        let mut rec = HitRecord {
+            point: r.at(t),
+            normal,
+            t,
+            u: (y - self.y.0) / (self.y.1 - self.y.0),
+            v: (z - self.z.0) / (self.z.1 - self.z.0),
+            barycentric: None,
+            front_face: false,
+            mat: resolve(&self.mat),
+            velocity: Vec3::ZERO,
+        };
+        rec.set_face_normal(r, normal);
+        Some(rec)
+    }
+
+    fn bounding_box(&self, _t1: f64, _t2: f64) -> Option<AABB> {
+        Some(AABB::new(
+            vec3(self.k - 0.0001, self.y.0, self.z.0),
+            vec3(self.k + 0.0001, self.y.1, self.z.1),
+        ))
+    }
+
+    fn pdf_value(&self, origin: Point3, dir: Vec3) -> f64 {
+        let area = (self.y.1 - self.y.0) * (self.z.1 - self.z.0);
+        area_pdf(
+            self.hit(&ray(origin, dir, 0.0), 0.001, f64::INFINITY),
+            dir,
+            area,
+        )
+    }
+
+    fn random_toward(&self, origin: Point3) -> Vec3 {
+        vec3(
+            self.k,
+            random_range(self.y.0, self.y.1),
+            random_range(self.z.0, self.z.1),
+        ) - origin
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Cube {
+    bbox: AABB,
+    mat: Arc<dyn Material>,
+}
+
+impl Cube {
+    pub fn new(p0: Vec3, p1: Vec3, mat: Arc<dyn Material>) -> Self {
+        Self {
+            bbox: AABB::new(p0, p1),
+            mat,
+        }
+    }
+
+    pub fn unit_cube(pos: Vec3, mat: Arc<dyn Material>) -> Self {
+        Self::new(pos, pos + Vec3::ONE, mat)
+    }
+
+    /// Coordinates of `point` in [0, 1] across the face it lies on, so that a
+    /// texture is repeated on each face.
+    pub fn face_uv(&self, point: &Point3) -> (f64, f64) {
+        let size = self.bbox.max - self.bbox.min;
+        let p = *point - self.bbox.min;
+        let rel = vec3(p.x / size.x, p.y / size.y, p.z / size.z);
+
+        // the face axis is the one where the point is the farthest from the center
+        let d = (rel - 0.5 * Vec3::ONE).abs();
+        if d.x >= d.y && d.x >= d.z {
+            (rel.z, rel.y)
+        } else if d.y >= d.z {
+            (rel.x, rel.z)
+        } else {
+            (rel.x, rel.y)
+        }
+    }
+}
+
+impl Hit for Cube {
+    fn hit(&self, r: &Ray, mut t_min: f64, mut t_max: f64) -> Option<HitRecord> {
+        let inv_d = 1.0 / r.dir;
+        let t1 = (self.bbox.min - r.origin) * inv_d;
+        let t2 = (self.bbox.max - r.origin) * inv_d;
+
+        for i in 0..3 {
+            t_min = t1[i].min(t2[i]).max(t_min);
+            t_max = t1[i].max(t2[i]).min(t_max);
+        }
+
+        if t_min >= t_max {
+            return None;
+        }
+
+        let point = r.at(t_min);
+        let normal = self.bbox.get_normal(&point);
+        let (u, v) = self.face_uv(&point);
+        let mut rec = HitRecord {
+            t: t_min,
+            mat: resolve(&self.mat),
+            normal,
+            point,
+            u,
+            v,
+            barycentric: None,
+            front_face: false,
+            velocity: Vec3::ZERO,
+        };
+        rec.set_face_normal(r, normal);
+
+        Some(rec)
+    }
+
+    fn bounding_box(&self, _: f64, _: f64) -> Option<AABB> {
+        Some(self.bbox)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Triangle {
+    vertices: [Point3; 3],
+    normals: Option<[Vec3; 3]>,
+    uvs: Option<[(f64, f64); 3]>,
+    mat: Arc<dyn Material>,
+}
+
+impl Triangle {
+    pub fn new(vertices: [Point3; 3], mat: Arc<dyn Material>) -> Self {
+        Self {
+            vertices,
+            normals: None,
+            uvs: None,
+            mat,
+        }
+    }
+
+    /// Shades the triangle with the vertex normals interpolated across it
+    /// instead of its flat geometric normal.
+    pub fn with_normals(self, normals: [Vec3; 3]) -> Self {
+        Self {
+            normals: Some(normals),
+            ..self
+        }
+    }
+
+    /// Texture coordinates of the vertices, interpolated across the triangle.
+    /// Without them hits report their barycentric coordinates as uv.
+    pub fn with_uvs(self, uvs: [(f64, f64); 3]) -> Self {
+        Self {
+            uvs: Some(uvs),
+            ..self
+        }
+    }
+}
+
+impl Hit for Triangle {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        // Moller-Trumbore
+        let [v0, v1, v2] = self.vertices;
+        let e1 = v1 - v0;
+        let e2 = v2 - v0;
+        let p = r.dir.cross(e2);
+        let det = e1.dot(p);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+
+        let inv_det = 1.0 / det;
+        let s = r.origin - v0;
+        let u = s.dot(p) * inv_det;
+        if !is_campled(u, 0.0, 1.0) {
+            return None;
+        }
+
+        let q = s.cross(e1);
+        let v = r.dir.dot(q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = e2.dot(q) * inv_det;
+        if !is_campled(t, t_min, t_max) {
+            return None;
+        }
+
+        let normal = e1.cross(e2).normalize();
+        let mut rec = HitRecord {
+            point: r.at(t),
+            normal,
+            t,
+            u,
+            v,
+            barycentric: Some((u, v)),
+            front_face: false,
+            mat: resolve(&self.mat),
+            velocity: Vec3::ZERO,
+        };
+        rec.set_face_normal(r, normal);
+
+        if self.normals.is_none() && self.uvs.is_none() {
+            return Some(rec);
+        }
+
+        // the geometric normal tells the side, the shading normal is smooth
+        let (w0, w1, w2) = barycentric(rec.point, v0, v1, v2);
+        if let Some([n0, n1, n2]) = self.normals {
+            let shading = (w0 * n0 + w1 * n1 + w2 * n2).normalize();
+            rec.normal = if rec.front_face { shading } else { -shading };
+        }
+        if let Some([uv0, uv1, uv2]) = self.uvs {
+            rec.u = w0 * uv0.0 + w1 * uv1.0 + w2 * uv2.0;
+            rec.v = w0 * uv0.1 + w1 * uv1.1 + w2 * uv2.1;
+        }
+        Some(rec)
+    }
+
+    fn bounding_box(&self, _: f64, _: f64) -> Option<AABB> {
+        Some(AABB::from_points(&self.vertices)?.expand(0.0001))
+    }
+}
+
+// relative precision of the sphere tracing of a torus, and its step limit
+const TORUS_EPSILON: f64 = 1e-6;
+const TORUS_MAX_STEPS: usize = 256;
+
+/// Ring around `axis` through `center`, the points at `minor_radius` of its
+/// circle of `major_radius`. Rays are sphere traced with the exact distance
+/// to the torus inside its bounding sphere, and the root found is polished
+/// by Newton steps on the quartic of the ray torus intersection. Rays that
+/// graze the surface for longer than the march allows get the first root of
+/// the quartic bracketed along the rest of the ray.
+#[derive(Clone, Debug)]
+pub struct Torus {
+    pub center: Point3,
+    pub axis: Vec3,
+    pub major_radius: f64,
+    pub minor_radius: f64,
+    pub mat: Arc<dyn Material>,
+    // axes of the local frame orthogonal to `axis`
+    frame: (Vec3, Vec3),
+}
+
+impl Torus {
+    pub fn new(
+        center: Point3,
+        axis: Vec3,
+        major_radius: f64,
+        minor_radius: f64,
+        mat: Arc<dyn Material>,
+    ) -> Self {
+        let axis = axis.normalize();
+        Self {
+            center,
+            axis,
+            major_radius,
+            minor_radius,
+            mat,
+            frame: axis.orthonormal_pair(),
+        }
+    }
+
+    // coordinates of `v` in a frame whose z axis is the axis of the torus
+    fn to_local(&self, v: Vec3) -> Vec3 {
+        let (a, b) = self.frame;
+        vec3(v.dot(a), v.dot(b), v.dot(self.axis))
+    }
+
+    fn to_world(&self, v: Vec3) -> Vec3 {
+        let (a, b) = self.frame;
+        v.x * a + v.y * b + v.z * self.axis
+    }
+
+    // signed distance of the local point `p` to the surface
+    fn distance(&self, p: Point3) -> f64 {
+        (p.x.hypot(p.y) - self.major_radius).hypot(p.z) - self.minor_radius
+    }
+
+    // quartic (|p|^2 + R^2 - r^2)^2 - 4 R^2 (x^2 + y^2) along the ray and its derivative
+    fn quartic(&self, o: Point3, d: Vec3, s: f64) -> (f64, f64) {
+        let p = o + s * d;
+        let r2 = self.major_radius * self.major_radius;
+        let k = p.squared_norm() + r2 - self.minor_radius * self.minor_radius;
+        (
+            k * k - 4.0 * r2 * (p.x * p.x + p.y * p.y),
+            4.0 * k * p.dot(d) - 8.0 * r2 * (p.x * d.x + p.y * d.y),
+        )
+    }
+
+    // first root of the quartic in [start, end], bracketed between the points
+    // where it is extremal and found by Newton steps falling back on bisection
+    // when they leave the bracket
+    fn bracketed_root(&self, o: Point3, d: Vec3, start: f64, end: f64, eps: f64) -> Option<f64> {
+        // (s^2 + b s + c)^2 - 4 R^2 (a2 s^2 + a1 s + a0), its derivative over 4
+        let r2 = self.major_radius * self.major_radius;
+        let (b, c) = (
+            2.0 * o.dot(d),
+            o.squared_norm() + r2 - self.minor_radius * self.minor_radius,
+        );
+        let (a2, a1) = (d.x * d.x + d.y * d.y, 2.0 * (o.x * d.x + o.y * d.y));
+        let mut bounds = cubic_roots(
+            1.5 * b,
+            (b * b + 2.0 * c - 4.0 * r2 * a2) / 2.0,
+            (2.0 * b * c - 4.0 * r2 * a1) / 4.0,
+        );
+        bounds.retain(|s| *s > start && *s < end);
+        bounds.push(end);
+        bounds.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        let mut lo = start;
+        let f_lo = self.quartic(o, d, lo).0;
+        let mut hi = bounds.into_iter().find(|s| {
+            let crossed = self.quartic(o, d, *s).0.signum() != f_lo.signum();
+            if !crossed {
+                lo = *s;
+            }
+            crossed
+        })?;
+
+        let mut s = 0.5 * (lo + hi);
+        while hi - lo > eps {
+            let (f, df) = self.quartic(o, d, s);
+            if f.signum() == f_lo.signum() {
+                lo = s;
+            } else {
+                hi = s;
+            }
+            let newton = s - f / df;
+            s = if newton > lo && newton < hi {
+                newton
+            } else {
+                0.5 * (lo + hi)
+            };
+            if (f / df).abs() < eps {
+                break;
+            }
+        }
+        Some(s)
+    }
+}
+
+// real roots of s^3 + a s^2 + b s + c
+fn cubic_roots(a: f64, b: f64, c: f64) -> Vec<f64> {
+    // t^3 + p t + q with s = t - a / 3
+    let p = b - a * a / 3.0;
+    let q = 2.0 * a * a * a / 27.0 - a * b / 3.0 + c;
+    let discriminant = q * q / 4.0 + p * p * p / 27.0;
+    let roots = if discriminant > 0.0 {
+        let sqrtd = discriminant.sqrt();
+        vec![(-q / 2.0 + sqrtd).cbrt() + (-q / 2.0 - sqrtd).cbrt()]
+    } else if p == 0.0 {
+        vec![0.0]
+    } else {
+        let m = 2.0 * (-p / 3.0).sqrt();
+        let theta = (3.0 * q / (p * m)).clamp(-1.0, 1.0).acos() / 3.0;
+        (0..3)
+            .map(|k| m * (theta - 2.0 * PI * k as f64 / 3.0).cos())
+            .collect()
+    };
+
+    roots.into_iter().map(|t| t - a / 3.0).collect()
+}
+
+impl Hit for Torus {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        // unit speed local ray, its parameter is `len` times the one of `r`
+        let len = r.dir.norm();
+        let o = self.to_local(r.origin - self.center);
+        let d = self.to_local(r.dir / len);
+
+        let bound = self.major_radius + self.minor_radius;
+        let half_b = o.dot(d);
+        let discriminant = half_b * half_b - (o.squared_norm() - bound * bound);
+        if discriminant < 0.0 {
+            return None;
+        }
+        let eps = TORUS_EPSILON * self.minor_radius;
+        let sqrtd = discriminant.sqrt();
+        let end = (-half_b + sqrtd + eps).min(t_max * len);
+        let enter = -half_b - sqrtd;
+        let mut s = enter.max(t_min * len);
+
+        // a ray leaving the surface starts closer than `eps` to it, a root
+        // only counts once the march got away from its origin
+        let mut away = enter > t_min * len;
+        let mut converged = false;
+        for _ in 0..TORUS_MAX_STEPS {
+            let dist = self.distance(o + s * d).abs();
+            if dist >= eps {
+                away = true;
+            } else if away {
+                converged = true;
+                break;
+            }
+            s += dist.max(eps);
+            if s > end {
+                return None;
+            }
+        }
+        if !converged {
+            s = self.bracketed_root(o, d, s, end, eps)?;
+        }
+
+        for _ in 0..2 {
+            let (f, df) = self.quartic(o, d, s);
+            if df != 0.0 && (f / df).abs() < eps {
+                s -= f / df;
+            }
+        }
+        let t = s / len;
+        if !is_campled(t, t_min, t_max) {
+            return None;
+        }
+
+        let p = o + s * d;
+        let ring = self.major_radius * vec3(p.x, p.y, 0.0).normalize();
+        let local_normal = (p - ring).normalize();
+        let outward_normal = self.to_world(local_normal);
+        let mut rec = HitRecord {
+            point: r.at(t),
+            normal: outward_normal,
+            t,
+            u: p.y.atan2(p.x) / (2.0 * PI) + 0.5,
+            v: p.z.atan2(p.x.hypot(p.y) - self.major_radius) / (2.0 * PI) + 0.5,
+            barycentric: None,
+            front_face: false,
+            mat: resolve(&self.mat),
+            velocity: Vec3::ZERO,
+        };
+        rec.set_face_normal(r, outward_normal);
+        Some(rec)
+    }
+
+    fn bounding_box(&self, _: f64, _: f64) -> Option<AABB> {
+        let extent = |a: f64| self.major_radius * (1.0 - a * a).max(0.0).sqrt() + self.minor_radius;
+        let half = vec3(
+            extent(self.axis.x),
+            extent(self.axis.y),
+            extent(self.axis.z),
+        );
+        Some(AABB::new(self.center - half, self.center + half))
+    }
+
+    fn bounding_sphere(&self) -> Option<(Point3, f64)> {
+        Some((self.center, self.major_radius + self.minor_radius))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_build_tree() {
+        let world: HittableList = vec![
+            Arc::new(Sphere::new(
+                vec3(-10.0, 0.0, 0.0),
+                2.5,
+                Arc::new(Lambertian::from_color(Color::new(255, 0, 0, 255))),
+            )),
+            Arc::new(Sphere::new(
+                vec3(0.0, 0.0, 0.0),
+                2.5,
+                Arc::new(Lambertian::from_color(Color::new(0, 255, 0, 255))),
+            )),
+            Arc::new(Sphere::new(
+                vec3(10.0, 0.0, 0.0),
+                2.5,
+                Arc::new(Lambertian::from_color(Color::new(0, 0, 255, 255))),
+            )),
+        ];
+
+        let tree = BVTree::new(world);
+        println!("{}", tree);
+
+        let r = ray(vec3(0.0, -10.0, 0.0), vec3(0.0, 1.0, 0.0), 0.0);
+        println!("{:?}", tree.hit(&r, 0.0001, f64::INFINITY));
+    }
+
+    #[test]
+    #[should_panic(expected = "no object in bounding volume tree")]
+    fn test_empty_tree() {
+        BVTree::new(HittableList::new());
+    }
+
+    #[test]
+    fn test_bounding_sphere() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::from_color(Color::WHITE));
+        let world: HittableList = vec![
+            Arc::new(Sphere::new(vec3(-2.0, 0.0, 0.0), 1.0, mat.clone())),
+            Arc::new(Sphere::new(vec3(2.0, 0.0, 0.0), 1.0, mat)),
+        ];
+
+        let (center, radius) = world.bounding_sphere().unwrap();
+        for x in [-2.0, 2.0].iter() {
+            assert!((vec3(*x, 0.0, 0.0) - center).norm() + 1.0 <= radius + 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_optimize_for() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::from_color(Color::WHITE));
+        let mut world: HittableList = (0..5)
+            .map(|i| -> Arc<dyn Hit> {
+                Arc::new(Sphere::new(
+                    vec3(0.0, 0.0, 10.0 - 2.0 * i as f64),
+                    0.5,
+                    mat.clone(),
+                ))
+            })
+            .collect();
+        let original = world.clone();
+        let camera = Camera::new(
+            40.0,
+            vec3(0.0, 0.0, -5.0),
+            Vec3::ZERO,
+            1.0,
+            0.0,
+            5.0,
+            0.0,
+            0.0,
+        );
+
+        optimize_for(&mut world, &camera);
+
+        assert_eq!(world.len(), original.len());
+        for object in &original {
+            assert!(world.iter().any(|o| Arc::ptr_eq(o, object)));
+        }
+
+        for d in [
+            vec3(0.0, 0.0, 1.0),
+            vec3(0.01, 0.0, 1.0),
+            vec3(1.0, 0.0, 0.0),
+        ]
+        .iter()
+        {
+            let r = ray(camera.eye(), *d, 0.0);
+            let expected = original.hit(&r, 0.001, f64::INFINITY).map(|rec| rec.t);
+            assert_eq!(
+                world.hit(&r, 0.001, f64::INFINITY).map(|rec| rec.t),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_rect_pdf_value() {
+        let light = XZRect::new(
+            (-1.0, 1.0),
+            (-1.0, 1.0),
+            2.0,
+            Arc::new(DiffuseLight::from_color(Color::WHITE, 1.0)),
+        );
+
+        // hit at distance^2 = 4.5 with a cosine of 2 / sqrt(4.5) on an area of 4
+        let expected = 4.5 * 4.5f64.sqrt() / 8.0;
+        let pdf = light.pdf_value(Vec3::ZERO, vec3(0.5, 2.0, 0.5));
+        assert!((pdf - expected).abs() < 1e-9);
+        assert!((light.pdf_value(Vec3::ZERO, vec3(1.0, 4.0, 1.0)) - expected).abs() < 1e-9);
+        assert_eq!(light.pdf_value(Vec3::ZERO, vec3(0.0, -1.0, 0.0)), 0.0);
+
+        // the density integrates to one over the sphere of directions
+        let n = 200_000;
+        let sum: f64 = (0..n)
+            .map(|_| {
+                let z = random_range(-1.0, 1.0);
+                let phi = random_range(0.0, 2.0 * PI);
+                let r = (1.0 - z * z).sqrt();
+                light.pdf_value(Vec3::ZERO, vec3(r * phi.cos(), r * phi.sin(), z))
+            })
+            .sum();
+        assert!((4.0 * PI * sum / n as f64 - 1.0).abs() < 0.05);
+
+        for _ in 0..100 {
+            assert!(light.pdf_value(Vec3::ZERO, light.random_toward(Vec3::ZERO)) > 0.0);
+        }
+    }
+
+    #[test]
+    fn test_cube_face_uv() {
+        let cube = Cube::new(
+            vec3(-1.0, -2.0, -3.0),
+            vec3(1.0, 2.0, 3.0),
+            Arc::new(Lambertian::default()),
+        );
+
+        for axis in 0..3 {
+            for side in [-1.0, 1.0].iter() {
+                let mut dir = Vec3::ZERO;
+                match axis {
+                    0 => dir.x = -side,
+                    1 => dir.y = -side,
+                    _ => dir.z = -side,
+                }
+                let rec = cube
+                    .hit(&ray(-10.0 * dir, dir, 0.0), 0.001, f64::INFINITY)
+                    .unwrap();
+
+                assert!((rec.u - 0.5).abs() < 1e-9 && (rec.v - 0.5).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn test_aabb_merge() {
+        let a = AABB::new(vec3(0.0, 0.0, 0.0), vec3(1.0, 1.0, 1.0));
+        let b = AABB::new(vec3(2.0, -1.0, 3.0), vec3(4.0, 0.5, 5.0));
+
+        let merged = a.merge(b);
+        assert_eq!(merged.min, vec3(0.0, -1.0, 0.0));
+        assert_eq!(merged.max, vec3(4.0, 1.0, 5.0));
+
+        let identity = AABB::empty().merge(a);
+        assert_eq!((identity.min, identity.max), (a.min, a.max));
+
+        let padded = a.expand(0.5);
+        assert_eq!(padded.min, vec3(-0.5, -0.5, -0.5));
+        assert_eq!(padded.max, vec3(1.5, 1.5, 1.5));
+    }
+
+    #[test]
+    fn test_collect_boxed_objects() {
+        let sphere = |x: f64| -> Box<dyn Hit> {
+            Box::new(Sphere::new(
+                vec3(x, 0.0, 0.0),
+                1.0,
+                Arc::new(Lambertian::default()),
+            ))
+        };
+
+        let mut world: HittableList = (0..3).map(|i| sphere(i as f64)).collect();
+        assert_eq!(world.len(), 3);
+
+        world.extend(vec![sphere(5.0), sphere(6.0)]);
+        assert_eq!(world.len(), 5);
+    }
+
+    #[test]
+    fn test_visibility_mask() {
+        let catcher = Visibility::new(
+            Arc::new(XZRect::new(
+                (-5.0, 5.0),
+                (-5.0, 5.0),
+                0.0,
+                Arc::new(Lambertian::default()),
+            )),
+            VisibilityMask {
+                camera: false,
+                ..VisibilityMask::ALL
+            },
+        );
+        let camera = Camera::new(
+            40.0,
+            vec3(0.0, 3.0, -3.0),
+            Vec3::ZERO,
+            1.0,
+            0.0,
+            3.0,
+            0.0,
+            0.0,
+        );
+
+        let primary = camera.get_ray(0.5, 0.5);
+        assert_eq!(primary.kind, RayKind::Camera);
+        assert!(catcher.hit(&primary, 0.001, f64::INFINITY).is_none());
+
+        let down = ray(vec3(0.0, 1.0, 0.0), vec3(0.0, -1.0, 0.0), 0.0);
+        let shadow = down.with_kind(RayKind::Shadow);
+        assert!(catcher.hit(&shadow, 0.001, f64::INFINITY).is_some());
+        assert!(catcher.hit(&down, 0.001, f64::INFINITY).is_some());
+    }
+
+    #[test]
+    fn test_lod_proxy() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::default());
+        let cube = Arc::new(Cube::new(-Vec3::ONE, Vec3::ONE, mat.clone()));
+        let lod = Lod::new(cube, mat, 50.0);
+
+        // passes between a corner of the cube and its bounding sphere
+        let dir = vec3(0.0, 0.0, 1.0);
+        let far = ray(vec3(1.2, 1.2, -100.0), dir, 0.0);
+        let near = ray(vec3(1.2, 1.2, -10.0), dir, 0.0);
+        assert!(lod.hit(&far, 0.001, f64::INFINITY).is_some());
+        assert!(lod.hit(&near, 0.001, f64::INFINITY).is_none());
+        assert!(lod
+            .hit(&far.with_kind(RayKind::Camera), 0.001, f64::INFINITY)
+            .is_none());
+
+        let exact = ray(vec3(0.5, 0.5, -10.0), dir, 0.0);
+        let rec = lod.hit(&exact, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 9.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_triangle_vertex_normals() {
+        let vertices = [
+            vec3(0.0, 0.0, 0.0),
+            vec3(1.0, 0.0, 0.0),
+            vec3(0.0, 1.0, 0.0),
+        ];
+        let normals = [
+            vec3(-1.0, 0.0, 1.0).normalize(),
+            vec3(1.0, 0.0, 1.0).normalize(),
+            vec3(0.0, 1.0, 1.0).normalize(),
+        ];
+        let triangle =
+            Triangle::new(vertices, Arc::new(Lambertian::default())).with_normals(normals);
+        let centroid = (vertices[0] + vertices[1] + vertices[2]) / 3.0;
+        let r = ray(centroid + vec3(0.0, 0.0, 1.0), vec3(0.0, 0.0, -1.0), 0.0);
+
+        let rec = triangle.hit(&r, 0.001, f64::INFINITY).unwrap();
+        let expected = ((normals[0] + normals[1] + normals[2]) / 3.0).normalize();
+        assert!((rec.normal - expected).norm() < 1e-9);
+
+        let flat = Triangle::new(vertices, Arc::new(Lambertian::default()));
+        let rec = flat.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.normal - vec3(0.0, 0.0, 1.0)).norm() < 1e-9);
+    }
+
+    #[test]
+    fn test_triangle_uvs() {
+        let mut img = image::RgbaImage::new(2, 2);
+        img.put_pixel(1, 1, image::Rgba([255, 0, 0, 255]));
+        let texture = ImageTexture::new(img);
+        let triangle = Triangle::new(
+            [
+                vec3(0.0, 0.0, 0.0),
+                vec3(1.0, 0.0, 0.0),
+                vec3(0.0, 1.0, 0.0),
+            ],
+            Arc::new(Lambertian::default()),
+        )
+        .with_uvs([(0.6, 0.1), (0.9, 0.1), (0.9, 0.4)]);
+        let r = ray(vec3(1.0 / 3.0, 1.0 / 3.0, 1.0), vec3(0.0, 0.0, -1.0), 0.0);
+
+        let rec = triangle.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.u - 0.8).abs() < 1e-9 && (rec.v - 0.2).abs() < 1e-9);
+        let texel = texture.texel(rec.u, rec.v, &rec.point);
+        assert_eq!((texel.r, texel.g, texel.b), (255, 0, 0));
+    }
+
+    #[test]
+    fn test_refit() {
+        let sphere = |x: f64| -> Arc<dyn Hit> {
+            Arc::new(Sphere::new(
+                vec3(x, 0.0, 0.0),
+                1.0,
+                Arc::new(Lambertian::default()),
+            ))
+        };
+        let mut tree = BVTree::new(vec![sphere(-5.0), sphere(0.0), sphere(5.0)]);
+        let down = |x: f64| ray(vec3(x, 10.0, 0.0), vec3(0.0, -1.0, 0.0), 0.0);
+
+        for object in tree.objects_mut() {
+            if object.bounding_sphere().unwrap().0.x == 5.0 {
+                *object = sphere(20.0);
+            }
+        }
+        tree.refit();
+
+        assert!(tree.hit(&down(20.0), 0.001, f64::INFINITY).is_some());
+        assert!(tree.hit(&down(5.0), 0.001, f64::INFINITY).is_none());
+        assert!(tree.hit(&down(-5.0), 0.001, f64::INFINITY).is_some());
+
+        tree.rebuild();
+        assert_eq!(tree.objects_count, 3);
+        assert!(tree.hit(&down(20.0), 0.001, f64::INFINITY).is_some());
+    }
+
+    #[test]
+    fn test_uniform_heterogeneous_medium_matches_constant() {
+        let phase: Arc<dyn Material> = Arc::new(Isotropic::from_color(Color::WHITE));
+        let boundary: Arc<dyn Hit> = Arc::new(Sphere::new(Vec3::ZERO, 1.0, phase.clone()));
+        let constant = ConstantMedium::new(boundary.clone(), 0.5, phase.clone());
+        let uniform: Arc<dyn Texture> = Arc::new(SolidColor::new(Color::WHITE));
+        let heterogeneous = HeterogeneousMedium::new(boundary, uniform, 0.5, phase);
+
+        let r = ray(vec3(0.0, 0.0, -2.0), vec3(0.0, 0.0, 2.0), 0.0);
+        let n = 20000;
+        let count = |medium: &dyn Hit| {
+            let hits = (0..n).filter_map(|_| medium.hit(&r, 0.001, f64::INFINITY));
+            let (count, depth) = hits.fold((0, 0.0), |(c, d), rec| (c + 1, d + rec.t));
+            (count as f64 / n as f64, depth / count as f64)
+        };
+
+        // through 2 units of fog of density 0.5
+        let expected = 1.0 - (-1.0f64).exp();
+        let (p_constant, t_constant) = count(&constant);
+        let (p_heterogeneous, t_heterogeneous) = count(&heterogeneous);
+        assert!((p_constant - expected).abs() < 0.02);
+        assert!((p_heterogeneous - expected).abs() < 0.02);
+        assert!((t_constant - t_heterogeneous).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_volume_medium_density_is_exact() {
+        let phase: Arc<dyn Material> = Arc::new(Isotropic::from_color(Color::WHITE));
+        let boundary: Arc<dyn Hit> = Arc::new(Sphere::new(Vec3::ZERO, 1.0, phase.clone()));
+        let data = vec![0.3, 0.7, 0.123, 0.999, 0.0, 0.5, 0.25, 1.0];
+        let volume = Arc::new(Volume3DTexture::new(
+            data,
+            (2, 2, 2),
+            vec3(-1.0, -1.0, -1.0),
+            Vec3::ONE,
+        ));
+        let medium = HeterogeneousMedium::new(boundary, volume.clone(), 0.5, phase);
+
+        for _ in 0..100 {
+            let p = Vec3::random(-1.0, 1.0);
+            assert_eq!(medium.density_at(&p), volume.sample(&p));
+        }
+    }
+
+    #[test]
+    fn test_cached_bounds() {
+        use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+
+        // sphere counting the computations of its box
+        #[derive(Debug)]
+        struct Counted(Sphere, Arc<AtomicUsize>);
+
+        impl Hit for Counted {
+            fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+                self.0.hit(r, t_min, t_max)
+            }
+
+            fn bounding_box(&self, t1: f64, t2: f64) -> Option<AABB> {
+                self.1.fetch_add(1, SeqCst);
+                self.0.bounding_box(t1, t2)
+            }
+        }
+
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::from_color(Color::WHITE));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let sphere = |center| -> Arc<dyn Hit> {
+            Arc::new(Counted(
+                Sphere::new(center, 1.0, mat.clone()),
+                calls.clone(),
+            ))
+        };
+        let mut cached = CachedBounds::new(sphere(Vec3::ZERO));
+
+        let bbox = cached.bounding_box(0.0, 0.0).unwrap();
+        assert_eq!((bbox.min, bbox.max), (-Vec3::ONE, Vec3::ONE));
+        for _ in 0..10 {
+            let again = cached.bounding_box(0.0, 0.0).unwrap();
+            assert_eq!((again.min, again.max), (bbox.min, bbox.max));
+        }
+        assert_eq!(calls.load(SeqCst), 1);
+
+        cached.set_object(sphere(vec3(2.0, 0.0, 0.0)));
+        let moved = cached.bounding_box(0.0, 0.0).unwrap();
+        assert_eq!(moved.min, vec3(1.0, -1.0, -1.0));
+        assert_eq!(moved.max, vec3(3.0, 1.0, 1.0));
+        cached.bounding_box(0.0, 0.0);
+        assert_eq!(calls.load(SeqCst), 2);
+
+        let lod = Lod::new(sphere(Vec3::ZERO), mat.clone(), 10.0);
+        let before = calls.load(SeqCst);
+        for _ in 0..10 {
+            lod.bounding_box(0.0, 0.0);
+        }
+        assert_eq!(calls.load(SeqCst), before + 1);
+    }
+
+    #[test]
+    fn test_test_budget() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::from_color(Color::WHITE));
+        let world: HittableList = (0..16)
+            .map(|i| -> Arc<dyn Hit> {
+                Arc::new(Sphere::new(
+                    vec3(4.0 * i as f64, 0.0, 0.0),
+                    1.0,
+                    mat.clone(),
+                ))
+            })
+            .collect();
+        let tree = BVTree::new(world.clone());
+        let r = ray(vec3(-5.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), 0.0);
+        let exact = tree.hit(&r, 0.001, f64::INFINITY).unwrap().t;
+
+        let mut generous = TestBudget::new(1000);
+        let rec = tree.hit_probed(&r, 0.001, f64::INFINITY, &mut generous);
+        assert_eq!(rec.unwrap().t, exact);
+        assert!(generous.remaining > 0);
+
+        for tests in 0..8 {
+            for rec in [
+                tree.hit_probed(&r, 0.001, f64::INFINITY, &mut TestBudget::new(tests)),
+                world.hit_probed(&r, 0.001, f64::INFINITY, &mut TestBudget::new(tests)),
+            ] {
+                assert!(rec.is_none_or(|rec| rec.t >= exact));
+            }
+        }
+        assert!(world
+            .hit_probed(&r, 0.001, f64::INFINITY, &mut TestBudget::new(0))
+            .is_none());
+    }
+
+    #[test]
+    fn test_probe_counts_tests() {
+        #[derive(Default)]
+        struct Counter {
+            nodes: Vec<usize>,
+            primitives: usize,
+            hits: usize,
+        }
+
+        impl Probe for Counter {
+            fn node(&mut self, id: usize, _hit: bool) {
+                self.nodes.push(id);
+            }
+
+            fn primitive(&mut self, _object: &dyn Hit, hit: bool) {
+                self.primitives += 1;
+                self.hits += hit as usize;
+            }
+        }
+
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::from_color(Color::WHITE));
+        let world: HittableList = (0..3)
+            .map(|i| -> Arc<dyn Hit> {
+                Arc::new(Sphere::new(
+                    vec3(4.0 * i as f64, 0.0, 0.0),
+                    1.0,
+                    mat.clone(),
+                ))
+            })
+            .collect();
+        let r = ray(vec3(-5.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), 0.0);
+
+        let mut counter = Counter::default();
+        let rec = world.hit_probed(&r, 0.001, f64::INFINITY, &mut counter);
+        assert_eq!(
+            rec.unwrap().t,
+            world.hit(&r, 0.001, f64::INFINITY).unwrap().t
+        );
+        assert_eq!((counter.primitives, counter.hits), (3, 1));
+
+        // the ray goes through the boxes of all the 5 nodes and 3 leaves
+        let tree = BVTree::new(world);
+        let mut counter = Counter::default();
+        let rec = tree.hit_probed(&r, 0.001, f64::INFINITY, &mut counter);
+        assert_eq!(rec.unwrap().t, 4.0);
+        assert_eq!(counter.nodes.len(), 5);
+        assert_eq!(counter.nodes[0], tree.root_id);
+        assert_eq!((counter.primitives, counter.hits), (3, 3));
+    }
+
+    #[test]
+    fn test_sphere_list_matches_hittable_list() {
+        let world: HittableList = (0..50)
+            .map(|_| -> Arc<dyn Hit> {
+                let mat = Arc::new(Lambertian::from_color(Color::random()));
+                Arc::new(Sphere::new(
+                    Vec3::random(-10.0, 10.0),
+                    random_range(0.2, 2.0),
+                    mat,
+                ))
+            })
+            .collect();
+        let spheres = SphereList::from_list(&world).unwrap();
+        assert_eq!(spheres.len(), 50);
+
+        for _ in 0..1000 {
+            let r = ray(Vec3::random(-15.0, 15.0), Vec3::random_unit_sphere(), 0.0);
+            let expected = world.hit(&r, 0.001, f64::INFINITY);
+            let rec = spheres.hit(&r, 0.001, f64::INFINITY);
+            assert_eq!(rec.is_some(), expected.is_some());
+            if let (Some(rec), Some(expected)) = (rec, expected) {
+                assert_eq!(
+                    (rec.t, rec.point, rec.normal),
+                    (expected.t, expected.point, expected.normal)
+                );
+                assert!(Arc::ptr_eq(&rec.mat, &expected.mat));
+            }
+        }
+
+        let boxes = (
+            world.bounding_box(0.0, 0.0).unwrap(),
+            spheres.bounding_box(0.0, 0.0).unwrap(),
+        );
+        assert_eq!((boxes.0.min, boxes.0.max), (boxes.1.min, boxes.1.max));
+
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::default());
+        let mixed: HittableList = vec![
+            Arc::new(Sphere::new(Vec3::ZERO, 1.0, mat.clone())),
+            Arc::new(XYRect::new((0.0, 1.0), (0.0, 1.0), 0.0, mat)),
+        ];
+        assert!(SphereList::from_list(&mixed).is_none());
+
+        let origin = vec3(0.0, 20.0, 0.0);
+        for _ in 0..100 {
+            let dir = spheres.random_toward(origin);
+            assert!(world
+                .hit(&ray(origin, dir, 0.0), 0.001, f64::INFINITY)
+                .is_some());
+            assert!((spheres.pdf_value(origin, dir) - world.pdf_value(origin, dir)).abs() < 1e-12);
+        }
+
+        let empty = HittableList::new();
+        assert_eq!(empty.pdf_value(origin, Vec3::ONE), 0.0);
+        assert_eq!(empty.random_toward(origin), vec3(1.0, 0.0, 0.0));
+    }
+
+    // cargo test --release bench_sphere_list -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_sphere_list() {
+        let world: HittableList = (0..500)
+            .map(|_| -> Arc<dyn Hit> {
+                let mat = Arc::new(Lambertian::default());
+                Arc::new(Sphere::new(Vec3::random(-50.0, 50.0), 1.0, mat))
+            })
+            .collect();
+        let spheres = SphereList::from_list(&world).unwrap();
+        let rays: Vec<Ray> = (0..20000)
+            .map(|_| ray(Vec3::ZERO, Vec3::random_unit_sphere(), 0.0))
+            .collect();
+
+        let time = |object: &dyn Hit| {
+            let clock = std::time::Instant::now();
+            let hits = rays
+                .iter()
+                .filter(|r| object.hit(r, 0.001, f64::INFINITY).is_some())
+                .count();
+            (clock.elapsed(), hits)
+        };
+        let (list, list_hits) = time(&world);
+        let (soa, soa_hits) = time(&spheres);
+        assert_eq!(list_hits, soa_hits);
+        println!(
+            "HittableList: {:?}, SphereList: {:?} (x{:.1})",
+            list,
+            soa,
+            list.as_secs_f64() / soa.as_secs_f64()
+        );
+    }
+
+    // cargo test --release bench_dot -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_dot() {
+        let points: Vec<Vec3> = (0..1_000_000).map(|_| Vec3::random(-1.0, 1.0)).collect();
+        let n = vec3(0.3, -0.5, 0.8).normalize();
+
+        let time = |f: &dyn Fn(&Vec3) -> f64| {
+            let clock = std::time::Instant::now();
+            let sum: f64 = points.iter().map(f).sum();
+            (clock.elapsed(), sum)
+        };
+        let (method, method_sum) = time(&|p| p.dot(n).abs() + p.squared_norm());
+        let (manual, manual_sum) = time(&|p| {
+            (p.x * n.x + p.y * n.y + p.z * n.z).abs() + p.x * p.x + p.y * p.y + p.z * p.z
+        });
+        assert!((method_sum - manual_sum).abs() < 1e-6 * manual_sum);
+        println!(
+            "Vec3::dot: {:?}, written out: {:?} (x{:.2})",
+            method,
+            manual,
+            method.as_secs_f64() / manual.as_secs_f64()
+        );
+    }
+
+    #[test]
+    fn test_aabb_intersect_interval() {
+        let bbox = AABB::new(-0.5 * Vec3::ONE, 0.5 * Vec3::ONE);
+        let r = ray(vec3(-2.0, 0.0, 0.0), vec3(2.0, 0.0, 0.0), 0.0);
+
+        assert_eq!(
+            bbox.intersect_interval(&r, 0.0, f64::INFINITY),
+            Some((0.75, 1.25))
+        );
+        assert_eq!(
+            bbox.intersect_interval(&r, 1.0, f64::INFINITY),
+            Some((1.0, 1.25))
+        );
+        assert_eq!(bbox.intersect_interval(&r, 0.0, 0.5), None);
+
+        let miss = ray(vec3(-2.0, 1.0, 0.0), vec3(1.0, 0.0, 0.0), 0.0);
+        assert_eq!(bbox.intersect_interval(&miss, 0.0, f64::INFINITY), None);
+        assert!(!bbox.hit(&miss, 0.0, f64::INFINITY));
+    }
+
+    #[test]
+    fn test_accelerator_selection() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::default());
+        let scene = |n| -> HittableList {
+            (0..n)
+                .map(|_| -> Arc<dyn Hit> {
+                    Arc::new(Sphere::new(Vec3::random(-20.0, 20.0), 0.5, mat.clone()))
+                })
+                .collect()
+        };
+        let threshold = Config::default().bvh_threshold as usize;
+
+        for (n, bvh) in [(3, false), (1000, true)].iter() {
+            let world = scene(*n);
+            let accelerator = Accelerator::build(world.clone(), threshold);
+            assert_eq!(matches!(accelerator, Accelerator::Bvh(_)), *bvh);
+            assert_eq!(matches!(accelerator, Accelerator::Spheres(_)), !*bvh);
+            assert_eq!(accelerator.objects_count(), *n);
+
+            for _ in 0..200 {
+                let r = ray(Vec3::ZERO, Vec3::random_unit_sphere(), 0.0);
+                let expected = world.hit(&r, 0.001, f64::INFINITY).map(|rec| rec.t);
+                assert_eq!(
+                    accelerator.hit(&r, 0.001, f64::INFINITY).map(|rec| rec.t),
+                    expected
+                );
+            }
+        }
+
+        let mut mixed = scene(3);
+        mixed.push(Arc::new(XYRect::new((0.0, 1.0), (0.0, 1.0), 0.0, mat)));
+        let accelerator = Accelerator::build(mixed, threshold);
+        assert!(matches!(accelerator, Accelerator::Linear(_)));
+    }
+
+    #[test]
+    fn test_csg_difference_is_concave() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::default());
+        let a: Arc<dyn Hit> = Arc::new(Sphere::new(Vec3::ZERO, 1.0, mat.clone()));
+        let b: Arc<dyn Hit> = Arc::new(Sphere::new(vec3(0.0, 0.0, -1.0), 1.0, mat));
+        let difference = Csg::new(a.clone(), b.clone(), CsgOp::Difference);
+
+        // on the axis the ray enters through the bottom of the bite
+        let r = ray(vec3(0.0, 0.0, -5.0), vec3(0.0, 0.0, 1.0), 0.0);
+        let rec = difference.hit(&r, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 5.0).abs() < 1e-9);
+        assert!(rec.front_face);
+        assert!((rec.normal - vec3(0.0, 0.0, -1.0)).norm() < 1e-9);
+
+        // off axis the normal points toward the center of the subtracted sphere
+        let r = ray(vec3(0.5, 0.0, -5.0), vec3(0.0, 0.0, 1.0), 0.0);
+        let rec = difference.hit(&r, 0.001, f64::INFINITY).unwrap();
+        let z = -1.0 + 0.75f64.sqrt();
+        assert!((rec.point - vec3(0.5, 0.0, z)).norm() < 1e-9);
+        assert!((rec.normal - (vec3(0.0, 0.0, -1.0) - rec.point)).norm() < 1e-9);
+        assert!(rec.front_face);
+
+        // the ray leaves the difference through the surface of `a`
+        let exit = difference.hit(&r, rec.t + 1e-6, f64::INFINITY).unwrap();
+        assert!((exit.point - vec3(0.5, 0.0, 0.75f64.sqrt())).norm() < 1e-9);
+        assert!(!exit.front_face);
+
+        let union = Csg::new(a.clone(), b.clone(), CsgOp::Union);
+        let intersection = Csg::new(a, b.clone(), CsgOp::Intersection);
+        let r = ray(vec3(0.0, 0.0, -5.0), vec3(0.0, 0.0, 1.0), 0.0);
+        assert!((union.hit(&r, 0.001, f64::INFINITY).unwrap().t - 3.0).abs() < 1e-9);
+        assert!((intersection.hit(&r, 0.001, f64::INFINITY).unwrap().t - 4.0).abs() < 1e-9);
+
+        // rays through the bite only miss
+        let r = ray(vec3(-5.0, 0.0, -1.8), vec3(1.0, 0.0, 0.0), 0.0);
+        assert!(b.hit(&r, 0.001, f64::INFINITY).is_some());
+        assert!(difference.hit(&r, 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn test_frustum_cull_keeps_reflections() {
+        let mirror: Arc<dyn Material> = Arc::new(Metal::new(Color::WHITE, 0.0));
+        let red: Arc<dyn Material> = Arc::new(DiffuseLight::from_color(Color::RED, 1.0));
+        let mut world: HittableList = vec![
+            Arc::new(XYRect::new((-10.0, 10.0), (-10.0, 10.0), -5.0, mirror)),
+            // behind the camera
+            Arc::new(Sphere::new(vec3(0.0, 0.0, 5.0), 1.0, red)),
+        ];
+        let camera = Camera::new(
+            40.0,
+            Vec3::ZERO,
+            vec3(0.0, 0.0, -1.0),
+            1.0,
+            0.0,
+            5.0,
+            0.0,
+            0.0,
+        );
+
+        frustum_cull(&mut world, &camera, (400, 400));
+        assert!(format!("{:?}", world[0]).starts_with("XYRect"));
+        assert!(format!("{:?}", world[1]).starts_with("Visibility"));
+
+        let to_sphere = ray(Vec3::ZERO, vec3(0.0, 0.0, 1.0), 0.0);
+        assert!(world
+            .hit(&to_sphere.with_kind(RayKind::Camera), 0.001, f64::INFINITY)
+            .is_none());
+        assert!(world.hit(&to_sphere, 0.001, f64::INFINITY).is_some());
+
+        let color = ray_color(&camera.get_ray(0.5, 0.5), &world, 5, Vec3::ZERO, T_MIN);
+        assert!(color.x > 0.9 && color.y == 0.0 && color.z == 0.0);
+    }
+
+    #[test]
+    fn test_frustum_cull_keeps_moving_sphere_at_edge() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::default());
+        // spans x in -5..-3 at z = -10, the left edge of the frame is at x = -3.64
+        let centers = (vec3(-4.0, 0.0, -10.0), vec3(-4.0, 0.1, -10.0));
+        let mut world: HittableList =
+            vec![Arc::new(MovingSphere::new(centers, 1.0, 0.0, 1.0, mat))];
+        let camera = Camera::new(
+            40.0,
+            Vec3::ZERO,
+            vec3(0.0, 0.0, -1.0),
+            1.0,
+            0.0,
+            10.0,
+            0.0,
+            1.0,
+        );
+
+        frustum_cull(&mut world, &camera, (400, 400));
+        assert!(format!("{:?}", world[0]).starts_with("MovingSphere"));
+        let r = ray(Vec3::ZERO, vec3(-3.3, 0.0, -10.0), 0.0).with_kind(RayKind::Camera);
+        assert!(world.hit(&r, 0.001, f64::INFINITY).is_some());
+    }
+
+    #[test]
+    fn test_frustum_cull_keeps_last_pixel_and_defocus() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::default());
+        let camera = |aperture: f64, focus_dist: f64| {
+            Camera::new(
+                40.0,
+                Vec3::ZERO,
+                vec3(0.0, 0.0, -1.0),
+                1.0,
+                aperture,
+                focus_dist,
+                0.0,
+                0.0,
+            )
+        };
+        let culled = |world: &HittableList| format!("{:?}", world[0]).starts_with("Visibility");
+
+        // past the right edge x = 3.64 of the viewport, in the last of 4 columns
+        let pinhole = camera(0.0, 10.0);
+        let sphere = || -> HittableList {
+            vec![Arc::new(Sphere::new(
+                vec3(5.1, 0.0, -10.0),
+                0.2,
+                mat.clone(),
+            ))]
+        };
+        let mut world = sphere();
+        frustum_cull(&mut world, &pinhole, (4, 4));
+        assert!(!culled(&world));
+        // `get_ray` samples x = 3 + 0.6 of 4 columns at s = 3.6 / 3
+        let r = pinhole.get_ray(1.2, 0.5);
+        assert!(world.hit(&r, 0.001, f64::INFINITY).is_some());
+        let mut world = sphere();
+        frustum_cull(&mut world, &pinhole, (400, 400));
+        assert!(culled(&world));
+
+        // seen through the lens edge at x = -1 far beyond the focus plane
+        let defocused = camera(2.0, 1.0);
+        let mut world: HittableList = vec![Arc::new(Sphere::new(
+            vec3(8.0, 0.0, -10.0),
+            0.5,
+            mat.clone(),
+        ))];
+        frustum_cull(&mut world, &defocused, (400, 400));
+        assert!(!culled(&world));
+        let (s, t) = defocused.project(&vec3(-0.1, 0.0, -1.0)).unwrap();
+        assert!((0.0..=1.0).contains(&s) && (0.0..=1.0).contains(&t));
+        let r = defocused.get_ray_lens(s, t, vec3(-1.0, 0.0, 0.0));
+        assert!(world.hit(&r, 0.001, f64::INFINITY).is_some());
+    }
+
+    #[test]
+    fn test_parallel_bvh_matches_serial() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::default());
+        let world: HittableList = (0..5000)
+            .map(|_| -> Arc<dyn Hit> {
+                Arc::new(Sphere::new(Vec3::random(-50.0, 50.0), 0.5, mat.clone()))
+            })
+            .collect();
+
+        let parallel = BVTree::new(world.clone());
+        let serial = BVTree::new_serial(world);
+        assert_eq!(parallel.to_string(), serial.to_string());
+        for _ in 0..500 {
+            let r = ray(Vec3::ZERO, Vec3::random_unit_sphere(), 0.0);
+            let hit = |tree: &BVTree| tree.hit(&r, 0.001, f64::INFINITY).map(|rec| rec.t);
+            assert_eq!(hit(&parallel), hit(&serial));
+        }
+    }
+
+    // cargo test --release bench_parallel_bvh -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_parallel_bvh() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::default());
+        let world: HittableList = (0..200_000)
+            .map(|_| -> Arc<dyn Hit> {
+                Arc::new(Sphere::new(Vec3::random(-500.0, 500.0), 1.0, mat.clone()))
+            })
+            .collect();
+
+        let time = |build: fn(HittableList) -> BVTree| {
+            let clock = std::time::Instant::now();
+            let tree = build(world.clone());
+            (clock.elapsed(), tree.objects_count)
+        };
+        let (serial, _) = time(BVTree::new_serial);
+        let (parallel, count) = time(BVTree::new);
+        assert_eq!(count, world.len());
+        println!(
+            "serial: {:?}, parallel: {:?} (x{:.1})",
+            serial,
+            parallel,
+            serial.as_secs_f64() / parallel.as_secs_f64()
+        );
+    }
+
+    #[test]
+    fn test_torus_no_self_hit() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::default());
+        let torus = Torus::new(Vec3::ZERO, vec3(0.0, 1.0, 0.0), 2.0, 0.5, mat);
+        let t_min = scene_epsilon(&torus);
+
+        // leaving the top of the tube nearly tangent
+        let grazing = ray(vec3(2.0, 0.5, 0.0), vec3(1.0, 0.01, 0.0), 0.0);
+        let rec = torus.hit(&grazing, t_min, f64::INFINITY);
+        assert!(rec.is_none(), "{:?}", rec.map(|rec| rec.t));
+
+        // leaving the inner side of the tube across the hole, onto its far side
+        let across = ray(vec3(-1.5, 0.0, 0.0), vec3(1.0, 0.0, 0.0), 0.0);
+        let rec = torus.hit(&across, t_min, f64::INFINITY).unwrap();
+        assert!((rec.t - 3.0).abs() < 1e-9 && rec.front_face);
+    }
+
+    #[test]
+    fn test_torus() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::default());
+        let torus = Torus::new(Vec3::ZERO, vec3(0.0, 1.0, 0.0), 2.0, 0.5, mat.clone());
+        let down = vec3(0.0, -1.0, 0.0);
+
+        // down the axis through the hole
+        assert!(torus
+            .hit(&ray(vec3(0.0, 5.0, 0.0), down, 0.0), 0.001, f64::INFINITY)
+            .is_none());
+
+        // down through the tube, onto its top
+        let rec = torus
+            .hit(&ray(vec3(2.0, 5.0, 0.0), down, 0.0), 0.001, f64::INFINITY)
+            .unwrap();
+        assert!((rec.t - 4.5).abs() < 1e-9, "{}", rec.t);
+        assert!((rec.normal - vec3(0.0, 1.0, 0.0)).norm() < 1e-6 && rec.front_face);
+
+        // across the ring, hitting the outer side of the tube, and from inside the tube
+        let across = ray(vec3(-5.0, 0.0, 0.0), vec3(2.0, 0.0, 0.0), 0.0);
+        let rec = torus.hit(&across, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 1.25).abs() < 1e-9 && (rec.normal - vec3(-1.0, 0.0, 0.0)).norm() < 1e-6);
+        let rec = torus
+            .hit(
+                &ray(vec3(2.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), 0.0),
+                0.001,
+                f64::INFINITY,
+            )
+            .unwrap();
+        assert!((rec.t - 0.5).abs() < 1e-9 && !rec.front_face);
+
+        // toward the top of a tilted torus, the hit is on the surface and in the bounding box
+        let tilted = Torus::new(vec3(1.0, 2.0, 3.0), vec3(1.0, 1.0, 0.0), 2.0, 0.5, mat);
+        let r = ray(vec3(4.0, 2.0, 6.0), vec3(-3.0, 0.0, -1.0), 0.0);
+        let rec = tilted.hit(&r, 0.001, f64::INFINITY).unwrap();
+        let p = tilted.to_local(rec.point - tilted.center);
+        assert!(tilted.distance(p).abs() < 1e-9);
+        let bbox = tilted.bounding_box(0.0, 0.0).unwrap();
+        assert!(bbox.hit(&ray(rec.point, r.dir, 0.0), -1e-6, 1e-6));
+
+        // grazing the top of the tube, for more steps than the march takes
+        let y: f64 = 0.5 - 1e-6;
+        let grazing = ray(vec3(-5.0, y, 0.0), vec3(1.0, 0.0, 0.0), 0.0);
+        let rec = torus.hit(&grazing, 0.001, f64::INFINITY).unwrap();
+        let expected = 3.0 - (0.25 - y * y).sqrt();
+        assert!((rec.t - expected).abs() < 1e-6, "{} vs {}", rec.t, expected);
     }
 }
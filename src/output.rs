@@ -0,0 +1,52 @@
+use super::{Point3, Vec3};
+use std::{fmt::Write as _, fs, io, path::Path};
+
+/// Writes linear radiance as a 32 bit float RGB OpenEXR image.
+/// `pixels` are in render order, the first row being the bottom of the image.
+pub fn write_exr(path: &Path, width: u32, height: u32, pixels: &[Vec3]) -> exr::error::UnitResult {
+    exr::prelude::write_rgb_file(path, width as usize, height as usize, |x, y| {
+        let v = pixels[(height as usize - 1 - y) * width as usize + x];
+        (v.x as f32, v.y as f32, v.z as f32)
+    })
+}
+
+/// Writes paths, like those of `render::trace_paths`, as the polylines of a
+/// Wavefront `.obj` file.
+pub fn write_paths_obj(path: &Path, paths: &[Vec<Point3>]) -> io::Result<()> {
+    let mut obj = String::new();
+    let mut count = 0;
+    for vertices in paths {
+        for v in vertices {
+            writeln!(obj, "v {} {} {}", v.x, v.y, v.z).unwrap();
+        }
+        if vertices.len() > 1 {
+            let indices: Vec<String> = (count + 1..=count + vertices.len())
+                .map(|i| i.to_string())
+                .collect();
+            writeln!(obj, "l {}", indices.join(" ")).unwrap();
+        }
+        count += vertices.len();
+    }
+
+    fs::write(path, obj)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn test_write_exr() {
+        let path = std::env::temp_dir().join("raytracing_weekend_output.exr");
+        output::write_exr(&path, 1, 1, &[vec3(0.25, 1.5, 42.0)]).unwrap();
+
+        let image = exr::prelude::read_first_rgba_layer_from_file(
+            &path,
+            |_, _| (0.0, 0.0, 0.0),
+            |px: &mut (f32, f32, f32), _, (r, g, b, _): (f32, f32, f32, f32)| *px = (r, g, b),
+        )
+        .unwrap();
+
+        assert_eq!(image.layer_data.channel_data.pixels, (0.25, 1.5, 42.0));
+    }
+}
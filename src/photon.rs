@@ -0,0 +1,204 @@
+use super::{random, ray, ray_color, scene_epsilon, Hit, Point3, Ray, Vec3, PI};
+
+/// Light flux carried to a diffuse surface.
+#[derive(Debug, Clone, Copy)]
+pub struct Photon {
+    pub position: Point3,
+    pub power: Vec3,
+}
+
+/// Caustic photons, those that reached a diffuse surface through specular
+/// bounces only, sorted as a balanced kd-tree.
+#[derive(Debug, Clone, Default)]
+pub struct PhotonMap {
+    photons: Vec<Photon>,
+}
+
+impl PhotonMap {
+    /// Shoots `count` photons in every direction from a point light of power
+    /// `power`, following each through at most `depth` specular bounces.
+    pub fn caustics_from_point_light(
+        world: &impl Hit,
+        position: Point3,
+        power: Vec3,
+        count: usize,
+        depth: u32,
+    ) -> Self {
+        let t_min = scene_epsilon(world);
+        let mut photons = Vec::new();
+        for _ in 0..count {
+            let z = 1.0 - 2.0 * random();
+            let phi = 2.0 * PI * random();
+            let r = (1.0 - z * z).sqrt();
+            let dir = Vec3::new(r * phi.cos(), r * phi.sin(), z);
+
+            if let Some(photon) = trace(
+                world,
+                ray(position, dir, 0.0),
+                power / count as f64,
+                depth,
+                t_min,
+            ) {
+                photons.push(photon);
+            }
+        }
+
+        build(&mut photons, 0);
+        Self { photons }
+    }
+
+    pub fn len(&self) -> usize {
+        self.photons.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.photons.is_empty()
+    }
+
+    /// Irradiance at `point` estimated from the photons within `radius`.
+    pub fn irradiance(&self, point: Point3, radius: f64) -> Vec3 {
+        let mut flux = Vec3::ZERO;
+        gather(&self.photons, 0, point, radius * radius, &mut flux);
+        flux / (PI * radius * radius)
+    }
+}
+
+/// Same as `ray_color` with the caustics of `map` gathered within `radius`
+/// added where the camera ray, followed through specular bounces, first meets
+/// a diffuse surface.
+pub fn ray_color_photons(
+    r: &Ray,
+    world: &impl Hit,
+    depth: u32,
+    background: Vec3,
+    t_min: f64,
+    (map, radius): (&PhotonMap, f64),
+) -> Vec3 {
+    let mut throughput = Vec3::ONE;
+    let mut caustic_ray = *r;
+    let mut caustics = Vec3::ZERO;
+    for _ in 0..depth {
+        let record = match world.hit(&caustic_ray, t_min / caustic_ray.dir.norm(), f64::INFINITY) {
+            Some(record) => record,
+            None => break,
+        };
+        let (attenuation, scattered) = match record.mat.scatter(&caustic_ray, &record) {
+            Some(scattered) => scattered,
+            None => break,
+        };
+
+        throughput = throughput * attenuation.to_vec3();
+        if !record.mat.is_specular() {
+            // lambertian brdf is albedo / pi
+            caustics = throughput * map.irradiance(record.point, radius) / PI;
+            break;
+        }
+        caustic_ray = scattered;
+    }
+
+    caustics + ray_color(r, world, depth, background, t_min)
+}
+
+// caustic photon left by a photon path, if it reached a diffuse surface
+// after at least one specular bounce
+fn trace(world: &impl Hit, mut r: Ray, mut power: Vec3, depth: u32, t_min: f64) -> Option<Photon> {
+    for bounce in 0..depth {
+        let record = world.hit(&r, t_min / r.dir.norm(), f64::INFINITY)?;
+        if !record.mat.is_specular() {
+            return if bounce > 0 {
+                Some(Photon {
+                    position: record.point,
+                    power,
+                })
+            } else {
+                None
+            };
+        }
+
+        let (attenuation, scattered) = record.mat.scatter(&r, &record)?;
+        power = power * attenuation.to_vec3();
+        r = scattered;
+    }
+
+    None
+}
+
+// sort the photons as a kd-tree, the median of each slice splitting it
+// along the axis of its depth
+fn build(photons: &mut [Photon], depth: usize) {
+    if photons.len() <= 1 {
+        return;
+    }
+
+    let axis = depth % 3;
+    photons.sort_by(|a, b| a.position[axis].partial_cmp(&b.position[axis]).unwrap());
+    let mid = photons.len() / 2;
+    let (left, right) = photons.split_at_mut(mid);
+    build(left, depth + 1);
+    build(&mut right[1..], depth + 1);
+}
+
+fn gather(photons: &[Photon], depth: usize, point: Point3, r2: f64, flux: &mut Vec3) {
+    if photons.is_empty() {
+        return;
+    }
+
+    let mid = photons.len() / 2;
+    let photon = photons[mid];
+    if (photon.position - point).squared_norm() <= r2 {
+        *flux += photon.power;
+    }
+
+    let d = point[depth % 3] - photon.position[depth % 3];
+    let (near, far) = if d < 0.0 {
+        (&photons[..mid], &photons[mid + 1..])
+    } else {
+        (&photons[mid + 1..], &photons[..mid])
+    };
+    gather(near, depth + 1, point, r2, flux);
+    if d * d <= r2 {
+        gather(far, depth + 1, point, r2, flux);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_glass_sphere_focuses_caustic() {
+        let scene = |glass: bool| -> HittableList {
+            let mut world: HittableList = vec![Arc::new(XZRect::new(
+                (-10.0, 10.0),
+                (-10.0, 10.0),
+                0.0,
+                Arc::new(Lambertian::default()),
+            ))];
+            if glass {
+                world.push(Arc::new(Sphere::new(
+                    vec3(0.0, 2.0, 0.0),
+                    1.0,
+                    Arc::new(Dielectric::default()),
+                )));
+            }
+            world
+        };
+        let light = (vec3(0.0, 6.0, 0.0), 100.0 * Vec3::ONE);
+        let below = vec3(0.0, 0.0, 0.0);
+
+        let with = PhotonMap::caustics_from_point_light(&scene(true), light.0, light.1, 20000, 8);
+        let without =
+            PhotonMap::caustics_from_point_light(&scene(false), light.0, light.1, 20000, 8);
+
+        assert!(!with.is_empty() && without.is_empty());
+        assert!(with.irradiance(below, 0.5).x > without.irradiance(below, 0.5).x);
+
+        let camera = Camera::new(40.0, vec3(0.0, 8.0, -8.0), below, 1.0, 0.0, 10.0, 0.0, 0.0);
+        let r = camera.get_ray(0.5, 0.5);
+        let color = |map: &PhotonMap| {
+            ray_color_photons(&r, &scene(false), 1, Vec3::ZERO, T_MIN, (map, 0.5))
+        };
+        assert!(color(&with).x > color(&without).x);
+    }
+}
@@ -0,0 +1,334 @@
+use super::{Color, Vec3};
+use image::RgbaImage;
+
+/// Adds a glow around the pixels brighter than `threshold`: the bright
+/// pixels are extracted, blurred with a gaussian and added back scaled by
+/// `intensity`. Works on linear radiance, before tone mapping.
+pub fn bloom(
+    pixels: &[Vec3],
+    width: u32,
+    height: u32,
+    threshold: f64,
+    intensity: f64,
+) -> Vec<Vec3> {
+    let bright: Vec<Vec3> = pixels
+        .iter()
+        .map(|p| {
+            if p.luminance() > threshold {
+                *p
+            } else {
+                Vec3::ZERO
+            }
+        })
+        .collect();
+    if bright.iter().all(|p| *p == Vec3::ZERO) {
+        return pixels.to_vec();
+    }
+
+    let sigma = (width.max(height) as f64 * 0.01).max(1.0);
+    let glow = gaussian_blur(&bright, width, height, sigma);
+    pixels
+        .iter()
+        .zip(glow)
+        .map(|(p, g)| *p + intensity * g)
+        .collect()
+}
+
+/// Separable gaussian blur, the borders are clamped.
+pub fn gaussian_blur(pixels: &[Vec3], width: u32, height: u32, sigma: f64) -> Vec<Vec3> {
+    let radius = (3.0 * sigma).ceil() as i64;
+    let kernel: Vec<f64> = (-radius..=radius)
+        .map(|i| (-((i * i) as f64) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let norm: f64 = kernel.iter().sum();
+
+    let (w, h) = (width as i64, height as i64);
+    let blur = |src: &[Vec3], dx: i64, dy: i64| -> Vec<Vec3> {
+        (0..w * h)
+            .map(|i| {
+                let (x, y) = (i % w, i / w);
+                kernel
+                    .iter()
+                    .enumerate()
+                    .fold(Vec3::ZERO, |acc, (k, weight)| {
+                        let o = k as i64 - radius;
+                        let sx = (x + o * dx).clamp(0, w - 1);
+                        let sy = (y + o * dy).clamp(0, h - 1);
+                        acc + src[(sy * w + sx) as usize] * (weight / norm)
+                    })
+            })
+            .collect()
+    };
+
+    blur(&blur(pixels, 1, 0), 0, 1)
+}
+
+/// Lens like color fringes: the red and blue channels are sampled further
+/// from and closer to the image center than the green one, by a fraction
+/// `strength` of the distance to the center.
+pub fn chromatic_aberration(pixels: &[Vec3], width: u32, height: u32, strength: f64) -> Vec<Vec3> {
+    let center = (
+        width.saturating_sub(1) as f64 / 2.0,
+        height.saturating_sub(1) as f64 / 2.0,
+    );
+    (0..width * height)
+        .map(|i| {
+            let (x, y) = ((i % width) as f64, (i / width) as f64);
+            let (dx, dy) = (x - center.0, y - center.1);
+            let red = sample(
+                pixels,
+                width,
+                height,
+                center.0 + dx * (1.0 + strength),
+                center.1 + dy * (1.0 + strength),
+            );
+            let blue = sample(
+                pixels,
+                width,
+                height,
+                center.0 + dx * (1.0 - strength),
+                center.1 + dy * (1.0 - strength),
+            );
+
+            Vec3::new(red.x, pixels[i as usize].y, blue.z)
+        })
+        .collect()
+}
+
+/// Darkens the image toward its corners by `1 - amount * r^falloff`, where
+/// `r` is the distance to the center, 0 at the center and 1 at the corners.
+pub fn vignette(pixels: &[Vec3], width: u32, height: u32, amount: f64, falloff: f64) -> Vec<Vec3> {
+    let center = (
+        width.saturating_sub(1) as f64 / 2.0,
+        height.saturating_sub(1) as f64 / 2.0,
+    );
+    let max_dist = center.0.hypot(center.1).max(f64::EPSILON);
+    pixels
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            let (x, y) = ((i as u32 % width) as f64, (i as u32 / width) as f64);
+            let r = (x - center.0).hypot(y - center.1) / max_dist;
+            *p * (1.0 - amount * r.powf(falloff))
+        })
+        .collect()
+}
+
+/// Lift / gamma / gain color grading of a linear buffer, to apply before
+/// tonemapping: each channel of the pixels becomes
+/// `(gain * lerp(lift, 1, x))^(1 / gamma)`. Lift raises the blacks, gain
+/// scales the whites and gamma bends the midtones. A zero lift and unit
+/// gamma and gain leave the pixels untouched.
+pub fn grade(pixels: &[Vec3], lift: Vec3, gamma: Vec3, gain: Vec3) -> Vec<Vec3> {
+    let channel = |x: f64, i: usize| {
+        let lifted = lift.lerp(Vec3::ONE, x)[i];
+        (gain[i] * lifted).max(0.0).powf(1.0 / gamma[i])
+    };
+    pixels
+        .iter()
+        .map(|p| Vec3::new(channel(p.x, 0), channel(p.y, 1), channel(p.z, 2)))
+        .collect()
+}
+
+/// Scales the saturation of a linear buffer by `amount` around the
+/// luminance of each pixel: 0 is greyscale, 1 leaves the pixels untouched and
+/// larger amounts boost the colors. The luminance is kept, as its weights sum
+/// to 1.
+pub fn saturation(pixels: &[Vec3], amount: f64) -> Vec<Vec3> {
+    pixels
+        .iter()
+        .map(|p| *p + (amount - 1.0) * (*p - Vec3::ONE * p.luminance()))
+        .collect()
+}
+
+/// Highlight recovery of a linear buffer, to apply before tonemapping:
+/// pixels whose brightest channel is above `knee` are scaled so that it
+/// rolls off smoothly toward `limit` instead of clipping, which keeps the
+/// hue of the highlight. Pixels below the knee are left untouched.
+pub fn soft_clip(pixels: &[Vec3], knee: f64, limit: f64) -> Vec<Vec3> {
+    let range = limit - knee;
+    pixels
+        .iter()
+        .map(|p| {
+            let m = p.x.max(p.y).max(p.z);
+            if m <= knee || range <= 0.0 {
+                return *p;
+            }
+
+            // same value and slope as the identity at the knee
+            let compressed = knee + range * (1.0 - (-(m - knee) / range).exp());
+            *p * (compressed / m)
+        })
+        .collect()
+}
+
+/// Draws lines of `color` every `spacing` pixels from the top left corner of
+/// the image and a crosshair of arms `spacing / 2` long at its center, to
+/// check the framing against a reference. Other pixels are left untouched.
+pub fn grid_overlay(img: &mut RgbaImage, spacing: u32, color: Color) {
+    let (w, h) = img.dimensions();
+    if spacing == 0 || w == 0 || h == 0 {
+        return;
+    }
+
+    let (cx, cy) = (w / 2, h / 2);
+    let arm = spacing / 2;
+    for (x, y, pixel) in img.enumerate_pixels_mut() {
+        let grid = x % spacing == 0 || y % spacing == 0;
+        let cross =
+            (x == cx && y.max(cy) - y.min(cy) <= arm) || (y == cy && x.max(cx) - x.min(cx) <= arm);
+        if grid || cross {
+            *pixel = image::Rgba(color.into());
+        }
+    }
+}
+
+// bilinear lookup with clamped borders
+fn sample(pixels: &[Vec3], width: u32, height: u32, x: f64, y: f64) -> Vec3 {
+    let x = x.clamp(0.0, (width - 1) as f64);
+    let y = y.clamp(0.0, (height - 1) as f64);
+    let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+    let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+    let (fx, fy) = (x - x0 as f64, y - y0 as f64);
+    let at = |x: u32, y: u32| pixels[(y * width + x) as usize];
+
+    let bottom = at(x0, y0).lerp(at(x1, y0), fx);
+    let top = at(x0, y1).lerp(at(x1, y1), fx);
+    bottom.lerp(top, fy)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn test_bloom_spreads_bright_pixel() {
+        let mut pixels = vec![Vec3::ZERO; 81];
+        pixels[40] = vec3(100.0, 100.0, 100.0);
+
+        let out = postprocess::bloom(&pixels, 9, 9, 1.0, 0.5);
+        for i in [31, 39, 41, 49].iter() {
+            assert!(out[*i].luminance() > 0.0);
+        }
+        assert!(out[40].luminance() > 100.0);
+    }
+
+    #[test]
+    fn test_bloom_keeps_dim_image() {
+        let pixels = vec![vec3(0.5, 0.2, 0.1); 81];
+        assert_eq!(postprocess::bloom(&pixels, 9, 9, 1.0, 0.5), pixels);
+    }
+
+    #[test]
+    fn test_chromatic_aberration() {
+        let pixels: Vec<Vec3> = (0..81)
+            .map(|i| Vec3::ONE * ((i % 9) as f64 / 8.0))
+            .collect();
+        let out = postprocess::chromatic_aberration(&pixels, 9, 9, 0.2);
+
+        assert_eq!(out[40], pixels[40]);
+
+        let edge = out[9 * 4 + 7];
+        assert!((edge.x - edge.z).abs() > 0.05);
+        assert_eq!(edge.y, pixels[9 * 4 + 7].y);
+
+        let column = vec![vec3(0.1, 0.2, 0.3); 9];
+        assert_eq!(
+            postprocess::chromatic_aberration(&column, 1, 9, 0.2)[4],
+            column[4]
+        );
+        assert_eq!(
+            postprocess::chromatic_aberration(&column[..1], 1, 1, 0.2),
+            &column[..1]
+        );
+        assert!(postprocess::chromatic_aberration(&[], 0, 0, 0.2).is_empty());
+    }
+
+    #[test]
+    fn test_vignette() {
+        let pixels = vec![Vec3::ONE; 81];
+        let out = postprocess::vignette(&pixels, 9, 9, 0.5, 2.0);
+
+        assert_eq!(out[40], Vec3::ONE);
+        assert!((out[0] - Vec3::ONE * 0.5).norm() < 1e-9);
+        assert!(out[80].x < out[40].x);
+
+        assert_eq!(
+            postprocess::vignette(&pixels[..1], 1, 1, 0.5, 2.0),
+            &pixels[..1]
+        );
+        let column = postprocess::vignette(&pixels[..9], 1, 9, 0.5, 2.0);
+        assert_eq!(column[4], Vec3::ONE);
+        assert!((column[0] - Vec3::ONE * 0.5).norm() < 1e-9);
+        assert!(postprocess::vignette(&[], 0, 0, 0.5, 2.0).is_empty());
+    }
+
+    #[test]
+    fn test_grid_overlay() {
+        let background = image::Rgba([10, 20, 30, 255]);
+        let mut img = image::RgbaImage::from_pixel(40, 24, background);
+        postprocess::grid_overlay(&mut img, 16, Color::WHITE);
+
+        for (x, y, pixel) in img.enumerate_pixels() {
+            let grid = x % 16 == 0 || y % 16 == 0;
+            let cross = (x == 20 && (4..=20).contains(&y)) || (y == 12 && (12..=28).contains(&x));
+            if grid || cross {
+                assert_eq!(pixel.0, [255, 255, 255, 255], "({}, {})", x, y);
+            } else {
+                assert_eq!(*pixel, background, "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_grade() {
+        let pixels = vec![Vec3::ZERO, Vec3::ONE * 0.5, vec3(0.2, 1.0, 3.0)];
+        let neutral = postprocess::grade(&pixels, Vec3::ZERO, Vec3::ONE, Vec3::ONE);
+        assert_eq!(neutral, pixels);
+
+        let gained = postprocess::grade(&pixels, Vec3::ZERO, Vec3::ONE, Vec3::ONE * 2.0);
+        assert_eq!(gained[1], Vec3::ONE);
+
+        let lifted = postprocess::grade(&pixels, Vec3::ONE * 0.1, Vec3::ONE, Vec3::ONE);
+        assert!((lifted[0] - Vec3::ONE * 0.1).norm() < 1e-12);
+        assert!(lifted[1].x > 0.5);
+
+        let graded = postprocess::grade(&pixels, Vec3::ZERO, vec3(1.0, 2.0, 1.0), Vec3::ONE);
+        assert!((graded[1].y - 0.5f64.sqrt()).abs() < 1e-12 && graded[1].x == 0.5);
+    }
+
+    #[test]
+    fn test_soft_clip() {
+        let pixels = vec![
+            vec3(0.2, 0.5, 0.8),
+            vec3(0.4, 0.8, 0.81),
+            vec3(0.5, 1.0, 4.0),
+        ];
+        let clipped = postprocess::soft_clip(&pixels, 0.8, 1.0);
+        assert_eq!(clipped[0], pixels[0]);
+
+        // just above the knee, the slope is still about 1
+        assert!((clipped[1].z - 0.81).abs() < 1e-3 && clipped[1].z < 0.81);
+
+        // far above, the highlight approaches the limit with its hue
+        let hot = clipped[2];
+        assert!(hot.z < 1.0 && hot.z > 0.99);
+        assert!((hot.y / hot.z - 0.25).abs() < 1e-12 && (hot.x / hot.z - 0.125).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_saturation() {
+        let pixels = vec![vec3(0.8, 0.2, 0.1), vec3(0.1, 0.5, 0.9), Vec3::ONE * 0.3];
+        assert_eq!(postprocess::saturation(&pixels, 1.0), pixels);
+
+        let grey = postprocess::saturation(&pixels, 0.0);
+        for (g, p) in grey.iter().zip(&pixels) {
+            assert!((g.x - g.y).abs() < 1e-12 && (g.y - g.z).abs() < 1e-12);
+            assert!((g.luminance() - p.luminance()).abs() < 1e-12);
+        }
+
+        let boosted = postprocess::saturation(&pixels, 2.0);
+        assert!(boosted[0].x > pixels[0].x && boosted[0].z < pixels[0].z);
+        assert!((boosted[0].luminance() - pixels[0].luminance()).abs() < 1e-12);
+    }
+}
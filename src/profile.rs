@@ -0,0 +1,83 @@
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+/// Wall clock time spent in each named stage of a render.
+#[derive(Debug, Clone, Default)]
+pub struct Timings {
+    stages: Vec<(&'static str, Duration)>,
+}
+
+impl Timings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn time<T>(&mut self, stage: &'static str, f: impl FnOnce() -> T) -> T {
+        let clock = Instant::now();
+        let result = f();
+        self.stages.push((stage, clock.elapsed()));
+        result
+    }
+
+    pub fn get(&self, stage: &str) -> Option<Duration> {
+        self.stages
+            .iter()
+            .filter(|(name, _)| *name == stage)
+            .map(|(_, d)| *d)
+            .reduce(|a, b| a + b)
+    }
+
+    pub fn total(&self) -> Duration {
+        self.stages.iter().map(|(_, d)| *d).sum()
+    }
+}
+
+impl Display for Timings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let total = self.total().as_secs_f64();
+        writeln!(f, "Timings:")?;
+        for (name, d) in &self.stages {
+            let secs = d.as_secs_f64();
+            writeln!(
+                f,
+                "\t{:<12} = {:.4}s ({:.1}%)",
+                name,
+                secs,
+                100.0 * secs / total.max(f64::EPSILON)
+            )?;
+        }
+        writeln!(f, "\t{:<12} = {:.4}s", "total", total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn test_stages_are_timed() {
+        let config = Config {
+            width: 4,
+            height: 4,
+            aspect_ratio: 1.0,
+            samples: 1,
+            depth: 2,
+            ..Config::default()
+        };
+        let path = std::env::temp_dir().join("raytracing_weekend_profile.png");
+
+        let mut timings = Timings::new();
+        let (world, camera, background) =
+            timings.time("scene build", || scenes::two_spheres(config.aspect_ratio));
+        let tree = timings.time("bvh build", || BVTree::new(world));
+        let buffer = timings.time("render", || {
+            render::render(&tree, &camera, background, &config)
+        });
+        let img = timings.time("tone map", || render::to_image(&buffer, &config));
+        timings.time("save", || img.save(&path).unwrap());
+
+        for stage in ["scene build", "bvh build", "render", "tone map", "save"].iter() {
+            assert!(timings.get(stage).unwrap().as_nanos() > 0);
+        }
+    }
+}
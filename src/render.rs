@@ -0,0 +1,1716 @@
+use super::{
+    get_ray, get_ray_antithetic, get_ray_at, path_length, ray, ray_color_preview, ray_color_split,
+    scene_epsilon, AccumBuffer, Camera, Color, Config, Hit, Integrator, PathTracer, Point3, Ray,
+    RayKind, Vec3,
+};
+use image::{ImageBuffer, RgbaImage};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    ops::Range,
+    str::FromStr,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+// a pass that lowers the variance by less than this fraction counts as a plateau
+const PLATEAU: f64 = 0.01;
+
+/// Shadow rays toward the light of each sample of `render_preview`.
+pub const PREVIEW_SHADOW_RAYS: u32 = 4;
+
+/// Side of the square tiles of `render_tiled`.
+pub const TILE_SIZE: u32 = 16;
+
+// per pixel radiance sum and sum of squares
+type Accumulator = (Vec3, Vec3);
+
+/// Renders the `config.samples` samples of each pixel. With `config.debug_nan`
+/// the first sample of non-finite radiance panics with its pixel, sample
+/// index and the material the camera ray hit.
+pub fn render(world: &impl Hit, camera: &Camera, background: Color, config: &Config) -> Vec<Color> {
+    render_with(world, camera, background, config, &path_tracer(config))
+}
+
+/// Same as `render` with the radiance of each sample estimated by `integrator`.
+pub fn render_with(
+    world: &impl Hit,
+    camera: &Camera,
+    background: Color,
+    config: &Config,
+    integrator: &dyn Integrator,
+) -> Vec<Color> {
+    pass(world, camera, background.to_vec3(), config, integrator)
+        .iter()
+        .enumerate()
+        .map(|(i, (sum, _))| quantize(*sum, config.samples, i, config))
+        .collect()
+}
+
+/// Same as `render`, also returning the linear radiance of the pixels the
+/// colors are tonemapped from, for example to save an EXR alongside.
+pub fn render_hdr(
+    world: &impl Hit,
+    camera: &Camera,
+    background: Color,
+    config: &Config,
+) -> (Vec<Vec3>, Vec<Color>) {
+    let samples = config.samples as f64;
+    let radiance: Vec<Vec3> = pass(
+        world,
+        camera,
+        background.to_vec3(),
+        config,
+        &path_tracer(config),
+    )
+    .iter()
+    .map(|(sum, _)| *sum / samples)
+    .collect();
+    let colors = tonemap(&radiance, config);
+    (radiance, colors)
+}
+
+/// Renders passes of `config.samples` samples per pixel until the mean pixel
+/// variance drops below a positive `config.target_variance`, stops improving,
+/// or `config.max_passes` is reached. Returns the image and the number of passes.
+pub fn render_progressive(
+    world: &impl Hit,
+    camera: &Camera,
+    background: Color,
+    config: &Config,
+) -> (Vec<Color>, u32) {
+    let (radiance, passes) = render_progressive_hdr(world, camera, background, config);
+    (tonemap(&radiance, config), passes)
+}
+
+/// Same as `render_progressive` but returns the mean linear radiance of each pixel.
+pub fn render_progressive_hdr(
+    world: &impl Hit,
+    camera: &Camera,
+    background: Color,
+    config: &Config,
+) -> (Vec<Vec3>, u32) {
+    render_progressive_hdr_with(world, camera, background, config, &path_tracer(config))
+}
+
+/// Same as `render_progressive_hdr` with the radiance estimated by `integrator`.
+pub fn render_progressive_hdr_with(
+    world: &impl Hit,
+    camera: &Camera,
+    background: Color,
+    config: &Config,
+    integrator: &dyn Integrator,
+) -> (Vec<Vec3>, u32) {
+    let cancel = AtomicBool::new(false);
+    render_cancellable_with(world, camera, background, config, &cancel, integrator)
+}
+
+/// Same as `render_progressive_hdr`, stopping as soon as `cancel` is set, e.g.
+/// from a UI thread holding it in an `Arc`. The pass in progress is dropped
+/// and the radiance of the passes completed so far, black if none, is returned.
+pub fn render_cancellable(
+    world: &impl Hit,
+    camera: &Camera,
+    background: Color,
+    config: &Config,
+    cancel: &AtomicBool,
+) -> (Vec<Vec3>, u32) {
+    render_cancellable_with(
+        world,
+        camera,
+        background,
+        config,
+        cancel,
+        &path_tracer(config),
+    )
+}
+
+/// Same as `render_cancellable` with the radiance estimated by `integrator`.
+pub fn render_cancellable_with(
+    world: &impl Hit,
+    camera: &Camera,
+    background: Color,
+    config: &Config,
+    cancel: &AtomicBool,
+    integrator: &dyn Integrator,
+) -> (Vec<Vec3>, u32) {
+    let mut acc = vec![(Vec3::ZERO, Vec3::ZERO); (config.width * config.height) as usize];
+    let mut passes = 0;
+    let mut last_variance = f64::INFINITY;
+
+    while passes < config.max_passes.max(1) {
+        let pass = cancellable_pass(
+            world,
+            camera,
+            background.to_vec3(),
+            config,
+            integrator,
+            cancel,
+        );
+        let samples = match pass {
+            Some(samples) => samples,
+            None => break,
+        };
+        for (a, (sum, squares)) in acc.iter_mut().zip(samples) {
+            a.0 += sum;
+            a.1 += squares;
+        }
+        passes += 1;
+
+        let variance = mean_variance(&acc, passes * config.samples);
+        let plateau = variance > last_variance * (1.0 - PLATEAU);
+        if config.target_variance > 0.0 && (variance <= config.target_variance || plateau) {
+            break;
+        }
+        last_variance = variance;
+    }
+
+    let samples = (passes * config.samples).max(1) as f64;
+    let radiance = acc.iter().map(|(sum, _)| *sum / samples).collect();
+    (radiance, passes)
+}
+
+/// Adaptive sampling converging each color channel of each pixel on its own:
+/// a pixel takes passes of `config.samples` samples, up to
+/// `config.max_passes`, while the variance of any of its channels is above
+/// `config.target_variance`, and a converged channel stops accumulating.
+/// Returns the radiance and the number of samples of each channel.
+pub fn render_per_channel(
+    world: &impl Hit,
+    camera: &Camera,
+    background: Color,
+    config: &Config,
+) -> (Vec<Vec3>, Vec<[u32; 3]>) {
+    render_per_channel_with(world, camera, background, config, &path_tracer(config))
+}
+
+/// Same as `render_per_channel` with the radiance estimated by `integrator`.
+pub fn render_per_channel_with(
+    world: &impl Hit,
+    camera: &Camera,
+    background: Color,
+    config: &Config,
+    integrator: &dyn Integrator,
+) -> (Vec<Vec3>, Vec<[u32; 3]>) {
+    let t_min = t_min(world, config);
+    (0..config.width * config.height)
+        .into_par_iter()
+        .map(|i| {
+            let (x, y) = (i % config.width, i / config.width);
+            let mut sums = [0.0; 3];
+            let mut squares = [0.0; 3];
+            let mut counts = [0; 3];
+            let mut active = [true; 3];
+
+            for _ in 0..config.max_passes.max(1) {
+                for _ in 0..config.samples {
+                    let color = integrator.radiance(
+                        &get_ray(x, y, camera, config),
+                        world,
+                        background.to_vec3(),
+                        config.depth,
+                        t_min,
+                    );
+                    for c in (0..3).filter(|c| active[*c]) {
+                        sums[c] += color[c];
+                        squares[c] += color[c] * color[c];
+                        counts[c] += 1;
+                    }
+                }
+
+                for c in 0..3 {
+                    if !active[c] || counts[c] < 2 {
+                        continue;
+                    }
+                    let n = counts[c] as f64;
+                    let variance = (squares[c] - sums[c] * sums[c] / n) / ((n - 1.0) * n);
+                    active[c] = variance > config.target_variance;
+                }
+                if !active.iter().any(|a| *a) {
+                    break;
+                }
+            }
+
+            let mean = |c: usize| {
+                if counts[c] == 0 {
+                    0.0
+                } else {
+                    sums[c] / counts[c] as f64
+                }
+            };
+            (Vec3::new(mean(0), mean(1), mean(2)), counts)
+        })
+        .unzip()
+}
+
+/// Clamps, gamma corrects and optionally dithers linear radiance for display.
+pub fn tonemap(radiance: &[Vec3], config: &Config) -> Vec<Color> {
+    radiance
+        .iter()
+        .enumerate()
+        .map(|(i, v)| quantize(*v, 1, i, config))
+        .collect()
+}
+
+/// Renders the mean direct and indirect lighting of each pixel in separate
+/// buffers, their sum being the beauty pass.
+pub fn render_split(
+    world: &impl Hit,
+    camera: &Camera,
+    background: Color,
+    config: &Config,
+) -> (Vec<Vec3>, Vec<Vec3>) {
+    let samples = config.samples as f64;
+    let t_min = t_min(world, config);
+    (0..config.width * config.height)
+        .into_par_iter()
+        .map(|i| {
+            let (x, y) = (i % config.width, i / config.width);
+            let (mut direct, mut indirect) = (Vec3::ZERO, Vec3::ZERO);
+            for _ in 0..config.samples {
+                let (d, i) = ray_color_split(
+                    &get_ray(x, y, camera, config),
+                    world,
+                    config.depth,
+                    background.to_vec3(),
+                    t_min,
+                );
+                direct += d;
+                indirect += i;
+            }
+
+            (direct / samples, indirect / samples)
+        })
+        .unzip()
+}
+
+/// How `stereo_composite` combines the images of both eyes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StereoLayout {
+    /// left eye image on the left half, right eye image on the right half
+    SideBySide,
+    /// red channel of the left eye, green and blue of the right one
+    Anaglyph,
+}
+
+/// Renders the scene from both eyes of `camera.stereo_pair(ipd)`, sharing
+/// the world and its acceleration structure.
+pub fn render_stereo(
+    world: &impl Hit,
+    camera: &Camera,
+    background: Color,
+    config: &Config,
+    ipd: f64,
+) -> (Vec<Color>, Vec<Color>) {
+    let (left, right) = camera.stereo_pair(ipd);
+    (
+        render(world, &left, background, config),
+        render(world, &right, background, config),
+    )
+}
+
+/// Combines the renders of both eyes. A side by side buffer is
+/// `2 * config.width` wide, an anaglyph has the size of the renders.
+pub fn stereo_composite(
+    left: &[Color],
+    right: &[Color],
+    config: &Config,
+    layout: StereoLayout,
+) -> Vec<Color> {
+    match layout {
+        StereoLayout::SideBySide => left
+            .chunks(config.width as usize)
+            .zip(right.chunks(config.width as usize))
+            .flat_map(|(l, r)| l.iter().chain(r))
+            .copied()
+            .collect(),
+        StereoLayout::Anaglyph => left
+            .iter()
+            .zip(right)
+            .map(|(l, r)| Color::new(l.r, r.g, r.b, l.a.max(r.a)))
+            .collect(),
+    }
+}
+
+/// Fast preview of the scene lit by `light`, shaded by `ray_color_preview`
+/// with `PREVIEW_SHADOW_RAYS` shadow rays per sample.
+pub fn render_preview(
+    world: &impl Hit,
+    light: &dyn Hit,
+    camera: &Camera,
+    background: Color,
+    config: &Config,
+) -> Vec<Color> {
+    let t_min = t_min(world, config);
+    (0..config.width * config.height)
+        .into_par_iter()
+        .map(|i| {
+            let (x, y) = (i % config.width, i / config.width);
+            let sum = (0..config.samples).fold(Vec3::ZERO, |sum, _| {
+                let r = get_ray(x, y, camera, config);
+                let background = background.to_vec3();
+                sum + ray_color_preview(&r, world, light, background, t_min, PREVIEW_SHADOW_RAYS)
+            });
+            quantize(sum, config.samples, i as usize, config)
+        })
+        .collect()
+}
+
+/// Renders the mean radiance of each pixel like `render_progressive_hdr`
+/// does in one pass, with the caustics of `map` gathered within `radius`.
+#[cfg(feature = "photon_map")]
+pub fn render_photons(
+    world: &impl Hit,
+    camera: &Camera,
+    background: Color,
+    config: &Config,
+    (map, radius): (&crate::PhotonMap, f64),
+) -> Vec<Vec3> {
+    let t_min = t_min(world, config);
+    (0..config.width * config.height)
+        .into_par_iter()
+        .map(|i| {
+            let (x, y) = (i % config.width, i / config.width);
+            let sum = (0..config.samples).fold(Vec3::ZERO, |sum, _| {
+                sum + crate::ray_color_photons(
+                    &get_ray(x, y, camera, config),
+                    world,
+                    config.depth,
+                    background.to_vec3(),
+                    t_min,
+                    (map, radius),
+                )
+            });
+
+            sum / config.samples as f64
+        })
+        .collect()
+}
+
+/// Mean number of bounces of the paths traced through each pixel, with the
+/// Russian roulette of the config, showing where the tracer spends its effort.
+pub fn bounce_counts(world: &impl Hit, camera: &Camera, config: &Config) -> Vec<f64> {
+    let t_min = t_min(world, config);
+    (0..config.width * config.height)
+        .into_par_iter()
+        .map(|i| {
+            let (x, y) = (i % config.width, i / config.width);
+            let bounces: u32 = (0..config.samples)
+                .map(|_| {
+                    let r = get_ray(x, y, camera, config);
+                    path_length(&r, world, config.depth, t_min, config.roulette_min_bounces)
+                })
+                .sum();
+
+            bounces as f64 / config.samples as f64
+        })
+        .collect()
+}
+
+/// A sample traced by `debug_pixel`.
+#[derive(Debug, Clone)]
+pub struct SampleLog {
+    pub radiance: Vec3,
+    pub bounces: u32,
+    pub materials: Vec<String>,
+}
+
+/// Traces the `config.samples` samples of pixel (x, y) like `render`, logging
+/// their radiance, path length and the materials they hit to stderr.
+pub fn debug_pixel(
+    world: &impl Hit,
+    camera: &Camera,
+    background: Color,
+    config: &Config,
+    (x, y): (u32, u32),
+) -> Vec<SampleLog> {
+    let t_min = t_min(world, config);
+    (0..config.samples)
+        .map(|sample| {
+            let mut log = SampleLog {
+                radiance: Vec3::ZERO,
+                bounces: 0,
+                materials: Vec::new(),
+            };
+            let mut throughput = Vec3::ONE;
+            let mut r = get_ray(x, y, camera, config);
+
+            while log.bounces < config.depth {
+                let record = match world.hit(&r, t_min / r.dir.norm(), f64::INFINITY) {
+                    Some(record) => record,
+                    None => {
+                        log.radiance += throughput * background.to_vec3();
+                        break;
+                    }
+                };
+
+                // type name of the material, without its fields
+                let name = format!("{:?}", record.mat);
+                log.materials
+                    .push(name.split([' ', '{']).next().unwrap().to_string());
+                log.radiance += throughput * record.mat.emitted_hit(&record);
+                match record.mat.scatter(&r, &record) {
+                    Some((attenuation, scattered)) => {
+                        throughput = throughput * attenuation.to_vec3();
+                        r = scattered;
+                        log.bounces += 1;
+                    }
+                    None => break,
+                }
+            }
+
+            eprintln!(
+                "pixel ({}, {}) sample {}: radiance {:?}, {} bounces, hit {:?}",
+                x, y, sample, log.radiance, log.bounces, log.materials
+            );
+            log
+        })
+        .collect()
+}
+
+/// Vertices of a path traced through each of the pixels, the camera ray
+/// origin followed by the hit point of each bounce, e.g. to draw the paths
+/// with `output::write_paths_obj`. The last segment of a path leaving the
+/// scene is not recorded.
+pub fn trace_paths(
+    world: &impl Hit,
+    camera: &Camera,
+    config: &Config,
+    pixels: &[(u32, u32)],
+) -> Vec<Vec<Point3>> {
+    let t_min = t_min(world, config);
+    pixels
+        .iter()
+        .map(|(x, y)| {
+            let mut r = get_ray(*x, *y, camera, config);
+            let mut path = vec![r.origin];
+            for _ in 0..config.depth {
+                let record = match world.hit(&r, t_min / r.dir.norm(), f64::INFINITY) {
+                    Some(record) => record,
+                    None => break,
+                };
+                path.push(record.point);
+                match record.mat.scatter(&r, &record) {
+                    Some((_, scattered)) => r = scattered,
+                    None => break,
+                }
+            }
+
+            path
+        })
+        .collect()
+}
+
+/// Fraction of the light of `lights` blocked by the scene on the shadow
+/// catchers seen through each pixel, zero where no catcher is seen.
+pub fn shadow_alpha(
+    world: &impl Hit,
+    lights: &impl Hit,
+    camera: &Camera,
+    config: &Config,
+) -> Vec<f64> {
+    let t_min = t_min(world, config);
+    (0..config.width * config.height)
+        .into_par_iter()
+        .map(|i| {
+            let (x, y) = (i % config.width, i / config.width);
+            let occluded = (0..config.samples)
+                .filter(|_| {
+                    let r = get_ray(x, y, camera, config);
+                    let rec = match world.hit(&r, t_min / r.dir.norm(), f64::INFINITY) {
+                        Some(rec) if rec.mat.is_shadow_catcher() => rec,
+                        _ => return false,
+                    };
+
+                    let shadow = ray(rec.point, lights.random_toward(rec.point), r.time)
+                        .with_kind(RayKind::Shadow);
+                    let t_min = t_min / shadow.dir.norm();
+                    let light = match lights.hit(&shadow, t_min, f64::INFINITY) {
+                        Some(light) => light,
+                        None => return false,
+                    };
+                    world
+                        .hit(&shadow, t_min, light.t - t_min)
+                        .is_some_and(|blocker| !blocker.mat.is_shadow_catcher())
+                })
+                .count();
+
+            occluded as f64 / config.samples as f64
+        })
+        .collect()
+}
+
+/// Screen space motion in pixels of the surface seen at the center of each
+/// pixel, between the `previous` and `current` (camera, time) of an animation.
+/// The motion is stored in the x and y components, pixels missing the scene
+/// and static regions are zero.
+pub fn motion_vectors(
+    world: &impl Hit,
+    current: (&Camera, f64),
+    previous: (&Camera, f64),
+    config: &Config,
+) -> Vec<Vec3> {
+    let t_min = t_min(world, config);
+    let (camera, time) = current;
+    // a single pixel row or column spans the whole screen
+    let (w, h) = (config.width.max(2) - 1, config.height.max(2) - 1);
+    let (w, h) = (w as f64, h as f64);
+    (0..config.width * config.height)
+        .into_par_iter()
+        .map(|i| {
+            let (s, t) = ((i % config.width) as f64 / w, (i / config.width) as f64 / h);
+            let r = ray(camera.eye(), camera.focus_point(s, t) - camera.eye(), time)
+                .with_kind(RayKind::Camera);
+            let rec = match world.hit(&r, t_min / r.dir.norm(), f64::INFINITY) {
+                Some(rec) => rec,
+                None => return Vec3::ZERO,
+            };
+
+            let point = rec.point - rec.velocity * (time - previous.1);
+            match (camera.project(&rec.point), previous.0.project(&point)) {
+                (Some((s, t)), Some((ps, pt))) => Vec3::new((s - ps) * w, (t - pt) * h, 0.0),
+                _ => Vec3::ZERO,
+            }
+        })
+        .collect()
+}
+
+/// Renders the samples `first_sample..first_sample + config.samples` of each
+/// pixel, returning their mean radiance and count. Every sample seeds the
+/// random generator from `seed`, its pixel and its index, so workers given
+/// disjoint sample ranges reproduce pieces of the same render.
+pub fn render_partial(
+    world: &impl Hit,
+    camera: &Camera,
+    background: Color,
+    config: &Config,
+    seed: u64,
+    first_sample: u32,
+) -> (Vec<Vec3>, Vec<u32>) {
+    let integrator = path_tracer(config);
+    render_partial_with(
+        world,
+        camera,
+        background,
+        config,
+        seed,
+        first_sample,
+        &integrator,
+    )
+}
+
+/// Same as `render_partial` with the radiance estimated by `integrator`.
+pub fn render_partial_with(
+    world: &impl Hit,
+    camera: &Camera,
+    background: Color,
+    config: &Config,
+    seed: u64,
+    first_sample: u32,
+    integrator: &dyn Integrator,
+) -> (Vec<Vec3>, Vec<u32>) {
+    let samples = first_sample..first_sample + config.samples;
+    let t_min = t_min(world, config);
+    let radiance = (0..config.width * config.height)
+        .into_par_iter()
+        .map(|i| {
+            seeded_samples(
+                world,
+                camera,
+                background,
+                config,
+                integrator,
+                t_min,
+                seed,
+                i,
+                samples.clone(),
+            ) / config.samples as f64
+        })
+        .collect();
+
+    (
+        radiance,
+        vec![config.samples; (config.width * config.height) as usize],
+    )
+}
+
+/// Adds `extra_samples` samples to each pixel of `buffer`, seeded like
+/// `render_partial` so that adding samples in several calls gives the same
+/// image as taking them at once.
+pub fn render_additional(
+    world: &impl Hit,
+    camera: &Camera,
+    background: Color,
+    config: &Config,
+    buffer: &mut AccumBuffer,
+    extra_samples: u32,
+) {
+    let seed = buffer.seed;
+    let t_min = t_min(world, config);
+    let integrator = path_tracer(config);
+    buffer
+        .sums
+        .par_iter_mut()
+        .zip(buffer.counts.par_iter_mut())
+        .enumerate()
+        .for_each(|(i, (sum, count))| {
+            let samples = *count..*count + extra_samples;
+            *sum += seeded_samples(
+                world,
+                camera,
+                background,
+                config,
+                &integrator,
+                t_min,
+                seed,
+                i as u32,
+                samples,
+            );
+            *count += extra_samples;
+        });
+}
+
+/// Order in which `render_tiled` renders its tiles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TileOrder {
+    /// row by row from the bottom of the image
+    Rows,
+    /// shuffled with the render seed, an interrupted render covers the whole frame
+    Shuffled,
+}
+
+impl FromStr for TileOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rows" => Ok(Self::Rows),
+            "shuffled" => Ok(Self::Shuffled),
+            _ => Err(format!("unknown tile order: {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for TileOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Rows => write!(f, "rows"),
+            Self::Shuffled => write!(f, "shuffled"),
+        }
+    }
+}
+
+/// Renders `config.samples` samples of each pixel by tiles of `TILE_SIZE`
+/// taken in `config.tile_order`, sampled like `render_partial` so that the
+/// image doesn't depend on the order. The threads take the tiles from a
+/// queue in that order, and once `cancel` is set the tiles not started yet are
+/// skipped, their pixels having a zero count ready for `merge_partials`.
+pub fn render_tiled(
+    world: &impl Hit,
+    camera: &Camera,
+    background: Color,
+    config: &Config,
+    seed: u64,
+    cancel: &AtomicBool,
+) -> (Vec<Vec3>, Vec<u32>) {
+    let integrator = path_tracer(config);
+    render_tiled_with(world, camera, background, config, seed, cancel, &integrator)
+}
+
+/// Same as `render_tiled` with the radiance estimated by `integrator`.
+pub fn render_tiled_with(
+    world: &impl Hit,
+    camera: &Camera,
+    background: Color,
+    config: &Config,
+    seed: u64,
+    cancel: &AtomicBool,
+    integrator: &dyn Integrator,
+) -> (Vec<Vec3>, Vec<u32>) {
+    let t_min = t_min(world, config);
+    let (w, h) = (config.width, config.height);
+    let mut tiles: Vec<(u32, u32)> = (0..h)
+        .step_by(TILE_SIZE as usize)
+        .flat_map(|y| (0..w).step_by(TILE_SIZE as usize).map(move |x| (x, y)))
+        .collect();
+    if config.tile_order == TileOrder::Shuffled {
+        fastrand::Rng::with_seed(seed).shuffle(&mut tiles);
+    }
+
+    let next = AtomicUsize::new(0);
+    let rendered: Vec<Vec<(u32, Vec3)>> = (0..rayon::current_num_threads())
+        .into_par_iter()
+        .map(|_| {
+            let mut pixels = Vec::new();
+            while !cancel.load(Ordering::Relaxed) {
+                let (x0, y0) = match tiles.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    Some(tile) => *tile,
+                    None => break,
+                };
+                let tile = (y0..(y0 + TILE_SIZE).min(h))
+                    .flat_map(|y| (x0..(x0 + TILE_SIZE).min(w)).map(move |x| y * w + x));
+                pixels.extend(tile.map(|i| {
+                    let samples = 0..config.samples;
+                    let sum = seeded_samples(
+                        world, camera, background, config, integrator, t_min, seed, i, samples,
+                    );
+                    (i, sum / config.samples as f64)
+                }));
+            }
+            pixels
+        })
+        .collect();
+
+    let mut radiance = vec![Vec3::ZERO; (w * h) as usize];
+    let mut counts = vec![0; (w * h) as usize];
+    for (i, r) in rendered.into_iter().flatten() {
+        radiance[i as usize] = r;
+        counts[i as usize] = config.samples;
+    }
+
+    (radiance, counts)
+}
+
+/// Combines partial renders into the mean radiance of all their samples.
+pub fn merge_partials(partials: &[(Vec<Vec3>, Vec<u32>)]) -> Vec<Vec3> {
+    let len = partials.first().map_or(0, |(radiance, _)| radiance.len());
+    (0..len)
+        .map(|i| {
+            let (sum, count) =
+                partials
+                    .iter()
+                    .fold((Vec3::ZERO, 0), |(sum, count), (radiance, counts)| {
+                        (sum + radiance[i] * counts[i] as f64, count + counts[i])
+                    });
+            if count == 0 {
+                Vec3::ZERO
+            } else {
+                sum / count as f64
+            }
+        })
+        .collect()
+}
+
+// radiance sum of the given samples of pixel `i`
+#[allow(clippy::too_many_arguments)]
+fn seeded_samples(
+    world: &impl Hit,
+    camera: &Camera,
+    background: Color,
+    config: &Config,
+    integrator: &dyn Integrator,
+    t_min: f64,
+    seed: u64,
+    i: u32,
+    samples: Range<u32>,
+) -> Vec3 {
+    let (x, y) = (i % config.width, i / config.width);
+    let mut sum = Vec3::ZERO;
+    for sample in samples {
+        // the two samples of an antithetic pair draw from the seed of the first
+        let first = if config.antithetic {
+            sample & !1
+        } else {
+            sample
+        };
+        let rng = fastrand::Rng::with_seed(sample_seed(seed, i, first));
+        let (du, dv, lens) = (rng.f64(), rng.f64(), Vec3::random_unit_disk_with(&rng));
+        // the time of the ray and its path draw from the thread generator
+        let paths = [rng.u64(..), rng.u64(..)];
+        fastrand::seed(paths[(sample - first) as usize]);
+        let r = if sample == first {
+            get_ray_at(x, y, (du, dv), lens, camera, config)
+        } else {
+            get_ray_at(x, y, (1.0 - du, 1.0 - dv), -lens, camera, config)
+        };
+        let color = integrator.radiance(&r, world, background.to_vec3(), config.depth, t_min);
+        check_nan(color, &r, world, t_min, (x, y, sample), config);
+        sum += color;
+    }
+
+    sum
+}
+
+// with `config.debug_nan`, panics on a non-finite sample of pixel (x, y)
+fn check_nan(
+    color: Vec3,
+    r: &Ray,
+    world: &impl Hit,
+    t_min: f64,
+    (x, y, sample): (u32, u32, u32),
+    config: &Config,
+) {
+    if config.debug_nan && (color.is_nan() || color.is_infinite()) {
+        let hit = world.hit(r, t_min / r.dir.norm(), f64::INFINITY);
+        panic!(
+            "non-finite radiance {:?} at pixel ({}, {}) sample {}, camera ray hit {}",
+            color,
+            x,
+            y,
+            sample,
+            hit.map_or("nothing".to_string(), |rec| format!("{:?}", rec.mat))
+        );
+    }
+}
+
+// splitmix64 of the render seed, pixel and sample index
+fn sample_seed(seed: u64, pixel: u32, sample: u32) -> u64 {
+    let mut z = seed ^ ((pixel as u64) << 32 | sample as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Copies a render buffer into an image, flipped so that its first row is the top.
+pub fn to_image(buffer: &[Color], config: &Config) -> RgbaImage {
+    let mut img: RgbaImage = ImageBuffer::new(config.width, config.height);
+    for (x, y, pixel) in img.enumerate_pixels_mut() {
+        *pixel = image::Rgba(buffer[(y * config.width + x) as usize].into());
+    }
+
+    image::imageops::flip_vertical(&img)
+}
+
+/// 16 bit per channel image of linear radiance, converted from
+/// `config.working_space` to `config.color_space`,
+/// clamped by `config.clamp_mode` and gamma encoded like `tonemap`, flipped like
+/// `to_image`.
+pub fn to_image_16(radiance: &[Vec3], config: &Config) -> ImageBuffer<image::Rgba<u16>, Vec<u16>> {
+    let quantize = |c: f64| (65536.0 * c.powf(0.45)).clamp(0.0, 65535.0) as u16;
+    let mut img = ImageBuffer::new(config.width, config.height);
+    for (x, y, pixel) in img.enumerate_pixels_mut() {
+        let v = config.color_space.convert_from(
+            config.working_space,
+            radiance[(y * config.width + x) as usize],
+        );
+        let v = config.clamp_mode.apply(v);
+        *pixel = image::Rgba([quantize(v.x), quantize(v.y), quantize(v.z), u16::MAX]);
+    }
+
+    image::imageops::flip_vertical(&img)
+}
+
+/// Grey image of the sample counts of `render_per_channel`, the most sampled
+/// channel of each pixel scaled so that the most sampled pixel is white.
+/// Flipped like `to_image`.
+pub fn sample_count_image(counts: &[[u32; 3]], config: &Config) -> RgbaImage {
+    let pixel_counts: Vec<u32> = counts.iter().map(|c| c[0].max(c[1]).max(c[2])).collect();
+    let max = pixel_counts.iter().copied().max().unwrap_or(0).max(1) as f64;
+    let mut img: RgbaImage = ImageBuffer::new(config.width, config.height);
+    for (x, y, pixel) in img.enumerate_pixels_mut() {
+        let count = pixel_counts[(y * config.width + x) as usize] as f64;
+        let grey = (255.0 * count / max).round() as u8;
+        *pixel = image::Rgba([grey, grey, grey, 255]);
+    }
+
+    image::imageops::flip_vertical(&img)
+}
+
+fn pass(
+    world: &impl Hit,
+    camera: &Camera,
+    background: Vec3,
+    config: &Config,
+    integrator: &dyn Integrator,
+) -> Vec<Accumulator> {
+    let cancel = AtomicBool::new(false);
+    cancellable_pass(world, camera, background, config, integrator, &cancel).unwrap()
+}
+
+// samples of a pass, None if `cancel` was set before all pixels were done
+fn cancellable_pass(
+    world: &impl Hit,
+    camera: &Camera,
+    background: Vec3,
+    config: &Config,
+    integrator: &dyn Integrator,
+    cancel: &AtomicBool,
+) -> Option<Vec<Accumulator>> {
+    let t_min = t_min(world, config);
+    (0..config.width * config.height)
+        .into_par_iter()
+        .map(|i| {
+            if cancel.load(Ordering::Relaxed) {
+                return None;
+            }
+
+            let (x, y) = (i % config.width, i / config.width);
+            let mut acc = (Vec3::ZERO, Vec3::ZERO);
+            let mut mirror = None;
+            for sample in 0..config.samples {
+                let r = match mirror.take() {
+                    Some(r) => r,
+                    None if config.antithetic => {
+                        let [r, m] = get_ray_antithetic(x, y, camera, config);
+                        mirror = Some(m);
+                        r
+                    }
+                    None => get_ray(x, y, camera, config),
+                };
+                let color = integrator.radiance(&r, world, background, config.depth, t_min);
+                check_nan(color, &r, world, t_min, (x, y, sample), config);
+                acc.0 += color;
+                acc.1 += color * color;
+            }
+
+            Some(acc)
+        })
+        .collect()
+}
+
+// display color in `config.color_space` of the sum of `samples` samples of
+// pixel `i`, in `config.working_space`
+fn quantize(sum: Vec3, samples: u32, i: usize, config: &Config) -> Color {
+    let sum = config.color_space.convert_from(config.working_space, sum);
+    if config.dither {
+        let (x, y) = (i as u32 % config.width, i as u32 / config.width);
+        Color::from_vec_dithered(sum, samples as u64, config.clamp_mode, x, y)
+    } else {
+        Color::from_vec_clamped(sum, samples as u64, config.clamp_mode)
+    }
+}
+
+/// The integrator of `render`, with the indirect clamp and Russian roulette
+/// of `config`.
+pub fn path_tracer(config: &Config) -> PathTracer {
+    PathTracer {
+        indirect_clamp: config.indirect_clamp,
+        roulette_min_bounces: config.roulette_min_bounces,
+    }
+}
+
+// self intersection distance of the render
+fn t_min(world: &impl Hit, config: &Config) -> f64 {
+    config.t_min.unwrap_or_else(|| scene_epsilon(world))
+}
+
+/// Mean over the pixels of the variance of their estimated color.
+fn mean_variance(acc: &[Accumulator], samples: u32) -> f64 {
+    if samples < 2 || acc.is_empty() {
+        return f64::INFINITY;
+    }
+
+    let n = samples as f64;
+    let total: f64 = acc
+        .iter()
+        .map(|(sum, squares)| {
+            let variance = (*squares - *sum * *sum / n) / ((n - 1.0) * n);
+            (variance.x + variance.y + variance.z) / 3.0
+        })
+        .sum();
+
+    total / acc.len() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    };
+
+    fn test_config() -> Config {
+        Config {
+            width: 8,
+            height: 8,
+            aspect_ratio: 1.0,
+            samples: 4,
+            depth: 5,
+            target_variance: 1e-6,
+            max_passes: 16,
+            ..Config::default()
+        }
+    }
+
+    fn test_camera() -> Camera {
+        Camera::new(
+            40.0,
+            vec3(0.0, 0.0, -5.0),
+            Vec3::ZERO,
+            1.0,
+            0.0,
+            5.0,
+            0.0,
+            0.0,
+        )
+    }
+
+    #[test]
+    fn test_flat_scene_converges_fast() {
+        let world = HittableList::new();
+        let (_, passes) =
+            render::render_progressive(&world, &test_camera(), Color::WHITE, &test_config());
+
+        assert!(passes <= 2);
+    }
+
+    #[test]
+    fn test_noisy_scene_needs_more_passes() {
+        let world: HittableList = vec![
+            Arc::new(Sphere::new(
+                Vec3::ZERO,
+                1.5,
+                Arc::new(Lambertian::from_color(Color::LIGHT_GREY)),
+            )),
+            Arc::new(Sphere::new(
+                vec3(0.0, -101.5, 0.0),
+                100.0,
+                Arc::new(Lambertian::from_color(Color::LIGHT_GREY)),
+            )),
+        ];
+        let (_, flat_passes) = render::render_progressive(
+            &HittableList::new(),
+            &test_camera(),
+            Color::WHITE,
+            &test_config(),
+        );
+        let (_, noisy_passes) =
+            render::render_progressive(&world, &test_camera(), Color::WHITE, &test_config());
+
+        assert!(noisy_passes > flat_passes);
+    }
+
+    #[test]
+    fn test_motion_vectors() {
+        let world: HittableList = vec![Arc::new(MovingSphere::new(
+            (vec3(-0.5, 0.0, 0.0), vec3(0.5, 0.0, 0.0)),
+            1.0,
+            0.0,
+            1.0,
+            Arc::new(Lambertian::from_color(Color::WHITE)),
+        ))];
+        let config = test_config();
+        let camera = test_camera();
+
+        let motion = render::motion_vectors(&world, (&camera, 1.0), (&camera, 0.0), &config);
+
+        let center = motion[(4 * config.width + 4) as usize];
+        assert!(center.x.abs() > 0.5);
+        assert!(center.y.abs() < 1e-9);
+        assert_eq!(motion[0], Vec3::ZERO);
+
+        let still = render::motion_vectors(&world, (&camera, 1.0), (&camera, 1.0), &config);
+        assert!(still.iter().all(|m| m.norm() < 1e-9));
+
+        let pixel = Config {
+            width: 1,
+            height: 1,
+            ..config
+        };
+        let motion = render::motion_vectors(&world, (&camera, 1.0), (&camera, 0.0), &pixel);
+        assert!(motion[0].x.is_finite() && motion[0].y.is_finite());
+    }
+
+    #[test]
+    fn test_merge_partials() {
+        let (world, camera, background) = scenes::cornell_box(1.0);
+        for antithetic in [false, true] {
+            let config = Config {
+                samples: 12,
+                antithetic,
+                ..test_config()
+            };
+            let full = render::render_partial(&world, &camera, background, &config, 7, 0);
+
+            // odd sample ranges split the antithetic pairs
+            let config = Config {
+                samples: 3,
+                ..config
+            };
+            let partials: Vec<_> = (0..4)
+                .map(|i| render::render_partial(&world, &camera, background, &config, 7, 3 * i))
+                .collect();
+            let merged = render::merge_partials(&partials);
+
+            assert!(full.0.iter().any(|v| *v != Vec3::ZERO));
+            for (m, f) in merged.iter().zip(full.0.iter()) {
+                assert!((*m - *f).norm() < 1e-9 * (1.0 + f.norm()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_glass_bounces_more_than_diffuse() {
+        let sphere = |mat: Arc<dyn Material>| -> HittableList {
+            vec![Arc::new(Sphere::new(Vec3::ZERO, 1.5, mat))]
+        };
+        let config = test_config();
+        let mean = |counts: Vec<f64>| counts.iter().sum::<f64>() / counts.len() as f64;
+
+        let diffuse = render::bounce_counts(
+            &sphere(Arc::new(Lambertian::default())),
+            &test_camera(),
+            &config,
+        );
+        let glass = render::bounce_counts(
+            &sphere(Arc::new(Dielectric::default())),
+            &test_camera(),
+            &config,
+        );
+
+        assert!(mean(glass) > mean(diffuse));
+    }
+
+    #[test]
+    fn test_debug_pixel_logs_each_sample() {
+        let (world, camera, background) = scenes::cornell_box(1.0);
+        let config = test_config();
+
+        let logs = render::debug_pixel(&world, &camera, background, &config, (4, 4));
+
+        assert_eq!(logs.len(), config.samples as usize);
+        assert!(logs
+            .iter()
+            .all(|l| l.materials[0].starts_with("Lambertian")));
+    }
+
+    #[test]
+    fn test_render_additional() {
+        let (world, camera, background) = scenes::cornell_box(1.0);
+        let config = test_config();
+
+        let mut twice = AccumBuffer::new(config.width, config.height);
+        render::render_additional(&world, &camera, background, &config, &mut twice, 50);
+        render::render_additional(&world, &camera, background, &config, &mut twice, 50);
+        let mut once = AccumBuffer::new(config.width, config.height);
+        render::render_additional(&world, &camera, background, &config, &mut once, 100);
+
+        assert_eq!(twice.counts, once.counts);
+        for (a, b) in twice.radiance().iter().zip(once.radiance().iter()) {
+            assert!((*a - *b).norm() < 1e-9 * (1.0 + b.norm()));
+        }
+    }
+
+    #[test]
+    fn test_scaled_scene_epsilon() {
+        let scene = |scale: f64| -> (HittableList, Camera) {
+            let world: HittableList = vec![
+                Arc::new(Sphere::new(
+                    Vec3::ZERO,
+                    scale,
+                    Arc::new(Lambertian::default()),
+                )),
+                Arc::new(Sphere::new(
+                    vec3(0.0, -101.0 * scale, 0.0),
+                    100.0 * scale,
+                    Arc::new(Lambertian::default()),
+                )),
+            ];
+            let eye = vec3(0.0, 0.0, -5.0 * scale);
+            let camera = Camera::new(40.0, eye, Vec3::ZERO, 1.0, 0.0, 5.0 * scale, 0.0, 0.0);
+            (world, camera)
+        };
+        let config = test_config();
+        let mean = |radiance: &[Vec3]| {
+            radiance.iter().map(|v| v.luminance()).sum::<f64>() / radiance.len() as f64
+        };
+
+        let (world, camera) = scene(1.0);
+        let (reference, _) = render::render_partial(&world, &camera, Color::WHITE, &config, 3, 0);
+        let (world, camera) = scene(1e5);
+        let (scaled, _) = render::render_partial(&world, &camera, Color::WHITE, &config, 3, 0);
+
+        assert_eq!(scene_epsilon(&world), 1e5 * scene_epsilon(&scene(1.0).0));
+        assert!((mean(&scaled) - mean(&reference)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_shadow_alpha() {
+        let light: HittableList = vec![Arc::new(XZRect::new(
+            (-0.5, 0.5),
+            (-0.5, 0.5),
+            3.0,
+            Arc::new(DiffuseLight::from_color(Color::WHITE, 10.0)),
+        ))];
+        let mut world = light.clone();
+        world.push(Arc::new(XZRect::new(
+            (-10.0, 10.0),
+            (-10.0, 10.0),
+            0.0,
+            Arc::new(ShadowCatcher),
+        )));
+        world.push(Arc::new(Sphere::new(
+            vec3(0.0, 1.0, 0.0),
+            0.5,
+            Arc::new(Lambertian::default()),
+        )));
+        let camera = Camera::new(
+            40.0,
+            vec3(0.0, 5.0, -5.0),
+            Vec3::ZERO,
+            1.0,
+            0.0,
+            7.0,
+            0.0,
+            0.0,
+        );
+        let config = Config {
+            width: 32,
+            height: 32,
+            samples: 16,
+            ..test_config()
+        };
+
+        let alpha = render::shadow_alpha(&world, &light, &camera, &config);
+        let at = |p: Point3| {
+            let (s, t) = camera.project(&p).unwrap();
+            let (x, y) = ((s * 31.0).round() as u32, (t * 31.0).round() as u32);
+            alpha[(y * config.width + x) as usize]
+        };
+
+        assert!(at(Vec3::ZERO) > 0.0);
+        assert_eq!(at(vec3(2.5, 0.0, 2.5)), 0.0);
+
+        // the catcher itself doesn't show in the beauty pass
+        let r = camera.get_ray(0.5, 0.5);
+        assert!(ray_color(&r, &world, 2, Vec3::ONE, T_MIN).x > 0.99);
+    }
+
+    #[test]
+    fn test_render_hdr_matches_tonemap() {
+        let world: HittableList = vec![Arc::new(Sphere::new(
+            Vec3::ZERO,
+            1.5,
+            Arc::new(Lambertian::from_color(Color::LIGHT_GREY)),
+        ))];
+        let (camera, bg, config) = (test_camera(), Color::WHITE, test_config());
+
+        let rgb = |colors: Vec<Color>| colors.iter().map(|c| (c.r, c.g, c.b)).collect::<Vec<_>>();
+
+        let (radiance, colors) = render::render_hdr(&world, &camera, bg, &config);
+        assert_eq!(rgb(colors), rgb(render::tonemap(&radiance, &config)));
+    }
+
+    #[test]
+    fn test_per_channel_sampling_favors_noisy_channel() {
+        // only the red channel of paths bouncing on the red spheres varies
+        let red: Arc<dyn Material> = Arc::new(Lambertian::from_color(Color::new(200, 0, 0, 255)));
+        let world: HittableList = vec![
+            Arc::new(Sphere::new(Vec3::ZERO, 1.5, red.clone())),
+            Arc::new(Sphere::new(vec3(0.0, -101.5, 0.0), 100.0, red)),
+        ];
+
+        let (radiance, counts) =
+            render::render_per_channel(&world, &test_camera(), Color::WHITE, &test_config());
+
+        assert!(radiance.iter().all(|v| !v.is_nan()));
+        let total = |c: usize| counts.iter().map(|n| n[c]).sum::<u32>();
+        assert!(total(0) > 2 * total(1));
+        assert_eq!(total(1), total(2));
+    }
+
+    #[test]
+    fn test_cancel_stops_render() {
+        let world: HittableList = vec![Arc::new(Sphere::new(
+            Vec3::ZERO,
+            1.5,
+            Arc::new(Lambertian::from_color(Color::LIGHT_GREY)),
+        ))];
+        // no target variance, so that only cancelling stops before the last pass
+        let config = Config {
+            samples: 16,
+            target_variance: 0.0,
+            max_passes: 500,
+            ..test_config()
+        };
+
+        let cancel = AtomicBool::new(true);
+        let (radiance, passes) =
+            render::render_cancellable(&world, &test_camera(), Color::WHITE, &config, &cancel);
+        assert_eq!(passes, 0);
+        assert!(radiance.iter().all(|v| *v == Vec3::ZERO));
+
+        let cancel = Arc::new(AtomicBool::new(false));
+        let flag = cancel.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            flag.store(true, Ordering::Relaxed);
+        });
+        let clock = std::time::Instant::now();
+        let (radiance, passes) =
+            render::render_cancellable(&world, &test_camera(), Color::WHITE, &config, &cancel);
+        assert!(clock.elapsed().as_secs() < 5);
+        assert!(radiance.iter().all(|v| !v.is_nan()));
+
+        let uncancelled =
+            render::render_progressive_hdr(&world, &test_camera(), Color::WHITE, &config).1;
+        assert_eq!(uncancelled, config.max_passes);
+        assert!(passes < uncancelled);
+    }
+
+    #[test]
+    fn test_trace_paths_in_mirror_box() {
+        let mirror: Arc<dyn Material> = Arc::new(Metal::new(Color::WHITE, 0.0));
+        let world: HittableList = vec![
+            Arc::new(XYRect::new((-2.0, 2.0), (-2.0, 2.0), -6.0, mirror.clone())),
+            Arc::new(XYRect::new((-2.0, 2.0), (-2.0, 2.0), 2.0, mirror.clone())),
+            Arc::new(XZRect::new((-2.0, 2.0), (-6.0, 2.0), -2.0, mirror.clone())),
+            Arc::new(XZRect::new((-2.0, 2.0), (-6.0, 2.0), 2.0, mirror.clone())),
+            Arc::new(YZRect::new((-2.0, 2.0), (-6.0, 2.0), -2.0, mirror.clone())),
+            Arc::new(YZRect::new((-2.0, 2.0), (-6.0, 2.0), 2.0, mirror)),
+        ];
+        let config = Config {
+            depth: 6,
+            ..test_config()
+        };
+
+        let paths = render::trace_paths(&world, &test_camera(), &config, &[(4, 4), (0, 7)]);
+        assert_eq!(paths.len(), 2);
+        for path in paths {
+            // the camera origin and a hit per bounce, never escaping the box
+            assert_eq!(path.len(), 7);
+            assert_eq!(path[0], vec3(0.0, 0.0, -5.0));
+            assert!(path[1..]
+                .iter()
+                .all(|p| p.x.abs().max(p.y.abs()) <= 2.0 + 1e-6));
+        }
+
+        let path = std::env::temp_dir().join("raytracing_weekend_paths.obj");
+        let paths = render::trace_paths(&world, &test_camera(), &config, &[(4, 4)]);
+        output::write_paths_obj(&path, &paths).unwrap();
+        let obj = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(obj.lines().filter(|l| l.starts_with("v ")).count(), 7);
+        assert_eq!(obj.lines().filter(|l| l.starts_with("l ")).count(), 1);
+    }
+
+    #[test]
+    fn test_16_bit_image_has_more_levels() {
+        let config = Config {
+            width: 4096,
+            height: 1,
+            ..Config::default()
+        };
+        let ramp: Vec<Vec3> = (0..4096).map(|i| Vec3::ONE * (i as f64 / 4095.0)).collect();
+
+        let levels_8 = render::to_image(&render::tonemap(&ramp, &config), &config)
+            .pixels()
+            .map(|p| p.0[0])
+            .collect::<std::collections::HashSet<_>>()
+            .len();
+        let image_16 = render::to_image_16(&ramp, &config);
+        let levels_16 = image_16
+            .pixels()
+            .map(|p| p.0[0])
+            .collect::<std::collections::HashSet<_>>()
+            .len();
+
+        assert!(levels_8 <= 256);
+        assert!(levels_16 > 4 * levels_8);
+        assert_eq!(image_16.get_pixel(4095, 0).0, [65535, 65535, 65535, 65535]);
+    }
+
+    #[derive(Debug)]
+    struct NanLight;
+
+    impl Material for NanLight {
+        fn scatter(&self, _r: &Ray, _rec: &HitRecord) -> Option<(Color, Ray)> {
+            None
+        }
+
+        fn emitted(&self, _u: f64, _v: f64, _point: &Point3) -> Vec3 {
+            Vec3::ONE * f64::NAN
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "camera ray hit NanLight")]
+    fn test_debug_nan_panics() {
+        let world: HittableList = vec![Arc::new(Sphere::new(Vec3::ZERO, 10.0, Arc::new(NanLight)))];
+        let config = Config {
+            debug_nan: true,
+            ..test_config()
+        };
+        render::render(&world, &test_camera(), Color::BLACK, &config);
+    }
+
+    #[test]
+    #[should_panic(expected = "camera ray hit NanLight")]
+    fn test_debug_nan_panics_in_seeded_renders() {
+        let world: HittableList = vec![Arc::new(Sphere::new(Vec3::ZERO, 10.0, Arc::new(NanLight)))];
+        let config = Config {
+            debug_nan: true,
+            ..test_config()
+        };
+        render::render_partial(&world, &test_camera(), Color::BLACK, &config, 3, 0);
+    }
+
+    #[test]
+    fn test_antithetic_lowers_variance() {
+        // emission rising linearly along x, smooth over a pixel
+        #[derive(Debug)]
+        struct Ramp;
+
+        impl Texture for Ramp {
+            fn texel(&self, _u: f64, _v: f64, point: &Point3) -> Color {
+                let t = 0.5 + 0.2 * point.x;
+                Color::from_floats([t, t, t, 1.0])
+            }
+        }
+
+        let ramp: Arc<dyn Texture> = Arc::new(Ramp);
+        let light = Arc::new(DiffuseLight::new(&ramp, 1.0));
+        let world: HittableList = vec![Arc::new(XYRect::new((-5.0, 5.0), (-5.0, 5.0), 0.0, light))];
+
+        // mean over the pixels of the variance of 2 samples estimates, of
+        // seeded renders or not
+        let variance = |antithetic: bool, seeded: bool| {
+            let config = Config {
+                samples: 2,
+                depth: 1,
+                antithetic,
+                ..test_config()
+            };
+            let camera = test_camera();
+            let renders: Vec<Vec<Vec3>> = (0..100)
+                .map(|seed| {
+                    if seeded {
+                        render::render_partial(&world, &camera, Color::BLACK, &config, seed, 0).0
+                    } else {
+                        render::render_hdr(&world, &camera, Color::BLACK, &config).0
+                    }
+                })
+                .collect();
+            let pixels = renders[0].len();
+            (0..pixels)
+                .map(|i| {
+                    let values: Vec<f64> = renders.iter().map(|r| r[i].x).collect();
+                    let mean = values.iter().sum::<f64>() / values.len() as f64;
+                    values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64
+                })
+                .sum::<f64>()
+                / pixels as f64
+        };
+
+        for seeded in [false, true] {
+            let (independent, antithetic) = (variance(false, seeded), variance(true, seeded));
+            assert!(
+                antithetic < 0.25 * independent,
+                "{} vs {}",
+                antithetic,
+                independent
+            );
+        }
+    }
+
+    #[test]
+    fn test_sample_count_image() {
+        let grey: Arc<dyn Material> = Arc::new(Lambertian::from_color(Color::LIGHT_GREY));
+        let world: HittableList = vec![
+            Arc::new(Sphere::new(Vec3::ZERO, 1.5, grey.clone())),
+            Arc::new(Sphere::new(vec3(0.0, -101.5, 0.0), 100.0, grey)),
+        ];
+        let config = test_config();
+
+        let (_, counts) = render::render_per_channel(&world, &test_camera(), Color::WHITE, &config);
+        let img = render::sample_count_image(&counts, &config);
+
+        // the noisy sphere and ground in the bottom half, the flat sky in the top row
+        let mean = |rows: std::ops::Range<u32>| {
+            let n = rows.len() as f64 * 8.0;
+            rows.flat_map(|y| (0..8).map(move |x| (x, y)))
+                .map(|(x, y)| img.get_pixel(x, y).0[0] as f64)
+                .sum::<f64>()
+                / n
+        };
+        assert!(
+            mean(4..8) > 4.0 * mean(0..1),
+            "{} vs {}",
+            mean(4..8),
+            mean(0..1)
+        );
+    }
+
+    #[test]
+    fn test_acescg_working_space() {
+        let srgb = Config::builder().width(1).height(1).build().unwrap();
+        let aces = Config {
+            working_space: ColorSpace::Acescg,
+            ..srgb.clone()
+        };
+        let channels = |c: Color| (c.r, c.g, c.b);
+        let encode = |v: Vec3, config: &Config| channels(render::tonemap(&[v], config)[0]);
+
+        let grey = Vec3::ONE * 0.18;
+        assert_eq!(encode(grey, &aces), encode(grey, &srgb));
+        assert!(
+            (ColorSpace::Acescg.to_srgb(ColorSpace::Acescg.convert(grey)) - grey).norm() < 1e-4
+        );
+
+        // the same values are a more saturated red in the wider ACEScg gamut
+        let red = vec3(0.6, 0.2, 0.1);
+        let (r1, g1, _) = encode(red, &srgb);
+        let (r2, g2, _) = encode(red, &aces);
+        assert!(r2 > r1 && g2 < g1);
+    }
+
+    #[test]
+    fn test_stereo() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::from_color(Color::new(200, 20, 20, 255)));
+        let world: HittableList = vec![Arc::new(Sphere::new(vec3(0.0, 0.0, -3.0), 0.5, mat))];
+        let config = test_config();
+
+        // the near sphere shifts between the eyes
+        let (left, right) =
+            render::render_stereo(&world, &test_camera(), Color::WHITE, &config, 0.5);
+        assert_ne!(left, right);
+
+        let sbs =
+            render::stereo_composite(&left, &right, &config, render::StereoLayout::SideBySide);
+        assert_eq!(sbs.len(), 2 * left.len());
+        assert_eq!(&sbs[8..16], &right[0..8]);
+        assert_eq!(&sbs[16..24], &left[8..16]);
+
+        let l = vec![Color::new(200, 10, 10, 255); 64];
+        let r = vec![Color::new(10, 100, 150, 255); 64];
+        let anaglyph = render::stereo_composite(&l, &r, &config, render::StereoLayout::Anaglyph);
+        assert!(anaglyph
+            .iter()
+            .all(|c| *c == Color::new(200, 100, 150, 255)));
+    }
+
+    #[test]
+    fn test_tile_order_gives_same_render() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::from_color(Color::LIGHT_GREY));
+        let world: HittableList = vec![Arc::new(Sphere::new(Vec3::ZERO, 1.5, mat))];
+        let config = Config {
+            width: 40,
+            height: 24,
+            ..test_config()
+        };
+        let shuffled = Config {
+            tile_order: render::TileOrder::Shuffled,
+            ..config.clone()
+        };
+        let cancel = AtomicBool::new(false);
+
+        let rows = render::render_tiled(&world, &test_camera(), Color::WHITE, &config, 3, &cancel);
+        let tiles =
+            render::render_tiled(&world, &test_camera(), Color::WHITE, &shuffled, 3, &cancel);
+        assert_eq!(rows, tiles);
+        assert!(rows.1.iter().all(|c| *c == config.samples));
+        assert_eq!(
+            "shuffled".parse::<render::TileOrder>(),
+            Ok(render::TileOrder::Shuffled)
+        );
+    }
+
+    #[test]
+    fn test_cancelled_rows_render_bottom_tiles() {
+        // cancels the render after `limit` samples
+        struct Cancelling {
+            samples: AtomicUsize,
+            limit: usize,
+            cancel: Arc<AtomicBool>,
+        }
+
+        impl Integrator for Cancelling {
+            fn radiance(&self, _: &Ray, _: &dyn Hit, _: Vec3, _: u32, _: f64) -> Vec3 {
+                if self.samples.fetch_add(1, Ordering::Relaxed) + 1 >= self.limit {
+                    self.cancel.store(true, Ordering::Relaxed);
+                }
+                Vec3::ONE
+            }
+        }
+
+        let world: HittableList = vec![Arc::new(Sphere::new(
+            Vec3::ZERO,
+            1.5,
+            Arc::new(Lambertian::default()),
+        ))];
+        // more tiles than can be in progress when the render is cancelled
+        let size = render::TILE_SIZE;
+        let config = Config {
+            width: size * (rayon::current_num_threads() as u32 + 3),
+            height: 2 * size,
+            ..test_config()
+        };
+        let cancel = Arc::new(AtomicBool::new(false));
+        let integrator = Cancelling {
+            samples: AtomicUsize::new(0),
+            limit: 2 * (size * size * config.samples) as usize,
+            cancel: cancel.clone(),
+        };
+        let (_, counts) = render::render_tiled_with(
+            &world,
+            &test_camera(),
+            Color::WHITE,
+            &config,
+            3,
+            &cancel,
+            &integrator,
+        );
+
+        // the tiles rendered are the first ones row by row
+        let tiles_x = config.width / size;
+        let tile = |i: u32| (i / config.width / size) * tiles_x + i % config.width / size;
+        let mut rendered = vec![false; (tiles_x * config.height / size) as usize];
+        for (i, count) in counts.iter().enumerate() {
+            rendered[tile(i as u32) as usize] |= *count > 0;
+        }
+        let first = rendered.iter().take_while(|r| **r).count();
+        assert!(first >= 2 && first < rendered.len(), "{:?}", rendered);
+        assert!(rendered[first..].iter().all(|r| !r), "{:?}", rendered);
+    }
+
+    #[test]
+    fn test_custom_integrator() {
+        struct Direction;
+
+        impl Integrator for Direction {
+            fn radiance(&self, r: &Ray, _: &dyn Hit, _: Vec3, _: u32, _: f64) -> Vec3 {
+                r.dir.normalize()
+            }
+        }
+
+        let world: HittableList = vec![Arc::new(Sphere::new(
+            Vec3::ZERO,
+            1.5,
+            Arc::new(Lambertian::default()),
+        ))];
+        let config = test_config();
+        let colors = render::render_with(&world, &test_camera(), Color::WHITE, &config, &Direction);
+
+        // the camera looks down +z, the rays of one side of the image have
+        // a positive x and of the other side a clamped negative one
+        let center = colors[4 * 8 + 4];
+        assert!(
+            center.b > 240 && center.r < 128 && center.g < 128,
+            "{:?}",
+            center
+        );
+        let (left, right) = (colors[4 * 8].r, colors[4 * 8 + 7].r);
+        assert!(
+            left.min(right) == 0 && left.max(right) > 100,
+            "{} {}",
+            left,
+            right
+        );
+        assert_ne!(
+            colors,
+            render::render(&world, &test_camera(), Color::WHITE, &config)
+        );
+
+        let camera = test_camera();
+        let cancel = AtomicBool::new(false);
+        let renders = [
+            render::render_progressive_hdr_with(&world, &camera, Color::WHITE, &config, &Direction)
+                .0,
+            render::render_per_channel_with(&world, &camera, Color::WHITE, &config, &Direction).0,
+            render::render_partial_with(&world, &camera, Color::WHITE, &config, 3, 0, &Direction).0,
+            render::render_tiled_with(
+                &world,
+                &camera,
+                Color::WHITE,
+                &config,
+                3,
+                &cancel,
+                &Direction,
+            )
+            .0,
+        ];
+        for radiance in &renders {
+            assert!(radiance[4 * 8 + 4].z > 0.95, "{:?}", radiance[4 * 8 + 4]);
+        }
+    }
+}
@@ -1,9 +1,13 @@
+#![allow(clippy::vec_init_then_push)]
+
 use super::{
-    random, random_range, vec3, Camera, CheckerTexture, Color, Cube, Dielectric, DiffuseLight,
-    HittableList, ImageTexture, Lambertian, Metal, MovingSphere, Point3, Sphere, Vec3,
+    mesh, radians, random, random_range, vec3, Camera, CheckerTexture, Color, Cube, Dielectric,
+    DiffuseLight, Hit, HittableList, ImageTexture, Lambertian, Material, Metal, MovingSphere, NoiseTexture, Point3,
+    Sphere, Vec3,
     XZRect, YZRect, XYRect,
 };
-use std::{path::Path, sync::Arc};
+use serde::Deserialize;
+use std::{io, path::Path, sync::Arc};
 
 pub fn two_spheres(a: f64) -> (HittableList, Camera, Color) {
     let mut world = HittableList::with_capacity(4);
@@ -80,7 +84,7 @@ pub fn globe(a: f64) -> (HittableList, Camera, Color) {
     world.push(Arc::new(Sphere::new(
         Vec3::ZERO,
         2.0,
-        Arc::new(Lambertian::new(ImageTexture::from_path(&Path::new(
+        Arc::new(Lambertian::new(ImageTexture::from_path(Path::new(
             "assets/earthmap.jpg",
         )))),
     )));
@@ -98,6 +102,34 @@ pub fn globe(a: f64) -> (HittableList, Camera, Color) {
     (world, camera, Color::WHITE)
 }
 
+/// Reference image of the noise textures: two marble spheres of different
+/// vein frequencies on a marble ground.
+pub fn two_perlin_spheres(a: f64) -> (HittableList, Camera, Color) {
+    let mut world = HittableList::with_capacity(3);
+
+    world.push(Arc::new(Sphere::new(
+        vec3(0.0, -1000.0, 0.0),
+        1000.0,
+        Arc::new(Lambertian::new(NoiseTexture::new(1.0))),
+    )));
+    world.push(Arc::new(Sphere::new(
+        vec3(0.0, 2.0, -2.5),
+        2.0,
+        Arc::new(Lambertian::new(NoiseTexture::new(4.0))),
+    )));
+    world.push(Arc::new(Sphere::new(
+        vec3(0.0, 2.0, 2.5),
+        2.0,
+        Arc::new(Lambertian::new(NoiseTexture::new(12.0))),
+    )));
+
+    let eye = vec3(13.0, 2.0, 3.0);
+    let lookat = vec3(0.0, 1.0, 0.0);
+    let camera = Camera::new(30.0, eye, lookat, a, 0.0, 10.0, 0.0, 0.0);
+
+    (world, camera, Color::new(179, 204, 255, 255))
+}
+
 pub fn spheres(a: f64) -> (HittableList, Camera, Color) {
     let mut w = HittableList::with_capacity(500);
     w.push(Arc::new(Sphere::new(
@@ -282,3 +314,107 @@ pub fn cornell_box(a: f64) -> (HittableList, Camera, Color) {
 
     (world, camera, Color::BLACK)
 }
+
+/// Mesh of an `.obj` file lit by a white sky, the camera looking at it from
+/// the -z side and framing its bounding sphere.
+pub fn from_obj(path: &Path, a: f64) -> io::Result<(HittableList, Camera, Color)> {
+    let world = mesh::load_obj(path, Arc::new(Lambertian::default()))?;
+    let (center, radius) = world.bounding_sphere().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "obj file without faces")
+    })?;
+
+    let fov = 40.0;
+    let half_fov = (radians(fov) / 2.0).min(((radians(fov) / 2.0).tan() * a).atan());
+    let distance = 1.1 * radius / half_fov.sin();
+    let eye = center - vec3(0.0, 0.0, distance);
+    let camera = Camera::new(fov, eye, center, a, 0.0, distance, 0.0, 0.0);
+
+    Ok((world, camera, Color::WHITE))
+}
+
+/// Scene of a RON description, for example
+/// `(camera: (eye: (x: 0, y: 1, z: -5), target: (x: 0, y: 1, z: 0)), objects: [Sphere(
+/// center: (x: 0, y: 1, z: 0), radius: 1, material: Lambertian((x: 0.8, y: 0.2, z: 0.2)))])`.
+pub fn from_ron(
+    src: &str,
+    a: f64,
+) -> Result<(HittableList, Camera, Color), ron::error::SpannedError> {
+    let scene: SceneDescription = ron::from_str(src)?;
+    let c = &scene.camera;
+    let focus_dist = c.focus_dist.unwrap_or_else(|| (c.target - c.eye).norm());
+    let camera = Camera::new(c.fov, c.eye, c.target, a, c.aperture, focus_dist, 0.0, 0.0);
+
+    let world = scene.objects.iter().map(ObjectDescription::build).collect();
+    Ok((world, camera, color(scene.background)))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct SceneDescription {
+    camera: CameraDescription,
+    #[serde(default = "white")]
+    background: Vec3,
+    objects: Vec<ObjectDescription>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct CameraDescription {
+    eye: Point3,
+    target: Point3,
+    #[serde(default = "default_fov")]
+    fov: f64,
+    #[serde(default)]
+    aperture: f64,
+    /// distance to the target when missing
+    #[serde(default)]
+    focus_dist: Option<f64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+enum ObjectDescription {
+    Sphere { center: Point3, radius: f64, material: MaterialDescription },
+    Cube { min: Point3, max: Point3, material: MaterialDescription },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+enum MaterialDescription {
+    Lambertian(Vec3),
+    Metal { albedo: Vec3, fuzz: f64 },
+    Dielectric(f64),
+    DiffuseLight { color: Vec3, power: f64 },
+}
+
+impl ObjectDescription {
+    fn build(&self) -> Arc<dyn Hit> {
+        match self {
+            Self::Sphere { center, radius, material } => {
+                Arc::new(Sphere::new(*center, *radius, material.build()))
+            }
+            Self::Cube { min, max, material } => Arc::new(Cube::new(*min, *max, material.build())),
+        }
+    }
+}
+
+impl MaterialDescription {
+    fn build(&self) -> Arc<dyn Material> {
+        match self {
+            Self::Lambertian(albedo) => Arc::new(Lambertian::from_color(color(*albedo))),
+            Self::Metal { albedo, fuzz } => Arc::new(Metal::new(color(*albedo), *fuzz)),
+            Self::Dielectric(eta) => Arc::new(Dielectric::new(*eta)),
+            Self::DiffuseLight { color: c, power } => {
+                Arc::new(DiffuseLight::from_color(color(*c), *power))
+            }
+        }
+    }
+}
+
+fn color(v: Vec3) -> Color {
+    Color::from_floats([v.x, v.y, v.z, 1.0])
+}
+
+fn white() -> Vec3 {
+    Vec3::ONE
+}
+
+fn default_fov() -> f64 {
+    40.0
+}
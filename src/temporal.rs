@@ -0,0 +1,153 @@
+use super::{Camera, Color, Vec3};
+
+/// Blends successive frames of an animation to reduce noise.
+///
+/// Each pixel of the current frame is reprojected on the previous frame
+/// through the focus plane of both cameras and averaged with its history.
+/// Pixels that fall out of the previous frame start a new history, and the
+/// whole history is dropped when the camera moves more than `max_shift`
+/// pixels between two frames.
+#[derive(Debug, Clone)]
+pub struct TemporalAccumulator {
+    width: u32,
+    height: u32,
+    pub max_shift: f64,
+    history: Vec<(Vec3, u32)>,
+    camera: Option<Camera>,
+}
+
+impl TemporalAccumulator {
+    pub fn new(width: u32, height: u32, max_shift: f64) -> Self {
+        Self {
+            width,
+            height,
+            max_shift,
+            history: Vec::new(),
+            camera: None,
+        }
+    }
+
+    pub fn accumulate(&mut self, current: &[Color], camera: &Camera) -> Vec<Color> {
+        let previous = match self.camera {
+            Some(previous) if !self.rejects(&previous, camera) => previous,
+            _ => {
+                self.reset(current, camera);
+                return current.to_vec();
+            }
+        };
+
+        let mut history = Vec::with_capacity(current.len());
+        for (i, color) in current.iter().enumerate() {
+            let (x, y) = (i as u32 % self.width, i as u32 / self.width);
+            let color = color.to_vec3();
+            let entry = match self.reproject(&previous, camera, x, y) {
+                Some(j) => {
+                    let (mean, frames) = self.history[j];
+                    (mean + (color - mean) / (frames + 1) as f64, frames + 1)
+                }
+                None => (color, 1),
+            };
+            history.push(entry);
+        }
+
+        self.history = history;
+        self.camera = Some(*camera);
+        self.history
+            .iter()
+            .map(|(mean, _)| Color::from_floats([mean.x, mean.y, mean.z, 1.0]))
+            .collect()
+    }
+
+    pub fn reset(&mut self, current: &[Color], camera: &Camera) {
+        self.history = current.iter().map(|c| (c.to_vec3(), 1)).collect();
+        self.camera = Some(*camera);
+    }
+
+    // index in the history of the pixel seen by `previous` at the place of (x, y)
+    fn reproject(&self, previous: &Camera, camera: &Camera, x: u32, y: u32) -> Option<usize> {
+        let (w, h) = self.scale();
+        let point = camera.focus_point(x as f64 / w, y as f64 / h);
+        let (s, t) = previous.project(&point)?;
+        let (i, j) = ((s * w).round(), (t * h).round());
+        if i < 0.0 || j < 0.0 || i >= self.width as f64 || j >= self.height as f64 {
+            return None;
+        }
+
+        Some(j as usize * self.width as usize + i as usize)
+    }
+
+    // reject the history when the frame corners or center moved too much
+    fn rejects(&self, previous: &Camera, camera: &Camera) -> bool {
+        let (w, h) = self.scale();
+        [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0), (0.5, 0.5)]
+            .iter()
+            .any(
+                |(s, t)| match previous.project(&camera.focus_point(*s, *t)) {
+                    Some((ps, pt)) => ((ps - s) * w).hypot((pt - t) * h) > self.max_shift,
+                    None => true,
+                },
+            )
+    }
+
+    // pixels per unit of screen coordinates, a single pixel row or column
+    // spanning the whole unit
+    fn scale(&self) -> (f64, f64) {
+        let (w, h) = (self.width.max(2) - 1, self.height.max(2) - 1);
+        (w as f64, h as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn camera(eye: Point3) -> Camera {
+        Camera::new(
+            40.0,
+            eye,
+            eye + vec3(0.0, 0.0, 1.0),
+            1.0,
+            0.0,
+            5.0,
+            0.0,
+            0.0,
+        )
+    }
+
+    #[test]
+    fn test_static_camera_converges_to_mean() {
+        let camera = camera(Vec3::ZERO);
+        let frames = [
+            Color::from_rgb(100, 100, 100),
+            Color::from_rgb(200, 200, 200),
+        ];
+
+        // single pixel rows and columns reproject without dividing by zero
+        for &(width, height) in &[(4, 4), (1, 1), (4, 1)] {
+            let mut acc = TemporalAccumulator::new(width, height, 2.0);
+            let len = (width * height) as usize;
+            let mut out = Vec::new();
+            for i in 0..4 {
+                out = acc.accumulate(&vec![frames[i % 2]; len], &camera);
+            }
+
+            for c in out {
+                assert_eq!((c.r, c.g, c.b), (150, 150, 150));
+            }
+        }
+    }
+
+    #[test]
+    fn test_camera_jump_resets() {
+        let mut acc = TemporalAccumulator::new(4, 4, 2.0);
+        acc.accumulate(&[Color::from_rgb(100, 100, 100); 16], &camera(Vec3::ZERO));
+        let out = acc.accumulate(
+            &[Color::from_rgb(200, 200, 200); 16],
+            &camera(vec3(50.0, 0.0, 0.0)),
+        );
+
+        for c in out {
+            assert_eq!((c.r, c.g, c.b), (200, 200, 200));
+        }
+    }
+}
@@ -1,9 +1,22 @@
 use std::{path::Path, sync::Arc};
 use image::RgbaImage;
-use crate::{Color, Point3};
+use crate::{vec3, Color, HitRecord, Point3, Vec3};
 
 pub trait Texture: std::fmt::Debug + Send + Sync {
     fn texel(&self, u: f64, v: f64, point: &Point3) -> Color;
+
+    /// Texel at a hit, for textures that need more of it than its texture
+    /// coordinates and point, like the barycentric coordinates of
+    /// `VertexColorTexture`.
+    fn texel_hit(&self, rec: &HitRecord) -> Color {
+        self.texel(rec.u, rec.v, &rec.point)
+    }
+
+    /// Scalar value of the texture, for textures used as a field rather
+    /// than a color. Defaults to the luminance of the texel.
+    fn value(&self, u: f64, v: f64, point: &Point3) -> f64 {
+        self.texel(u, v, point).to_vec3().luminance()
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -45,17 +58,27 @@ impl CheckerTexture {
     }
 }
 
-impl Texture for CheckerTexture {
-    fn texel(&self, u: f64, v: f64, point: &Point3) -> Color {
+impl CheckerTexture {
+    fn square(&self, point: &Point3) -> &Arc<dyn Texture> {
         let sines = 10.0 * point.x.sin() * 10.0 * point.y.sin() * 10.0 * point.z.sin();
         if sines < 0.0 {
-            return self.odd.texel(u, v, point);
+            &self.odd
         } else {
-            return self.even.texel(u, v, point);
+            &self.even
         }
     }
 }
 
+impl Texture for CheckerTexture {
+    fn texel(&self, u: f64, v: f64, point: &Point3) -> Color {
+        self.square(point).texel(u, v, point)
+    }
+
+    fn texel_hit(&self, rec: &HitRecord) -> Color {
+        self.square(&rec.point).texel_hit(rec)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ImageTexture {
     data: RgbaImage,
@@ -79,7 +102,7 @@ impl ImageTexture {
 
 impl Texture for ImageTexture {
     fn texel(&self, u: f64, v: f64, _: &Point3) -> Color {
-        if self.data.len() == 0 {
+        if self.data.is_empty() {
             return Color::CYAN;
         }
 
@@ -96,4 +119,265 @@ impl Texture for ImageTexture {
         let pixel = self.data.get_pixel(i, j);
         Color::from(pixel.0)
     }
-}
\ No newline at end of file
+}
+
+/// Colors of the corners of triangles blended by the barycentric coordinates
+/// of their hits, the first corner at (0, 0), the second at (1, 0) and the
+/// third at (0, 1). Hits of other objects blend by their texture coordinates.
+#[derive(Debug, Clone, Copy)]
+pub struct VertexColorTexture {
+    pub colors: [Color; 3],
+}
+
+impl VertexColorTexture {
+    pub fn new(colors: [Color; 3]) -> Self {
+        Self { colors }
+    }
+}
+
+impl Texture for VertexColorTexture {
+    fn texel(&self, u: f64, v: f64, _point: &Point3) -> Color {
+        let [a, b, c] = self.colors.map(|c| c.to_vec3());
+        let color = (1.0 - u - v) * a + u * b + v * c;
+        Color::from_floats([color.x, color.y, color.z, 1.0])
+    }
+
+    fn texel_hit(&self, rec: &HitRecord) -> Color {
+        let (u, v) = rec.barycentric.unwrap_or((rec.u, rec.v));
+        self.texel(u, v, &rec.point)
+    }
+}
+
+const POINT_COUNT: usize = 256;
+
+/// Perlin gradient noise, with random unit gradients on an integer lattice.
+#[derive(Clone)]
+pub struct Perlin {
+    gradients: Vec<Vec3>,
+    perm: [Vec<usize>; 3],
+}
+
+impl Perlin {
+    pub fn new() -> Self {
+        let gradients = (0..POINT_COUNT).map(|_| Vec3::random_unit_sphere()).collect();
+        let permutation = || {
+            let mut p: Vec<usize> = (0..POINT_COUNT).collect();
+            fastrand::shuffle(&mut p);
+            p
+        };
+        Self { gradients, perm: [permutation(), permutation(), permutation()] }
+    }
+
+    /// Noise in [-1, 1] at `point`, smoothly interpolated between the lattice gradients.
+    pub fn noise(&self, point: &Point3) -> f64 {
+        let floor = [point.x.floor(), point.y.floor(), point.z.floor()];
+        let f = [point.x - floor[0], point.y - floor[1], point.z - floor[2]];
+        // Hermite smoothing of the interpolation factors
+        let w = [0, 1, 2].map(|k| f[k] * f[k] * (3.0 - 2.0 * f[k]));
+
+        let mut sum = 0.0;
+        for corner in 0..8 {
+            let c = [corner & 1, corner >> 1 & 1, corner >> 2 & 1];
+            let hash = (0..3).fold(0, |hash, k| {
+                let lattice = (floor[k] as i64 + c[k] as i64).rem_euclid(POINT_COUNT as i64);
+                hash ^ self.perm[k][lattice as usize]
+            });
+            let weight = vec3(f[0] - c[0] as f64, f[1] - c[1] as f64, f[2] - c[2] as f64);
+            let blend = (0..3)
+                .map(|k| if c[k] == 1 { w[k] } else { 1.0 - w[k] })
+                .product::<f64>();
+            sum += blend * self.gradients[hash].dot(weight);
+        }
+
+        sum
+    }
+
+    /// Sum of `depth` octaves of noise of halving amplitude.
+    pub fn turbulence(&self, point: &Point3, depth: u32) -> f64 {
+        let mut sum = 0.0;
+        let mut p = *point;
+        let mut weight = 1.0;
+        for _ in 0..depth {
+            sum += weight * self.noise(&p);
+            weight *= 0.5;
+            p = 2.0 * p;
+        }
+
+        sum.abs()
+    }
+}
+
+impl Default for Perlin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for Perlin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Perlin").finish_non_exhaustive()
+    }
+}
+
+/// Marble like veins of turbulence, `scale` being their frequency.
+#[derive(Debug, Clone)]
+pub struct NoiseTexture {
+    pub noise: Perlin,
+    pub scale: f64,
+}
+
+impl NoiseTexture {
+    pub fn new(scale: f64) -> Self {
+        Self { noise: Perlin::new(), scale }
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn texel(&self, _u: f64, _v: f64, point: &Point3) -> Color {
+        let turbulence = self.noise.turbulence(point, 7);
+        let value = 0.5 * (1.0 + (self.scale * point.z + 10.0 * turbulence).sin());
+        Color::from_floats([value, value, value, 1.0])
+    }
+}
+
+/// Grid of values filling the box from `min` to `max`, trilinearly
+/// interpolated between the voxel centers.
+#[derive(Debug, Clone)]
+pub struct Volume3DTexture {
+    data: Vec<f64>,
+    dims: (usize, usize, usize),
+    min: Point3,
+    max: Point3,
+}
+
+impl Volume3DTexture {
+    /// `data` is indexed x first, then y and z, like `Vec3::to_voxel_index`.
+    pub fn new(data: Vec<f64>, dims: (usize, usize, usize), min: Point3, max: Point3) -> Self {
+        assert_eq!(data.len(), dims.0 * dims.1 * dims.2, "volume data does not match its size");
+        Self { data, dims, min, max }
+    }
+
+    pub fn sample(&self, point: &Point3) -> f64 {
+        let dims = [self.dims.0, self.dims.1, self.dims.2];
+
+        // lower voxel and interpolation factor along each axis
+        let mut base = [0usize; 3];
+        let mut f = [0.0; 3];
+        for k in 0..3 {
+            let last = (dims[k] - 1) as f64;
+            let t = (point[k] - self.min[k]) / (self.max[k] - self.min[k]);
+            let g = (t * dims[k] as f64 - 0.5).clamp(0.0, last);
+            base[k] = (g.floor() as usize).min(dims[k] - 1);
+            f[k] = g - base[k] as f64;
+        }
+
+        let mut value = 0.0;
+        for corner in 0..8 {
+            let mut voxel = [0.0; 3];
+            let mut weight = 1.0;
+            for k in 0..3 {
+                let upper = corner >> k & 1 == 1;
+                weight *= if upper { f[k] } else { 1.0 - f[k] };
+                voxel[k] = (base[k] + upper as usize).min(dims[k] - 1) as f64;
+            }
+            if weight > 0.0 {
+                let index = vec3(voxel[0], voxel[1], voxel[2]).to_voxel_index(self.dims);
+                value += weight * self.data[index.unwrap()];
+            }
+        }
+
+        value
+    }
+}
+
+impl Texture for Volume3DTexture {
+    fn texel(&self, _u: f64, _v: f64, point: &Point3) -> Color {
+        let value = self.sample(point).clamp(0.0, 1.0);
+        Color::from_floats([value, value, value, 1.0])
+    }
+
+    fn value(&self, _u: f64, _v: f64, point: &Point3) -> f64 {
+        self.sample(point)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn test_volume_texture_interpolates() {
+        let data = (0..8).map(|i| i as f64).collect();
+        let volume = Volume3DTexture::new(data, (2, 2, 2), Vec3::ZERO, vec3(2.0, 2.0, 2.0));
+
+        assert_eq!(volume.sample(&vec3(0.5, 0.5, 0.5)), 0.0);
+        assert_eq!(volume.sample(&vec3(1.5, 0.5, 0.5)), 1.0);
+        assert_eq!(volume.sample(&vec3(1.5, 1.5, 1.5)), 7.0);
+        assert_eq!(volume.sample(&vec3(1.0, 0.5, 0.5)), 0.5);
+        assert_eq!(volume.sample(&vec3(1.0, 1.0, 1.0)), 3.5);
+        assert_eq!(volume.sample(&vec3(-1.0, 0.5, 0.5)), 0.0);
+        assert_eq!(volume.value(0.0, 0.0, &vec3(1.5, 1.5, 1.5)), 7.0);
+    }
+
+    #[test]
+    fn test_perlin_noise_is_bounded_and_smooth() {
+        let perlin = Perlin::new();
+        for _ in 0..1000 {
+            let p = Vec3::random(-20.0, 20.0);
+            let n = perlin.noise(&p);
+            assert!((-1.0..=1.0).contains(&n));
+            assert!((perlin.noise(&(p + vec3(1e-6, 0.0, 0.0))) - n).abs() < 1e-4);
+        }
+        assert_eq!(perlin.noise(&vec3(3.0, -2.0, 7.0)), 0.0);
+    }
+
+    #[test]
+    fn test_two_perlin_spheres() {
+        let (world, _, _) = scenes::two_perlin_spheres(16.0 / 9.0);
+        assert_eq!(world.len(), 3);
+
+        // Perlin noise vanishes on the integer lattice, so there the texel of
+        // a marble texture only depends on its scale
+        let marble = |object: &std::sync::Arc<dyn Hit>, r: Ray, scale: f64| {
+            let mut rec = object.hit(&r, 0.001, f64::INFINITY).unwrap();
+            let expected = NoiseTexture::new(scale);
+            (1..20).all(|z| {
+                rec.point = vec3(rec.point.x.round(), rec.point.y.round(), z as f64);
+                rec.mat.scatter(&r, &rec).unwrap().0 == expected.texel(0.0, 0.0, &rec.point)
+            })
+        };
+        let down = ray(vec3(0.0, 10.0, 0.0), vec3(0.0, -1.0, 0.0), 0.0);
+        let back = ray(vec3(10.0, 2.0, -2.5), vec3(-1.0, 0.0, 0.0), 0.0);
+        let front = ray(vec3(10.0, 2.0, 2.5), vec3(-1.0, 0.0, 0.0), 0.0);
+        assert!(marble(&world[0], down, 1.0));
+        assert!(marble(&world[1], back, 4.0));
+        assert!(marble(&world[2], front, 12.0));
+        assert!(!marble(&world[2], front, 4.0));
+    }
+
+    #[test]
+    fn test_vertex_colors_blend_at_centroid() {
+        let texture = VertexColorTexture::new([
+            Color::new(255, 0, 0, 255),
+            Color::new(0, 255, 0, 255),
+            Color::new(0, 0, 255, 255),
+        ]);
+        let triangle = Triangle::new(
+            [vec3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0)],
+            std::sync::Arc::new(Lambertian::new(texture)),
+        );
+        let mapped = triangle.clone().with_uvs([(1.0, 0.0); 3]);
+
+        let r = ray(vec3(1.0 / 3.0, 1.0 / 3.0, 1.0), vec3(0.0, 0.0, -1.0), 0.0);
+        for triangle in [triangle, mapped].iter() {
+            let rec = triangle.hit(&r, 0.001, f64::INFINITY).unwrap();
+            let c = texture.texel_hit(&rec);
+            for channel in [c.r, c.g, c.b].iter() {
+                assert!((*channel as i32 - 85).abs() <= 1);
+            }
+        }
+
+        let corner = texture.texel(1.0, 0.0, &Vec3::ZERO);
+        assert_eq!((corner.r, corner.g, corner.b), (0, 255, 0));
+    }
+}
@@ -0,0 +1,89 @@
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use std::{ffi::OsStr, path::Path, sync::mpsc, time::Duration};
+
+// an editor save is often several events, they are merged over this delay
+const DEBOUNCE: Duration = Duration::from_millis(50);
+
+/// Calls `on_change` with the new contents of `path` each time the file is
+/// saved, until it returns `false`. The parent directory is watched so that
+/// editors replacing the file on save are followed. Watch and read errors are
+/// reported on stderr and the watch goes on.
+pub fn watch_file<F>(path: &Path, mut on_change: F) -> notify::Result<()>
+where
+    F: FnMut(&str) -> bool,
+{
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    watcher.watch(dir, RecursiveMode::NonRecursive)?;
+
+    while let Ok(event) = rx.recv() {
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                eprintln!("{:?}: {}", path, e);
+                continue;
+            }
+        };
+        if !is_save(&event, path.file_name()) {
+            continue;
+        }
+        std::thread::sleep(DEBOUNCE);
+        while rx.try_recv().is_ok() {}
+
+        match std::fs::read_to_string(path) {
+            Ok(src) => {
+                if !on_change(&src) {
+                    break;
+                }
+            }
+            Err(e) => eprintln!("{:?}: {}", path, e),
+        }
+    }
+
+    Ok(())
+}
+
+fn is_save(event: &Event, name: Option<&OsStr>) -> bool {
+    matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_))
+        && event.paths.iter().any(|p| p.file_name() == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::{sync::mpsc, time::Duration};
+
+    #[test]
+    fn test_change_triggers_reload() {
+        let dir = std::env::temp_dir().join(format!("watch_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("scene.ron");
+        std::fs::write(&path, "first").unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let watched = path.clone();
+        std::thread::spawn(move || {
+            watch::watch_file(&watched, |src| {
+                tx.send(src.to_string()).unwrap();
+                false
+            })
+        });
+
+        // save until the watcher is up and reloads the file
+        let mut reloaded = None;
+        for _ in 0..50 {
+            std::fs::write(&path, "second").unwrap();
+            if let Ok(src) = rx.recv_timeout(Duration::from_millis(100)) {
+                reloaded = Some(src);
+                break;
+            }
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(reloaded.as_deref(), Some("second"));
+    }
+}